@@ -35,6 +35,13 @@ impl ArkAddress {
         }
     }
 
+    /// Whether this address is meant for `network`. The encoding only tells mainnet apart from
+    /// the test networks, so a `tark` address is accepted on any of those.
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        let mainnet = self.hrp.as_str() == "ark";
+        mainnet == (network == Network::Bitcoin)
+    }
+
     pub fn encode(&self) -> String {
         let mut bytes = [0u8; 64];
 
@@ -47,6 +54,16 @@ impl ArkAddress {
     pub fn decode(value: &str) -> Result<Self, Error> {
         let (hrp, bytes) = bech32::decode(value).map_err(Error::address_format)?;
 
+        if !matches!(hrp.as_str(), "ark" | "tark") {
+            return Err(Error::address_format(format!("unknown prefix {hrp}")));
+        }
+        if bytes.len() != 64 {
+            return Err(Error::address_format(format!(
+                "expected 64 bytes of data, got {}",
+                bytes.len()
+            )));
+        }
+
         let server = XOnlyPublicKey::from_slice(&bytes[..32]).map_err(Error::address_format)?;
         let vtxo_tap_key =
             XOnlyPublicKey::from_slice(&bytes[32..]).map_err(Error::address_format)?;
@@ -100,4 +117,18 @@ mod tests {
 
         assert_eq!(encoded, address);
     }
+
+    #[test]
+    fn network_of_address() {
+        let address = "tark1x0lm8hhr2wc6n6lyemtyh9rz8rg2ftpkfun46aca56kjg3ws0tsztfpuanaquxc6faedvjk3tax0575y6perapg3e95654pk8r4fjecs5fyd2";
+        let testnet = ArkAddress::decode(address).unwrap();
+
+        assert!(testnet.is_valid_for_network(Network::Regtest));
+        assert!(testnet.is_valid_for_network(Network::Testnet));
+        assert!(!testnet.is_valid_for_network(Network::Bitcoin));
+
+        let mainnet = ArkAddress::new(Network::Bitcoin, testnet.server, testnet.vtxo_tap_key);
+        assert!(mainnet.is_valid_for_network(Network::Bitcoin));
+        assert!(!mainnet.is_valid_for_network(Network::Regtest));
+    }
 }
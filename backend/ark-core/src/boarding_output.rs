@@ -219,6 +219,7 @@ where
                     confirmation_blocktime: Some(confirmation_blocktime),
                     outpoint,
                     amount,
+                    is_confirmed: true,
                     is_spent: false,
                 } => {
                     let now = std::time::UNIX_EPOCH.elapsed().map_err(Error::ad_hoc)?;
@@ -236,12 +237,12 @@ where
                         spendable.push((outpoint, amount, boarding_output.clone()));
                     }
                 }
-                // The boarding output is still pending confirmation.
+                // The boarding output is still pending confirmation, i.e. only in the mempool.
                 ExplorerUtxo {
-                    confirmation_blocktime: None,
                     outpoint,
                     amount,
                     is_spent: false,
+                    ..
                 } => {
                     pending.push((outpoint, amount, boarding_output.clone()));
                 }
@@ -263,3 +264,51 @@ where
         spent,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+    use std::str::FromStr;
+
+    #[test]
+    fn mempool_deposits_are_pending_until_confirmed() {
+        let pk = XOnlyPublicKey::from_str(
+            "18845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166",
+        )
+        .unwrap();
+        let boarding_output = BoardingOutput::new(
+            &Secp256k1::new(),
+            pk,
+            pk,
+            bitcoin::Sequence::from_512_second_intervals(1_024),
+            Network::Regtest,
+        )
+        .unwrap();
+
+        let utxo = |vout, is_confirmed: bool| ExplorerUtxo {
+            outpoint: OutPoint {
+                txid: Txid::all_zeros(),
+                vout,
+            },
+            amount: Amount::from_sat(10_000),
+            confirmation_blocktime: is_confirmed
+                .then(|| std::time::UNIX_EPOCH.elapsed().unwrap().as_secs()),
+            is_confirmed,
+            is_spent: false,
+        };
+
+        let outpoints = list_boarding_outpoints(
+            |_| Ok(vec![utxo(0, false), utxo(1, true)]),
+            &[boarding_output],
+        )
+        .unwrap();
+
+        assert_eq!(outpoints.pending.len(), 1);
+        assert_eq!(outpoints.pending[0].0.vout, 0);
+        assert_eq!(outpoints.spendable.len(), 1);
+        assert_eq!(outpoints.spendable[0].0.vout, 1);
+        assert!(outpoints.expired.is_empty());
+    }
+}
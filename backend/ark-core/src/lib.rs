@@ -39,5 +39,8 @@ pub struct ExplorerUtxo {
     pub outpoint: OutPoint,
     pub amount: Amount,
     pub confirmation_blocktime: Option<u64>,
+    /// Whether the transaction that created the output is in a block, as opposed to only in the
+    /// mempool.
+    pub is_confirmed: bool,
     pub is_spent: bool,
 }
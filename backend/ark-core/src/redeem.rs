@@ -1,5 +1,6 @@
 use crate::tx_weight_estimator;
 use crate::tx_weight_estimator::compute_redeem_tx_fee;
+use crate::tx_weight_estimator::compute_redeem_tx_vsize;
 use crate::vtxo::Vtxo;
 use crate::ArkAddress;
 use crate::Error;
@@ -51,6 +52,58 @@ impl VtxoInput {
             outpoint,
         }
     }
+
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn outpoint(&self) -> OutPoint {
+        self.outpoint
+    }
+}
+
+/// The fee rate used for redeem transactions.
+pub const REDEEM_TX_FEE_RATE: FeeRate = FeeRate::from_sat_per_kwu(253);
+
+/// Estimate the fee of a redeem transaction spending `vtxo_inputs` into `num_outputs` outputs.
+pub fn estimate_redeem_fee(vtxo_inputs: &[VtxoInput], num_outputs: usize) -> Result<Amount, Error> {
+    let vtxos = weight_estimator_inputs(vtxo_inputs);
+
+    compute_redeem_tx_fee(REDEEM_TX_FEE_RATE, vtxos.as_slice(), num_outputs)
+}
+
+/// Estimate the virtual size, in vbytes, of a signed redeem transaction spending `vtxo_inputs`
+/// into `num_outputs` outputs.
+pub fn estimate_redeem_vsize(
+    vtxo_inputs: &[VtxoInput],
+    num_outputs: usize,
+) -> Result<usize, Error> {
+    let vtxos = weight_estimator_inputs(vtxo_inputs);
+
+    compute_redeem_tx_vsize(vtxos.as_slice(), num_outputs)
+}
+
+fn weight_estimator_inputs(vtxo_inputs: &[VtxoInput]) -> Vec<tx_weight_estimator::VtxoInput> {
+    vtxo_inputs
+        .iter()
+        .map(
+            |VtxoInput {
+                 vtxo,
+                 amount,
+                 outpoint,
+             }| {
+                let (script, control_block) = vtxo.forfeit_spend_info();
+
+                tx_weight_estimator::VtxoInput {
+                    outpoint: *outpoint,
+                    amount: *amount,
+                    revealed_script: Some(script),
+                    control_block,
+                    witness_size: Vtxo::FORFEIT_WITNESS_SIZE,
+                }
+            },
+        )
+        .collect()
 }
 
 /// Build a transaction to send VTXOs to another [`ArkAddress`].
@@ -58,6 +111,20 @@ pub fn build_redeem_transaction(
     outputs: &[(&ArkAddress, Amount)],
     change_address: Option<&ArkAddress>,
     vtxo_inputs: &[VtxoInput],
+) -> Result<Psbt, Error> {
+    build_redeem_transaction_with_lock_time(outputs, change_address, vtxo_inputs, LockTime::ZERO)
+}
+
+/// Build a transaction to send VTXOs to another [`ArkAddress`], with the given `nLockTime`.
+///
+/// A non-zero `lock_time` is only enforced if at least one input has a non-final sequence, so
+/// the inputs' sequence numbers are lowered to [`bitcoin::Sequence::ENABLE_LOCKTIME_NO_RBF`] in
+/// that case.
+pub fn build_redeem_transaction_with_lock_time(
+    outputs: &[(&ArkAddress, Amount)],
+    change_address: Option<&ArkAddress>,
+    vtxo_inputs: &[VtxoInput],
+    lock_time: LockTime,
 ) -> Result<Psbt, Error> {
     if vtxo_inputs.is_empty() {
         return Err(Error::transaction(
@@ -82,7 +149,8 @@ pub fn build_redeem_transaction(
         ))
     })?;
 
-    let (change_index, extra_fee) = if change_amount > Amount::ZERO {
+    // Without a change output, whatever the outputs leave over already goes to the fee.
+    let (change_index, leftover) = if change_amount > Amount::ZERO {
         match change_address {
             Some(change_address) => {
                 outputs.push(TxOut {
@@ -98,37 +166,7 @@ pub fn build_redeem_transaction(
         (None, Amount::ZERO)
     };
 
-    let fee = {
-        let vtxos = vtxo_inputs
-            .iter()
-            .map(
-                |VtxoInput {
-                     vtxo,
-                     amount,
-                     outpoint,
-                 }| {
-                    let (script, control_block) = vtxo.forfeit_spend_info();
-
-                    tx_weight_estimator::VtxoInput {
-                        outpoint: *outpoint,
-                        amount: *amount,
-                        revealed_script: Some(script),
-                        control_block,
-                        witness_size: Vtxo::FORFEIT_WITNESS_SIZE,
-                    }
-                },
-            )
-            .collect::<Vec<_>>();
-
-        let computed_fee = compute_redeem_tx_fee(
-            FeeRate::from_sat_per_kwu(253),
-            vtxos.as_slice(),
-            outputs.len(),
-        )
-        .map_err(Error::from)?;
-
-        computed_fee + extra_fee
-    };
+    let fee = estimate_redeem_fee(vtxo_inputs, outputs.len())?;
 
     // Subtract the fee from somewhere.
     //
@@ -142,9 +180,11 @@ pub fn build_redeem_transaction(
                 Error::coin_select("fee ({fee}) greater than change ({change_amount})")
             })?;
         }
-        // If there is no change output, subtract fee evenly from all outputs.
+        // If there is no change output, subtract the part of the fee that the leftover does not
+        // cover evenly from all outputs.
         _ => {
-            let fee_per_output = fee / (outputs.len() as u64);
+            let fee = fee.checked_sub(leftover).unwrap_or(Amount::ZERO);
+            let fee_per_output = Amount::from_sat(fee.to_sat().div_ceil(outputs.len() as u64));
 
             for output in outputs.iter_mut() {
                 output.value = output.value.checked_sub(fee_per_output).ok_or_else(|| {
@@ -157,8 +197,12 @@ pub fn build_redeem_transaction(
         }
     };
 
-    // TODO: Use a different locktime if we have CLTV multisig script.
-    let lock_time = LockTime::ZERO;
+    let sequence = if lock_time == LockTime::ZERO {
+        // TODO: Use a different sequence number if we have a CLTV multisig script.
+        bitcoin::Sequence::MAX
+    } else {
+        bitcoin::Sequence::ENABLE_LOCKTIME_NO_RBF
+    };
 
     let unsigned_tx = Transaction {
         version: transaction::Version::TWO,
@@ -168,8 +212,7 @@ pub fn build_redeem_transaction(
             .map(|VtxoInput { outpoint, .. }| TxIn {
                 previous_output: *outpoint,
                 script_sig: Default::default(),
-                // TODO: Use a different sequence number if we have a CLTV multisig script.
-                sequence: bitcoin::Sequence::MAX,
+                sequence,
                 witness: Default::default(),
             })
             .collect(),
@@ -145,6 +145,17 @@ pub fn compute_redeem_tx_fee(
     vtxos: &[VtxoInput],
     num_outputs: usize,
 ) -> Result<Amount, Error> {
+    let vsize = compute_redeem_tx_vsize(vtxos, num_outputs)?;
+
+    let fee = fee_rate
+        .fee_vb(vsize as u64)
+        .ok_or(Error::ad_hoc("failed calculating fee rate".to_string()))?;
+
+    Ok(fee)
+}
+
+/// Compute the virtual size of a signed redeem transaction, in vbytes.
+pub fn compute_redeem_tx_vsize(vtxos: &[VtxoInput], num_outputs: usize) -> Result<usize, Error> {
     if vtxos.is_empty() {
         return Err(Error::ad_hoc("missing VTXOs".to_string()));
     }
@@ -172,11 +183,5 @@ pub fn compute_redeem_tx_fee(
         redeem_tx_estimator.add_p2tr_output();
     }
 
-    let vsize = redeem_tx_estimator.vsize();
-
-    let fee = fee_rate
-        .fee_vb(vsize as u64)
-        .ok_or(Error::ad_hoc("failed calculating fee rate".to_string()))?;
-
-    Ok(fee)
+    Ok(redeem_tx_estimator.vsize())
 }
@@ -353,28 +353,42 @@ pub fn prepare_vtxo_tree_transactions(
 
         let vtxo_tree = vtxo_trees.get(&round_txid).expect("is there");
 
-        let root = &vtxo_tree.levels[0].nodes[0];
+        let root = vtxo_tree
+            .levels
+            .first()
+            .and_then(|level| level.nodes.first())
+            .ok_or_else(|| Error::ad_hoc(format!("empty VTXO tree in round {round_txid}")))?;
 
         let vtxo_txid = outpoint.txid;
         let leaf_node = vtxo_tree
             .levels
             .last()
-            .expect("at least one")
-            .nodes
-            .iter()
-            .find(|node| node.txid == vtxo_txid)
-            .expect("leaf node");
+            .and_then(|level| level.nodes.iter().find(|node| node.txid == vtxo_txid))
+            .ok_or_else(|| {
+                Error::ad_hoc(format!("VTXO {outpoint} is not a leaf of round {round_txid}"))
+            })?;
 
         // Build the branch from our VTXO to the root of the VTXO tree.
         let mut branch = vec![leaf_node];
         while branch[0].txid != root.txid {
+            if branch.len() > vtxo_tree.levels.len() {
+                return Err(Error::ad_hoc(format!(
+                    "VTXO tree of round {round_txid} has a cycle"
+                )));
+            }
+
+            let parent_txid = branch[0].parent_txid;
             let parent_node = vtxo_tree
                 .levels
                 .iter()
-                .find_map(|level| level.nodes.iter().find(|node| node.txid == branch[0].txid))
-                .expect("parent");
-
-            branch = [vec![parent_node], branch].concat()
+                .find_map(|level| level.nodes.iter().find(|node| node.txid == parent_txid))
+                .ok_or_else(|| {
+                    Error::ad_hoc(format!(
+                        "missing parent {parent_txid} in VTXO tree of round {round_txid}"
+                    ))
+                })?;
+
+            branch.insert(0, parent_node);
         }
 
         let branch = branch
@@ -293,6 +293,7 @@ where
                 // exit path is now _active_, have expired.
                 Some(ExplorerUtxo {
                     confirmation_blocktime: Some(confirmation_blocktime),
+                    is_confirmed: true,
                     ..
                 }) if vtxo.can_be_claimed_unilaterally_by_owner(
                     now,
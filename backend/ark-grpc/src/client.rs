@@ -1,4 +1,3 @@
-use crate::generated;
 use crate::generated::ark::v1::ark_service_client::ArkServiceClient;
 use crate::generated::ark::v1::explorer_service_client::ExplorerServiceClient;
 use crate::generated::ark::v1::input::TaprootTree;
@@ -19,45 +18,65 @@ use crate::generated::ark::v1::SubmitSignedForfeitTxsRequest;
 use crate::generated::ark::v1::SubmitTreeNoncesRequest;
 use crate::generated::ark::v1::SubmitTreeSignaturesRequest;
 use crate::generated::ark::v1::Tapscripts;
+use crate::retry::retry;
+use crate::timeout::timeout;
 use crate::tree;
+use crate::types::decode_psbt_base64;
 use crate::Error;
+use crate::RetryPolicy;
+use crate::Timeouts;
 use ark_core::server::Info;
 use ark_core::server::ListVtxo;
-use ark_core::server::RedeemTransaction;
 use ark_core::server::Round;
-use ark_core::server::RoundFailedEvent;
-use ark_core::server::RoundFinalizationEvent;
-use ark_core::server::RoundFinalizedEvent;
 use ark_core::server::RoundInput;
 use ark_core::server::RoundOutput;
-use ark_core::server::RoundSigningEvent;
-use ark_core::server::RoundSigningNoncesGeneratedEvent;
 use ark_core::server::RoundStreamEvent;
-use ark_core::server::RoundTransaction;
 use ark_core::server::TransactionEvent;
-use ark_core::server::TxTree;
-use ark_core::server::TxTreeLevel;
-use ark_core::server::TxTreeNode;
 use ark_core::server::VtxoOutPoint;
 use ark_core::ArkAddress;
 use async_stream::stream;
 use base64::Engine;
 use bitcoin::hex::DisplayHex;
 use bitcoin::secp256k1::PublicKey;
-use bitcoin::OutPoint;
 use bitcoin::Psbt;
-use bitcoin::Txid;
 use futures::Stream;
 use futures::StreamExt;
 use futures::TryStreamExt;
-use std::collections::HashMap;
-use std::str::FromStr;
 
 #[derive(Debug, Clone)]
 pub struct Client {
     url: String,
     ark_client: Option<ArkServiceClient<tonic::transport::Channel>>,
     explorer_client: Option<ExplorerServiceClient<tonic::transport::Channel>>,
+    retry_policy: RetryPolicy,
+    timeouts: Timeouts,
+    tls: Option<TlsConfig>,
+}
+
+/// How to secure the connection to the Ark server with TLS.
+///
+/// The server certificate is verified against the system's root certificates, plus
+/// `ca_certificate` if given.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM-encoded CA certificate to trust, e.g. for a server with a self-signed certificate.
+    pub ca_certificate: Option<Vec<u8>>,
+    /// Name to verify the server certificate against and to send as SNI, instead of the host
+    /// of the URL.
+    pub domain_name: Option<String>,
+}
+
+impl TlsConfig {
+    fn client_config(&self) -> tonic::transport::ClientTlsConfig {
+        let mut config = tonic::transport::ClientTlsConfig::new().with_native_roots();
+        if let Some(pem) = &self.ca_certificate {
+            config = config.ca_certificate(tonic::transport::Certificate::from_pem(pem));
+        }
+        if let Some(domain_name) = &self.domain_name {
+            config = config.domain_name(domain_name.clone());
+        }
+        config
+    }
 }
 
 impl Client {
@@ -66,16 +85,56 @@ impl Client {
             url,
             ark_client: None,
             explorer_client: None,
+            retry_policy: RetryPolicy::default(),
+            timeouts: Timeouts::default(),
+            tls: None,
         }
     }
 
+    /// Connect over TLS instead of plaintext.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Set how [`Client::connect`] and the read-only requests retry transport-level failures.
+    ///
+    /// The underlying channel re-establishes a broken connection by itself, so retrying a request
+    /// is enough to reconnect. Requests that change server state are never retried.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set how long connecting, each request and each round event may take.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
     pub async fn connect(&mut self) -> Result<(), Error> {
-        let ark_service_client = ArkServiceClient::connect(self.url.clone())
-            .await
-            .map_err(Error::connect)?;
-        let explorer_client = ExplorerServiceClient::connect(self.url.clone())
-            .await
-            .map_err(Error::connect)?;
+        let url = &self.url;
+        let tls = &self.tls;
+        let connect_timeout = self.timeouts.connect;
+        let (ark_service_client, explorer_client) = retry(&self.retry_policy, || async move {
+            let mut endpoint =
+                tonic::transport::Endpoint::from_shared(url.clone()).map_err(Error::connect)?;
+            if let Some(tls) = tls {
+                endpoint = endpoint
+                    .tls_config(tls.client_config())
+                    .map_err(Error::connect)?;
+            }
+
+            let connect = || {
+                timeout(connect_timeout, async {
+                    endpoint.connect().await.map_err(Error::connect)
+                })
+            };
+            let ark_service_client = ArkServiceClient::new(connect().await?);
+            let explorer_client = ExplorerServiceClient::new(connect().await?);
+            Ok((ark_service_client, explorer_client))
+        })
+        .await?;
 
         self.ark_client = Some(ark_service_client);
         self.explorer_client = Some(explorer_client);
@@ -83,12 +142,18 @@ impl Client {
     }
 
     pub async fn get_info(&mut self) -> Result<Info, Error> {
-        let mut client = self.inner_ark_client()?;
-
-        let response = client
-            .get_info(GetInfoRequest {})
-            .await
-            .map_err(Error::request)?;
+        let client = self.inner_ark_client()?;
+
+        let response = retry(&self.retry_policy, || {
+            let mut client = client.clone();
+            timeout(self.timeouts.request, async move {
+                client
+                    .get_info(GetInfoRequest {})
+                    .await
+                    .map_err(Error::request)
+            })
+        })
+        .await?;
 
         response.into_inner().try_into()
     }
@@ -96,12 +161,19 @@ impl Client {
     pub async fn list_vtxos(&self, address: &ArkAddress) -> Result<ListVtxo, Error> {
         let address = address.encode();
 
-        let mut client = self.inner_explorer_client()?;
+        let client = self.inner_explorer_client()?;
 
-        let response = client
-            .list_vtxos(ListVtxosRequest { address })
-            .await
-            .map_err(Error::request)?;
+        let response = retry(&self.retry_policy, || {
+            let mut client = client.clone();
+            let address = address.clone();
+            timeout(self.timeouts.request, async move {
+                client
+                    .list_vtxos(ListVtxosRequest { address })
+                    .await
+                    .map_err(Error::request)
+            })
+        })
+        .await?;
 
         let mut spent = response
             .get_ref()
@@ -173,13 +245,16 @@ impl Client {
             })
             .collect();
 
-        let response = client
-            .register_inputs_for_next_round(RegisterInputsForNextRoundRequest {
-                inputs,
-                notes: Vec::new(),
-            })
-            .await
-            .map_err(Error::request)?;
+        let response = timeout(self.timeouts.request, async {
+            client
+                .register_inputs_for_next_round(RegisterInputsForNextRoundRequest {
+                    inputs,
+                    notes: Vec::new(),
+                })
+                .await
+                .map_err(Error::request)
+        })
+        .await?;
         let request_id = response.into_inner().request_id;
 
         Ok(request_id)
@@ -204,17 +279,20 @@ impl Client {
 
         let cosigners_public_keys = cosigner_pks.iter().map(|pk| pk.to_string()).collect();
 
-        client
-            .register_outputs_for_next_round(RegisterOutputsForNextRoundRequest {
-                request_id,
-                outputs,
-                musig2: Some(Musig2 {
-                    cosigners_public_keys,
-                    signing_all,
-                }),
-            })
-            .await
-            .map_err(Error::request)?;
+        timeout(self.timeouts.request, async {
+            client
+                .register_outputs_for_next_round(RegisterOutputsForNextRoundRequest {
+                    request_id,
+                    outputs,
+                    musig2: Some(Musig2 {
+                        cosigners_public_keys,
+                        signing_all,
+                    }),
+                })
+                .await
+                .map_err(Error::request)
+        })
+        .await?;
 
         Ok(())
     }
@@ -222,33 +300,29 @@ impl Client {
     pub async fn submit_redeem_transaction(&self, redeem_psbt: Psbt) -> Result<Psbt, Error> {
         let mut client = self.inner_ark_client()?;
 
-        let base64 = base64::engine::GeneralPurpose::new(
-            &base64::alphabet::STANDARD,
-            base64::engine::GeneralPurposeConfig::new(),
-        );
-
-        let redeem_tx = base64.encode(redeem_psbt.serialize());
-
-        let res = client
-            .submit_redeem_tx(SubmitRedeemTxRequest { redeem_tx })
-            .await
-            .map_err(Error::request)?;
+        let redeem_tx = base64::engine::general_purpose::STANDARD.encode(redeem_psbt.serialize());
 
-        let psbt = base64
-            .decode(res.into_inner().signed_redeem_tx)
-            .map_err(Error::conversion)?;
-        let psbt = Psbt::deserialize(&psbt).map_err(Error::conversion)?;
+        let res = timeout(self.timeouts.request, async {
+            client
+                .submit_redeem_tx(SubmitRedeemTxRequest { redeem_tx })
+                .await
+                .map_err(Error::request)
+        })
+        .await?;
 
-        Ok(psbt)
+        decode_psbt_base64(&res.into_inner().signed_redeem_tx)
     }
 
     pub async fn ping(&self, request_id: String) -> Result<(), Error> {
         let mut client = self.inner_ark_client()?;
 
-        client
-            .ping(PingRequest { request_id })
-            .await
-            .map_err(|e| Error::ping(e.message().to_string()))?;
+        timeout(self.timeouts.request, async {
+            client
+                .ping(PingRequest { request_id })
+                .await
+                .map_err(|e| Error::ping(e.message().to_string()))
+        })
+        .await?;
 
         Ok(())
     }
@@ -263,14 +337,17 @@ impl Client {
 
         let pub_nonce_tree = tree::encode_tree(pub_nonce_tree).map_err(Error::conversion)?;
 
-        client
-            .submit_tree_nonces(SubmitTreeNoncesRequest {
-                round_id: round_id.to_string(),
-                pubkey: cosigner_pubkey.to_string(),
-                tree_nonces: pub_nonce_tree.to_lower_hex_string(),
-            })
-            .await
-            .map_err(Error::request)?;
+        timeout(self.timeouts.request, async {
+            client
+                .submit_tree_nonces(SubmitTreeNoncesRequest {
+                    round_id: round_id.to_string(),
+                    pubkey: cosigner_pubkey.to_string(),
+                    tree_nonces: pub_nonce_tree.to_lower_hex_string(),
+                })
+                .await
+                .map_err(Error::request)
+        })
+        .await?;
 
         Ok(())
     }
@@ -285,14 +362,17 @@ impl Client {
 
         let tree_signatures = tree::encode_tree(partial_sig_tree).map_err(Error::conversion)?;
 
-        client
-            .submit_tree_signatures(SubmitTreeSignaturesRequest {
-                round_id: round_id.to_string(),
-                pubkey: cosigner_pk.to_string(),
-                tree_signatures: tree_signatures.to_lower_hex_string(),
-            })
-            .await
-            .map_err(Error::request)?;
+        timeout(self.timeouts.request, async {
+            client
+                .submit_tree_signatures(SubmitTreeSignaturesRequest {
+                    round_id: round_id.to_string(),
+                    pubkey: cosigner_pk.to_string(),
+                    tree_signatures: tree_signatures.to_lower_hex_string(),
+                })
+                .await
+                .map_err(Error::request)
+        })
+        .await?;
 
         Ok(())
     }
@@ -304,21 +384,21 @@ impl Client {
     ) -> Result<(), Error> {
         let mut client = self.inner_ark_client()?;
 
-        let base64 = base64::engine::GeneralPurpose::new(
-            &base64::alphabet::STANDARD,
-            base64::engine::GeneralPurposeConfig::new(),
-        );
-
-        client
-            .submit_signed_forfeit_txs(SubmitSignedForfeitTxsRequest {
-                signed_forfeit_txs: signed_forfeit_txs
-                    .iter()
-                    .map(|psbt| base64.encode(psbt.serialize()))
-                    .collect(),
-                signed_round_tx: signed_round_psbt.map(|p| base64.encode(p.serialize())),
-            })
-            .await
-            .map_err(Error::request)?;
+        let base64 = &base64::engine::general_purpose::STANDARD;
+
+        timeout(self.timeouts.request, async {
+            client
+                .submit_signed_forfeit_txs(SubmitSignedForfeitTxsRequest {
+                    signed_forfeit_txs: signed_forfeit_txs
+                        .iter()
+                        .map(|psbt| base64.encode(psbt.serialize()))
+                        .collect(),
+                    signed_round_tx: signed_round_psbt.map(|p| base64.encode(p.serialize())),
+                })
+                .await
+                .map_err(Error::request)
+        })
+        .await?;
 
         Ok(())
     }
@@ -328,15 +408,22 @@ impl Client {
     ) -> Result<impl Stream<Item = Result<RoundStreamEvent, Error>> + Unpin, Error> {
         let mut client = self.inner_ark_client()?;
 
-        let response = client
-            .get_event_stream(GetEventStreamRequest {})
-            .await
-            .map_err(Error::request)?;
+        let response = timeout(self.timeouts.request, async {
+            client
+                .get_event_stream(GetEventStreamRequest {})
+                .await
+                .map_err(Error::request)
+        })
+        .await?;
         let mut stream = response.into_inner();
+        let event_timeout = self.timeouts.event_stream;
 
         let stream = stream! {
             loop {
-                match stream.try_next().await {
+                let next = timeout(event_timeout, async {
+                    stream.try_next().await.map_err(Error::event_stream)
+                });
+                match next.await {
                     Ok(Some(event)) => match event.event {
                         None => {
                             log::debug!("Got empty message");
@@ -349,7 +436,7 @@ impl Client {
                         yield Err(Error::event_stream_disconnect());
                     }
                     Err(e) => {
-                        yield Err(Error::event_stream(e));
+                        yield Err(e);
                     }
                 }
             }
@@ -363,10 +450,13 @@ impl Client {
     ) -> Result<impl Stream<Item = Result<TransactionEvent, Error>> + Unpin, Error> {
         let mut client = self.inner_ark_client()?;
 
-        let response = client
-            .get_transactions_stream(GetTransactionsStreamRequest {})
-            .await
-            .map_err(Error::request)?;
+        let response = timeout(self.timeouts.request, async {
+            client
+                .get_transactions_stream(GetTransactionsStreamRequest {})
+                .await
+                .map_err(Error::request)
+        })
+        .await?;
 
         let mut stream = response.into_inner();
 
@@ -395,12 +485,19 @@ impl Client {
     }
 
     pub async fn get_round(&self, round_txid: String) -> Result<Option<Round>, Error> {
-        let mut client = self.inner_explorer_client()?;
-
-        let response = client
-            .get_round(GetRoundRequest { txid: round_txid })
-            .await
-            .map_err(Error::request)?;
+        let client = self.inner_explorer_client()?;
+
+        let response = retry(&self.retry_policy, || {
+            let mut client = client.clone();
+            let txid = round_txid.clone();
+            timeout(self.timeouts.request, async move {
+                client
+                    .get_round(GetRoundRequest { txid })
+                    .await
+                    .map_err(Error::request)
+            })
+        })
+        .await?;
 
         let response = response.into_inner();
         let round = response.round.map(Round::try_from).transpose()?;
@@ -418,324 +515,3 @@ impl Client {
         self.explorer_client.clone().ok_or(Error::not_connected())
     }
 }
-
-impl TryFrom<generated::ark::v1::Tree> for TxTree {
-    type Error = Error;
-
-    fn try_from(value: generated::ark::v1::Tree) -> Result<Self, Self::Error> {
-        let levels = value
-            .levels
-            .into_iter()
-            .map(|level| level.try_into())
-            .collect::<Result<Vec<_>, Error>>()?;
-
-        Ok(TxTree { levels })
-    }
-}
-
-impl TryFrom<generated::ark::v1::TreeLevel> for TxTreeLevel {
-    type Error = Error;
-
-    fn try_from(value: generated::ark::v1::TreeLevel) -> Result<Self, Self::Error> {
-        let nodes = value
-            .nodes
-            .into_iter()
-            .map(|node| node.try_into())
-            .collect::<Result<Vec<_>, Error>>()?;
-
-        Ok(TxTreeLevel { nodes })
-    }
-}
-
-impl TryFrom<generated::ark::v1::Node> for TxTreeNode {
-    type Error = Error;
-
-    fn try_from(value: generated::ark::v1::Node) -> Result<Self, Self::Error> {
-        let txid: Txid = value.txid.parse().map_err(Error::conversion)?;
-
-        let tx = base64::engine::GeneralPurpose::new(
-            &base64::alphabet::STANDARD,
-            base64::engine::GeneralPurposeConfig::new(),
-        )
-        .decode(&value.tx)
-        .map_err(Error::conversion)?;
-
-        let tx = Psbt::deserialize(&tx).map_err(Error::conversion)?;
-
-        let parent_txid: Txid = value.parent_txid.parse().map_err(Error::conversion)?;
-
-        Ok(TxTreeNode {
-            txid,
-            tx,
-            parent_txid,
-        })
-    }
-}
-
-impl TryFrom<generated::ark::v1::RoundFinalizationEvent> for RoundFinalizationEvent {
-    type Error = Error;
-
-    fn try_from(value: generated::ark::v1::RoundFinalizationEvent) -> Result<Self, Self::Error> {
-        let base64 = &base64::engine::GeneralPurpose::new(
-            &base64::alphabet::STANDARD,
-            base64::engine::GeneralPurposeConfig::new(),
-        );
-
-        let vtxo_tree = value.vtxo_tree.unwrap_or_default().try_into()?;
-
-        let round_tx = base64.decode(&value.round_tx).map_err(Error::conversion)?;
-
-        let round_tx = Psbt::deserialize(&round_tx).map_err(Error::conversion)?;
-
-        let connector_tree = TxTree::try_from(value.connectors.unwrap_or_default())?;
-
-        let connectors_index = value
-            .connectors_index
-            .iter()
-            .map(|(key, value)| {
-                let key = {
-                    let parts = key.split(':').collect::<Vec<_>>();
-
-                    let txid = parts[0].parse().map_err(Error::conversion)?;
-                    let vout = parts[1].parse().map_err(Error::conversion)?;
-
-                    OutPoint { txid, vout }
-                };
-
-                let value = value.clone().try_into()?;
-
-                Ok((key, value))
-            })
-            .collect::<Result<HashMap<OutPoint, OutPoint>, Error>>()?;
-
-        Ok(RoundFinalizationEvent {
-            id: value.id,
-            round_tx,
-            vtxo_tree,
-            connector_tree,
-            min_relay_fee_rate: value.min_relay_fee_rate,
-            connectors_index,
-        })
-    }
-}
-
-impl TryFrom<generated::ark::v1::RoundFinalizedEvent> for RoundFinalizedEvent {
-    type Error = Error;
-
-    fn try_from(value: generated::ark::v1::RoundFinalizedEvent) -> Result<Self, Self::Error> {
-        let round_txid = value.round_txid.parse().map_err(Error::conversion)?;
-
-        Ok(RoundFinalizedEvent {
-            id: value.id,
-            round_txid,
-        })
-    }
-}
-
-impl From<generated::ark::v1::RoundFailed> for RoundFailedEvent {
-    fn from(value: generated::ark::v1::RoundFailed) -> Self {
-        RoundFailedEvent {
-            id: value.id,
-            reason: value.reason,
-        }
-    }
-}
-
-impl TryFrom<generated::ark::v1::RoundSigningEvent> for RoundSigningEvent {
-    type Error = Error;
-
-    fn try_from(value: generated::ark::v1::RoundSigningEvent) -> Result<Self, Self::Error> {
-        let unsigned_round_tx = base64::engine::GeneralPurpose::new(
-            &base64::alphabet::STANDARD,
-            base64::engine::GeneralPurposeConfig::new(),
-        )
-        .decode(&value.unsigned_round_tx)
-        .map_err(Error::conversion)?;
-
-        let unsigned_vtxo_tree = value
-            .unsigned_vtxo_tree
-            .map(|tree| tree.try_into())
-            .transpose()?;
-
-        let unsigned_round_tx = Psbt::deserialize(&unsigned_round_tx).map_err(Error::conversion)?;
-
-        Ok(RoundSigningEvent {
-            id: value.id,
-            cosigners_pubkeys: value
-                .cosigners_pubkeys
-                .into_iter()
-                .map(|pk| pk.parse().map_err(Error::conversion))
-                .collect::<Result<Vec<_>, Error>>()?,
-            unsigned_vtxo_tree,
-            unsigned_round_tx,
-        })
-    }
-}
-
-impl TryFrom<generated::ark::v1::RoundSigningNoncesGeneratedEvent>
-    for RoundSigningNoncesGeneratedEvent
-{
-    type Error = Error;
-
-    fn try_from(
-        value: generated::ark::v1::RoundSigningNoncesGeneratedEvent,
-    ) -> Result<Self, Self::Error> {
-        let tree_nonces = crate::decode_tree(value.tree_nonces)?;
-
-        Ok(RoundSigningNoncesGeneratedEvent {
-            id: value.id,
-            tree_nonces,
-        })
-    }
-}
-
-impl TryFrom<generated::ark::v1::get_event_stream_response::Event> for RoundStreamEvent {
-    type Error = Error;
-
-    fn try_from(
-        value: generated::ark::v1::get_event_stream_response::Event,
-    ) -> Result<Self, Self::Error> {
-        Ok(match value {
-            generated::ark::v1::get_event_stream_response::Event::RoundFinalization(e) => {
-                RoundStreamEvent::RoundFinalization(e.try_into()?)
-            }
-            generated::ark::v1::get_event_stream_response::Event::RoundFinalized(e) => {
-                RoundStreamEvent::RoundFinalized(e.try_into()?)
-            }
-            generated::ark::v1::get_event_stream_response::Event::RoundFailed(e) => {
-                RoundStreamEvent::RoundFailed(e.into())
-            }
-            generated::ark::v1::get_event_stream_response::Event::RoundSigning(e) => {
-                RoundStreamEvent::RoundSigning(e.try_into()?)
-            }
-            generated::ark::v1::get_event_stream_response::Event::RoundSigningNoncesGenerated(
-                e,
-            ) => RoundStreamEvent::RoundSigningNoncesGenerated(e.try_into()?),
-        })
-    }
-}
-
-impl TryFrom<generated::ark::v1::Round> for Round {
-    type Error = Error;
-
-    fn try_from(value: generated::ark::v1::Round) -> Result<Self, Self::Error> {
-        let base64 = base64::engine::GeneralPurpose::new(
-            &base64::alphabet::STANDARD,
-            base64::engine::GeneralPurposeConfig::new(),
-        );
-
-        let round_tx = {
-            let psbt = base64.decode(&value.round_tx).map_err(Error::conversion)?;
-            Psbt::deserialize(&psbt).map_err(Error::conversion)?
-        };
-
-        let vtxo_tree = value.vtxo_tree.unwrap_or_default().try_into()?;
-
-        let forfeit_txs = value
-            .forfeit_txs
-            .into_iter()
-            .map(|t| {
-                let psbt = base64.decode(&t).map_err(Error::conversion)?;
-                let psbt = Psbt::deserialize(&psbt).map_err(Error::conversion)?;
-                Ok(psbt)
-            })
-            .collect::<Result<Vec<_>, Error>>()?;
-
-        let connector_tree = TxTree::try_from(value.connectors.unwrap_or_default())?;
-
-        Ok(Round {
-            id: value.id,
-            start: value.start,
-            end: value.end,
-            round_tx,
-            vtxo_tree,
-            forfeit_txs,
-            connector_tree,
-            stage: value.stage,
-        })
-    }
-}
-
-impl TryFrom<generated::ark::v1::get_transactions_stream_response::Tx> for TransactionEvent {
-    type Error = Error;
-
-    fn try_from(
-        value: generated::ark::v1::get_transactions_stream_response::Tx,
-    ) -> Result<Self, Self::Error> {
-        match value {
-            generated::ark::v1::get_transactions_stream_response::Tx::Round(round) => {
-                Ok(TransactionEvent::Round(RoundTransaction::try_from(round)?))
-            }
-            generated::ark::v1::get_transactions_stream_response::Tx::Redeem(redeem) => Ok(
-                TransactionEvent::Redeem(RedeemTransaction::try_from(redeem)?),
-            ),
-        }
-    }
-}
-
-impl TryFrom<generated::ark::v1::RoundTransaction> for RoundTransaction {
-    type Error = Error;
-
-    fn try_from(value: generated::ark::v1::RoundTransaction) -> Result<Self, Self::Error> {
-        let spent_vtxos = value
-            .spent_vtxos
-            .iter()
-            .map(VtxoOutPoint::try_from)
-            .collect::<Result<Vec<_>, _>>()?;
-
-        let claimed_boarding_utxos = value
-            .claimed_boarding_utxos
-            .into_iter()
-            .map(OutPoint::try_from)
-            .collect::<Result<Vec<_>, _>>()?;
-
-        let spendable_vtxos = value
-            .spendable_vtxos
-            .iter()
-            .map(VtxoOutPoint::try_from)
-            .collect::<Result<Vec<_>, _>>()?;
-
-        Ok(RoundTransaction {
-            txid: Txid::from_str(value.txid.as_str()).map_err(Error::conversion)?,
-            spent_vtxos,
-            spendable_vtxos,
-            claimed_boarding_utxos,
-        })
-    }
-}
-
-impl TryFrom<generated::ark::v1::RedeemTransaction> for RedeemTransaction {
-    type Error = Error;
-
-    fn try_from(value: generated::ark::v1::RedeemTransaction) -> Result<Self, Self::Error> {
-        let spent_vtxos = value
-            .spent_vtxos
-            .iter()
-            .map(VtxoOutPoint::try_from)
-            .collect::<Result<Vec<_>, _>>()?;
-
-        let spendable_vtxos = value
-            .spendable_vtxos
-            .iter()
-            .map(VtxoOutPoint::try_from)
-            .collect::<Result<Vec<_>, _>>()?;
-
-        Ok(RedeemTransaction {
-            txid: Txid::from_str(value.txid.as_str()).map_err(Error::conversion)?,
-            spent_vtxos,
-            spendable_vtxos,
-        })
-    }
-}
-
-impl TryFrom<Outpoint> for OutPoint {
-    type Error = Error;
-
-    fn try_from(value: Outpoint) -> Result<Self, Self::Error> {
-        let point = OutPoint {
-            txid: Txid::from_str(value.txid.as_str()).map_err(Error::conversion)?,
-            vout: value.vout,
-        };
-        Ok(point)
-    }
-}
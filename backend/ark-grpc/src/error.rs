@@ -21,6 +21,7 @@ enum Kind {
     Ping,
     EventStreamDisconnect,
     EventStream,
+    Timeout,
 }
 
 impl Error {
@@ -63,6 +64,29 @@ impl Error {
         Error::new(Kind::EventStream).with(source)
     }
 
+    pub(crate) fn timeout(after: std::time::Duration) -> Self {
+        Error::new(Kind::Timeout).with(format!("no answer after {after:?}"))
+    }
+
+    /// Whether the Ark server did not answer in time.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self.inner.kind, Kind::Timeout)
+    }
+
+    /// Whether the connection to the Ark server is unusable, so that reconnecting may help.
+    pub fn is_transport(&self) -> bool {
+        match self.inner.kind {
+            Kind::Connect | Kind::NotConnected => true,
+            Kind::Request => self
+                .inner
+                .source
+                .as_ref()
+                .and_then(|source| source.downcast_ref::<tonic::Status>())
+                .is_some_and(|status| status.code() == tonic::Code::Unavailable),
+            _ => false,
+        }
+    }
+
     fn description(&self) -> &str {
         match &self.inner.kind {
             Kind::Connect => "failed to connect to Ark server",
@@ -72,6 +96,7 @@ impl Error {
             Kind::Ping => "error via ping",
             Kind::EventStreamDisconnect => "got disconnected from event stream",
             Kind::EventStream => "error via event stream",
+            Kind::Timeout => "Ark server timed out",
         }
     }
 }
@@ -104,3 +129,17 @@ impl StdError for Error {
             .map(|source| &**source as &(dyn StdError + 'static))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_connection_failures_are_transport_errors() {
+        assert!(Error::not_connected().is_transport());
+        assert!(Error::request(tonic::Status::unavailable("connection refused")).is_transport());
+
+        assert!(!Error::request(tonic::Status::invalid_argument("bad address")).is_transport());
+        assert!(!Error::conversion("bad PSBT").is_transport());
+    }
+}
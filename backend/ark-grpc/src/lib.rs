@@ -11,9 +11,13 @@ mod generated {
 pub mod client;
 
 mod error;
+mod retry;
+mod timeout;
 mod tree;
 mod types;
 
 pub use client::*;
 pub use error::Error;
+pub use retry::RetryPolicy;
+pub use timeout::Timeouts;
 pub use tree::*;
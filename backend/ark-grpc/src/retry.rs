@@ -0,0 +1,141 @@
+use crate::Error;
+use std::future::Future;
+use std::time::Duration;
+
+/// How often, and how patiently, a request is retried after a transport-level failure.
+///
+/// The delay before each retry doubles, starting at `initial_delay` and never exceeding
+/// `max_delay`. Errors the server returned on purpose are never retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one. `1` disables retrying.
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    fn delay(&self, retry: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Run `op` until it succeeds, fails with a non-transport error, or runs out of attempts.
+pub(crate) async fn retry<T, F, Fut>(policy: &RetryPolicy, mut op: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(e) if e.is_transport() && attempt < policy.max_attempts => {
+                let delay = policy.delay(attempt - 1);
+                log::warn!("Ark server unreachable ({e}), retrying in {delay:?}");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(4),
+        }
+    }
+
+    /// A stand-in for the transport that is unreachable for the first `failures` calls.
+    struct FlakyTransport {
+        failures: u32,
+        calls: Cell<u32>,
+    }
+
+    impl FlakyTransport {
+        async fn call(&self) -> Result<&'static str, Error> {
+            let calls = self.calls.get() + 1;
+            self.calls.set(calls);
+
+            if calls <= self.failures {
+                Err(Error::request(tonic::Status::unavailable("connection refused")))
+            } else {
+                Ok("pong")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transport_errors_until_success() {
+        let transport = FlakyTransport {
+            failures: 2,
+            calls: Cell::new(0),
+        };
+
+        let result = retry(&fast_policy(3), || transport.call()).await;
+
+        assert_eq!(result.unwrap(), "pong");
+        assert_eq!(transport.calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let transport = FlakyTransport {
+            failures: 2,
+            calls: Cell::new(0),
+        };
+
+        let result = retry(&fast_policy(2), || transport.call()).await;
+
+        assert!(result.unwrap_err().is_transport());
+        assert_eq!(transport.calls.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_errors() {
+        let calls = Cell::new(0);
+
+        let result: Result<(), _> = retry(&fast_policy(3), || async {
+            calls.set(calls.get() + 1);
+            Err(Error::conversion("bad PSBT"))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+        };
+
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(1), Duration::from_millis(200));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+        assert_eq!(policy.delay(3), Duration::from_millis(500));
+        assert_eq!(policy.delay(40), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::Error;
+use std::future::Future;
+use std::time::Duration;
+
+/// How long calls to the Ark server may take before they fail with a timeout error.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// For establishing the connection.
+    pub connect: Duration,
+    /// For each request, and each attempt of a retried request.
+    pub request: Duration,
+    /// For each event of the round event stream. Rounds legitimately take a while, and the next
+    /// one may only start after the server's round interval.
+    pub event_stream: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(10),
+            request: Duration::from_secs(30),
+            event_stream: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// Run `fut`, failing with a timeout error if it does not finish within `duration`.
+pub(crate) async fn timeout<T, Fut>(duration: Duration, fut: Fut) -> Result<T, Error>
+where
+    Fut: Future<Output = Result<T, Error>>,
+{
+    match tokio::time::timeout(duration, fut).await {
+        Ok(result) => result,
+        Err(_) => Err(Error::timeout(duration)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Client;
+    use crate::RetryPolicy;
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn stalled_future_times_out() {
+        let result: Result<(), _> =
+            timeout(Duration::from_millis(10), std::future::pending()).await;
+
+        let error = result.unwrap_err();
+        assert!(error.is_timeout());
+        assert!(!error.is_transport());
+    }
+
+    #[tokio::test]
+    async fn stalled_server_times_out_instead_of_hanging() {
+        // The kernel accepts connections to this listener, but nothing ever answers them.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let mut client = Client::new(url)
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 1,
+                ..Default::default()
+            })
+            .with_timeouts(Timeouts {
+                connect: Duration::from_millis(200),
+                request: Duration::from_millis(200),
+                event_stream: Duration::from_millis(200),
+            });
+
+        let call = async {
+            client.connect().await?;
+            client.get_info().await
+        };
+        let result = tokio::time::timeout(Duration::from_secs(5), call)
+            .await
+            .expect("the call to time out by itself");
+
+        assert!(result.unwrap_err().is_timeout());
+    }
+}
@@ -79,7 +79,9 @@ where
 
     let n_rows = u32::from_le_bytes(n_rows);
 
-    let mut matrix = Vec::with_capacity(n_rows as usize);
+    // The counts come from the server; every entry takes at least a byte, so a count larger
+    // than the input cannot be right and must not decide how much is allocated.
+    let mut matrix = Vec::with_capacity((n_rows as usize).min(bytes.len()));
 
     for _ in 0..n_rows {
         let mut n_columns = [0u8; 4];
@@ -89,7 +91,7 @@ where
 
         let n_columns = u32::from_le_bytes(n_columns);
 
-        let mut row = Vec::with_capacity(n_columns as usize);
+        let mut row = Vec::with_capacity((n_columns as usize).min(bytes.len()));
 
         for _ in 0..n_columns {
             let mut is_none = [0u8; 1];
@@ -1,12 +1,39 @@
 use crate::generated;
+use crate::generated::ark::v1::Outpoint;
 use crate::Error;
 use ark_core::server;
+use ark_core::server::RedeemTransaction;
+use ark_core::server::Round;
+use ark_core::server::RoundFailedEvent;
+use ark_core::server::RoundFinalizationEvent;
+use ark_core::server::RoundFinalizedEvent;
+use ark_core::server::RoundSigningEvent;
+use ark_core::server::RoundSigningNoncesGeneratedEvent;
+use ark_core::server::RoundStreamEvent;
+use ark_core::server::RoundTransaction;
+use ark_core::server::TransactionEvent;
+use ark_core::server::TxTree;
+use ark_core::server::TxTreeLevel;
+use ark_core::server::TxTreeNode;
+use ark_core::server::VtxoOutPoint;
 use base64::Engine;
 use bitcoin::address::NetworkUnchecked;
 use bitcoin::Address;
 use bitcoin::Amount;
 use bitcoin::OutPoint;
 use bitcoin::Psbt;
+use bitcoin::Txid;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Decode a PSBT sent by the Ark server as standard, padded base64.
+pub(crate) fn decode_psbt_base64(s: &str) -> Result<Psbt, Error> {
+    let psbt = base64::engine::general_purpose::STANDARD
+        .decode(s)
+        .map_err(Error::conversion)?;
+
+    Psbt::deserialize(&psbt).map_err(Error::conversion)
+}
 
 impl TryFrom<generated::ark::v1::GetInfoResponse> for server::Info {
     type Error = Error;
@@ -47,11 +74,11 @@ impl TryFrom<&generated::ark::v1::Vtxo> for server::VtxoOutPoint {
     type Error = Error;
 
     fn try_from(value: &generated::ark::v1::Vtxo) -> Result<Self, Self::Error> {
-        let outpoint = value.outpoint.as_ref().expect("outpoint");
-        let outpoint = OutPoint {
-            txid: outpoint.txid.parse().map_err(Error::conversion)?,
-            vout: outpoint.vout,
-        };
+        let outpoint = value
+            .outpoint
+            .clone()
+            .ok_or_else(|| Error::conversion("VTXO without an outpoint"))?
+            .try_into()?;
 
         let spent_by = match value.spent_by.is_empty() {
             true => None,
@@ -60,18 +87,7 @@ impl TryFrom<&generated::ark::v1::Vtxo> for server::VtxoOutPoint {
 
         let redeem_tx = match value.redeem_tx.is_empty() {
             true => None,
-            false => {
-                let base64 = base64::engine::GeneralPurpose::new(
-                    &base64::alphabet::STANDARD,
-                    base64::engine::GeneralPurposeConfig::new(),
-                );
-
-                let psbt = base64
-                    .decode(value.redeem_tx.clone())
-                    .map_err(Error::conversion)?;
-                let psbt = Psbt::deserialize(&psbt).map_err(Error::conversion)?;
-                Some(psbt)
-            }
+            false => Some(decode_psbt_base64(&value.redeem_tx)?),
         };
 
         Ok(Self {
@@ -89,3 +105,308 @@ impl TryFrom<&generated::ark::v1::Vtxo> for server::VtxoOutPoint {
         })
     }
 }
+
+impl TryFrom<generated::ark::v1::Tree> for TxTree {
+    type Error = Error;
+
+    fn try_from(value: generated::ark::v1::Tree) -> Result<Self, Self::Error> {
+        let levels = value
+            .levels
+            .into_iter()
+            .map(|level| level.try_into())
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(TxTree { levels })
+    }
+}
+
+impl TryFrom<generated::ark::v1::TreeLevel> for TxTreeLevel {
+    type Error = Error;
+
+    fn try_from(value: generated::ark::v1::TreeLevel) -> Result<Self, Self::Error> {
+        let nodes = value
+            .nodes
+            .into_iter()
+            .map(|node| node.try_into())
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(TxTreeLevel { nodes })
+    }
+}
+
+impl TryFrom<generated::ark::v1::Node> for TxTreeNode {
+    type Error = Error;
+
+    fn try_from(value: generated::ark::v1::Node) -> Result<Self, Self::Error> {
+        let txid: Txid = value.txid.parse().map_err(Error::conversion)?;
+
+        let tx = decode_psbt_base64(&value.tx)?;
+
+        let parent_txid: Txid = value.parent_txid.parse().map_err(Error::conversion)?;
+
+        Ok(TxTreeNode {
+            txid,
+            tx,
+            parent_txid,
+        })
+    }
+}
+
+impl TryFrom<generated::ark::v1::RoundFinalizationEvent> for RoundFinalizationEvent {
+    type Error = Error;
+
+    fn try_from(value: generated::ark::v1::RoundFinalizationEvent) -> Result<Self, Self::Error> {
+        let vtxo_tree = value.vtxo_tree.unwrap_or_default().try_into()?;
+
+        let round_tx = decode_psbt_base64(&value.round_tx)?;
+
+        let connector_tree = TxTree::try_from(value.connectors.unwrap_or_default())?;
+
+        let connectors_index = value
+            .connectors_index
+            .iter()
+            .map(|(key, value)| {
+                let key = {
+                    let (txid, vout) = key.split_once(':').ok_or_else(|| {
+                        Error::conversion(format!("connector index key {key:?} is not an outpoint"))
+                    })?;
+
+                    let txid = txid.parse().map_err(Error::conversion)?;
+                    let vout = vout.parse().map_err(Error::conversion)?;
+
+                    OutPoint { txid, vout }
+                };
+
+                let value = value.clone().try_into()?;
+
+                Ok((key, value))
+            })
+            .collect::<Result<HashMap<OutPoint, OutPoint>, Error>>()?;
+
+        Ok(RoundFinalizationEvent {
+            id: value.id,
+            round_tx,
+            vtxo_tree,
+            connector_tree,
+            min_relay_fee_rate: value.min_relay_fee_rate,
+            connectors_index,
+        })
+    }
+}
+
+impl TryFrom<generated::ark::v1::RoundFinalizedEvent> for RoundFinalizedEvent {
+    type Error = Error;
+
+    fn try_from(value: generated::ark::v1::RoundFinalizedEvent) -> Result<Self, Self::Error> {
+        let round_txid = value.round_txid.parse().map_err(Error::conversion)?;
+
+        Ok(RoundFinalizedEvent {
+            id: value.id,
+            round_txid,
+        })
+    }
+}
+
+impl From<generated::ark::v1::RoundFailed> for RoundFailedEvent {
+    fn from(value: generated::ark::v1::RoundFailed) -> Self {
+        RoundFailedEvent {
+            id: value.id,
+            reason: value.reason,
+        }
+    }
+}
+
+impl TryFrom<generated::ark::v1::RoundSigningEvent> for RoundSigningEvent {
+    type Error = Error;
+
+    fn try_from(value: generated::ark::v1::RoundSigningEvent) -> Result<Self, Self::Error> {
+        let unsigned_round_tx = decode_psbt_base64(&value.unsigned_round_tx)?;
+
+        let unsigned_vtxo_tree = value
+            .unsigned_vtxo_tree
+            .map(|tree| tree.try_into())
+            .transpose()?;
+
+        Ok(RoundSigningEvent {
+            id: value.id,
+            cosigners_pubkeys: value
+                .cosigners_pubkeys
+                .into_iter()
+                .map(|pk| pk.parse().map_err(Error::conversion))
+                .collect::<Result<Vec<_>, Error>>()?,
+            unsigned_vtxo_tree,
+            unsigned_round_tx,
+        })
+    }
+}
+
+impl TryFrom<generated::ark::v1::RoundSigningNoncesGeneratedEvent>
+    for RoundSigningNoncesGeneratedEvent
+{
+    type Error = Error;
+
+    fn try_from(
+        value: generated::ark::v1::RoundSigningNoncesGeneratedEvent,
+    ) -> Result<Self, Self::Error> {
+        let tree_nonces = crate::decode_tree(value.tree_nonces)?;
+
+        Ok(RoundSigningNoncesGeneratedEvent {
+            id: value.id,
+            tree_nonces,
+        })
+    }
+}
+
+impl TryFrom<generated::ark::v1::get_event_stream_response::Event> for RoundStreamEvent {
+    type Error = Error;
+
+    fn try_from(
+        value: generated::ark::v1::get_event_stream_response::Event,
+    ) -> Result<Self, Self::Error> {
+        Ok(match value {
+            generated::ark::v1::get_event_stream_response::Event::RoundFinalization(e) => {
+                RoundStreamEvent::RoundFinalization(e.try_into()?)
+            }
+            generated::ark::v1::get_event_stream_response::Event::RoundFinalized(e) => {
+                RoundStreamEvent::RoundFinalized(e.try_into()?)
+            }
+            generated::ark::v1::get_event_stream_response::Event::RoundFailed(e) => {
+                RoundStreamEvent::RoundFailed(e.into())
+            }
+            generated::ark::v1::get_event_stream_response::Event::RoundSigning(e) => {
+                RoundStreamEvent::RoundSigning(e.try_into()?)
+            }
+            generated::ark::v1::get_event_stream_response::Event::RoundSigningNoncesGenerated(
+                e,
+            ) => RoundStreamEvent::RoundSigningNoncesGenerated(e.try_into()?),
+        })
+    }
+}
+
+impl TryFrom<generated::ark::v1::Round> for Round {
+    type Error = Error;
+
+    fn try_from(value: generated::ark::v1::Round) -> Result<Self, Self::Error> {
+        let round_tx = decode_psbt_base64(&value.round_tx)?;
+
+        let vtxo_tree = value.vtxo_tree.unwrap_or_default().try_into()?;
+
+        let forfeit_txs = value
+            .forfeit_txs
+            .into_iter()
+            .map(|t| decode_psbt_base64(&t))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let connector_tree = TxTree::try_from(value.connectors.unwrap_or_default())?;
+
+        Ok(Round {
+            id: value.id,
+            start: value.start,
+            end: value.end,
+            round_tx,
+            vtxo_tree,
+            forfeit_txs,
+            connector_tree,
+            stage: value.stage,
+        })
+    }
+}
+
+impl TryFrom<generated::ark::v1::get_transactions_stream_response::Tx> for TransactionEvent {
+    type Error = Error;
+
+    fn try_from(
+        value: generated::ark::v1::get_transactions_stream_response::Tx,
+    ) -> Result<Self, Self::Error> {
+        match value {
+            generated::ark::v1::get_transactions_stream_response::Tx::Round(round) => {
+                Ok(TransactionEvent::Round(RoundTransaction::try_from(round)?))
+            }
+            generated::ark::v1::get_transactions_stream_response::Tx::Redeem(redeem) => Ok(
+                TransactionEvent::Redeem(RedeemTransaction::try_from(redeem)?),
+            ),
+        }
+    }
+}
+
+impl TryFrom<generated::ark::v1::RoundTransaction> for RoundTransaction {
+    type Error = Error;
+
+    fn try_from(value: generated::ark::v1::RoundTransaction) -> Result<Self, Self::Error> {
+        let spent_vtxos = value
+            .spent_vtxos
+            .iter()
+            .map(VtxoOutPoint::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let claimed_boarding_utxos = value
+            .claimed_boarding_utxos
+            .into_iter()
+            .map(OutPoint::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let spendable_vtxos = value
+            .spendable_vtxos
+            .iter()
+            .map(VtxoOutPoint::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(RoundTransaction {
+            txid: Txid::from_str(value.txid.as_str()).map_err(Error::conversion)?,
+            spent_vtxos,
+            spendable_vtxos,
+            claimed_boarding_utxos,
+        })
+    }
+}
+
+impl TryFrom<generated::ark::v1::RedeemTransaction> for RedeemTransaction {
+    type Error = Error;
+
+    fn try_from(value: generated::ark::v1::RedeemTransaction) -> Result<Self, Self::Error> {
+        let spent_vtxos = value
+            .spent_vtxos
+            .iter()
+            .map(VtxoOutPoint::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let spendable_vtxos = value
+            .spendable_vtxos
+            .iter()
+            .map(VtxoOutPoint::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(RedeemTransaction {
+            txid: Txid::from_str(value.txid.as_str()).map_err(Error::conversion)?,
+            spent_vtxos,
+            spendable_vtxos,
+        })
+    }
+}
+
+impl TryFrom<Outpoint> for OutPoint {
+    type Error = Error;
+
+    fn try_from(value: Outpoint) -> Result<Self, Self::Error> {
+        let point = OutPoint {
+            txid: Txid::from_str(value.txid.as_str()).map_err(Error::conversion)?,
+            vout: value.vout,
+        };
+        Ok(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vtxo_without_outpoint_is_an_error_not_a_panic() {
+        let vtxo = generated::ark::v1::Vtxo {
+            outpoint: None,
+            ..Default::default()
+        };
+        let error = VtxoOutPoint::try_from(&vtxo).unwrap_err();
+        assert!(format!("{error:?}").contains("VTXO without an outpoint"));
+    }
+}
@@ -0,0 +1,114 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use ark_core::ArkAddress;
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::Network;
+
+use crate::types::*;
+
+/// Networks an address is reported as being for, in order of preference when it is valid on
+/// several of them and the Ark server is on none of those.
+const NETWORKS: [Network; 5] = [
+    Network::Bitcoin,
+    Network::Testnet,
+    Network::Testnet4,
+    Network::Signet,
+    Network::Regtest,
+];
+
+/// Check whether `address` is an Ark or on-chain address, and for which network, so that a send
+/// form can flag a mistake before anything is sent. No wallet is needed.
+///
+/// Addresses do not always pin down a single network: every test network shares one Ark address
+/// prefix, and on-chain testnet and signet addresses look alike. Such an address is reported as
+/// being for the Ark server's network if it is valid there.
+#[get("/validate_address")]
+pub async fn validate_address(
+    query: web::Query<ValidateAddressQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let address = query.address.trim();
+    let server_network = data.server_info().map(|info| info.network);
+
+    let (kind, networks) = if let Ok(address) = ArkAddress::decode(address) {
+        ("ark", valid_networks(|network| address.is_valid_for_network(network)))
+    } else if let Ok(address) = address.parse::<bitcoin::Address<NetworkUnchecked>>() {
+        ("onchain", valid_networks(|network| address.is_valid_for_network(network)))
+    } else {
+        return HttpResponse::Ok().json(ValidateAddressResponse {
+            valid: false,
+            kind: None,
+            network: None,
+            matches_server_network: None,
+            error: Some("Not an Ark or Bitcoin address".to_string()),
+        });
+    };
+
+    let matches_server_network = server_network.map(|network| networks.contains(&network));
+    let network = match matches_server_network {
+        Some(true) => server_network,
+        _ => networks.first().copied(),
+    };
+
+    HttpResponse::Ok().json(ValidateAddressResponse {
+        valid: true,
+        kind: Some(kind),
+        network: network.map(|network| network.to_string()),
+        matches_server_network,
+        error: server_network
+            .filter(|_| matches_server_network == Some(false))
+            .map(|network| format!("Address is not for the server's network ({})", network)),
+    })
+}
+
+/// The canonical encoding of an Ark or on-chain address, so that two spellings of the same
+/// address compare equal. `None` if `address` is neither.
+pub(crate) fn canonical_address(address: &str) -> Option<String> {
+    if let Ok(address) = ArkAddress::decode(address) {
+        return Some(address.encode());
+    }
+    let address = address.parse::<bitcoin::Address<NetworkUnchecked>>().ok()?;
+    Some(address.assume_checked().to_string())
+}
+
+fn valid_networks(is_valid_for: impl Fn(Network) -> bool) -> Vec<Network> {
+    NETWORKS.into_iter().filter(|network| is_valid_for(*network)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn addresses_are_told_apart_by_kind_and_network() {
+        let data = web::Data::new(testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1"));
+        let app = test::init_service(App::new().app_data(data).service(validate_address)).await;
+
+        let validate = |address: &str| {
+            let uri = format!("/validate_address?address={}", address);
+            test::call_and_read_body_json::<_, _, serde_json::Value>(
+                &app,
+                test::TestRequest::get().uri(&uri).to_request(),
+            )
+        };
+
+        let ark = validate(
+            "tark1x0lm8hhr2wc6n6lyemtyh9rz8rg2ftpkfun46aca56kjg3ws0tsztfpuanaquxc6faedvjk3tax0575y6\
+             perapg3e95654pk8r4fjecs5fyd2",
+        )
+        .await;
+        assert_eq!(ark["valid"], true);
+        assert_eq!(ark["kind"], "ark");
+        assert_eq!(ark["network"], "regtest");
+
+        let mainnet = validate("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").await;
+        assert_eq!(mainnet["kind"], "onchain");
+        assert_eq!(mainnet["network"], "bitcoin");
+        assert_eq!(mainnet["matches_server_network"], false);
+
+        let garbage = validate("tark1qqqqqq").await;
+        assert_eq!(garbage["valid"], false);
+        assert!(garbage["error"].is_string());
+    }
+}
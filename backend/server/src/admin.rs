@@ -0,0 +1,75 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+
+use crate::types::*;
+
+#[get("/admin/cache/stats")]
+pub async fn cache_stats(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if let Err(response) = authorize(&req, &data.config) {
+        return response;
+    }
+
+    let caches = data.caches().iter().map(|cache| cache.stats()).collect();
+
+    HttpResponse::Ok().json(CacheStatsResponse { caches })
+}
+
+#[post("/admin/cache/flush")]
+pub async fn cache_flush(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    body: Option<web::Json<CacheFlushRequest>>,
+) -> impl Responder {
+    if let Err(response) = authorize(&req, &data.config) {
+        return response;
+    }
+
+    let only = body.and_then(|body| body.into_inner().cache);
+
+    let caches = data.caches();
+    if let Some(name) = &only
+        && !caches.iter().any(|cache| cache.name() == name)
+    {
+        return HttpResponse::NotFound().body(format!("Unknown cache: {}", name));
+    }
+
+    let flushed = caches
+        .iter()
+        .filter(|cache| only.as_deref().is_none_or(|name| cache.name() == name))
+        .map(|cache| {
+            let removed = cache.flush();
+            tracing::info!(cache = cache.name(), removed, "Flushed cache");
+            FlushedCache {
+                name: cache.name(),
+                removed,
+            }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(CacheFlushResponse { flushed })
+}
+
+/// Admin endpoints are only reachable when `admin_token` is configured, and then only with a
+/// matching `Authorization: Bearer <token>` header.
+fn authorize(req: &HttpRequest, config: &Config) -> Result<(), HttpResponse> {
+    let Some(expected) = config.admin_token.as_deref() else {
+        return Err(HttpResponse::NotFound().body("Admin endpoints are disabled"));
+    };
+
+    match bearer_token_matches(req, expected) {
+        true => Ok(()),
+        false => Err(HttpResponse::Unauthorized().body("Invalid or missing admin token")),
+    }
+}
+
+/// Whether `req` carries an `Authorization: Bearer <token>` header with `expected` as the token.
+pub(crate) fn bearer_token_matches(req: &HttpRequest, expected: &str) -> bool {
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,74 @@
+use tokio::sync::Mutex;
+
+/// A connection to the Ark server shared by every handler.
+///
+/// The connection is made on first use and reused afterwards; cloning an `ark_grpc::Client` only
+/// clones a handle to the same channel. When a call fails because the connection broke, handlers
+/// pass the error to [`ArkClient::check`] so that the next request reconnects.
+pub struct ArkClient {
+    url: String,
+    retry_policy: ark_grpc::RetryPolicy,
+    timeouts: ark_grpc::Timeouts,
+    tls: Option<ark_grpc::TlsConfig>,
+    // A tokio mutex: it is held while connecting, so concurrent requests wait for one connection
+    // attempt instead of each making their own.
+    client: Mutex<Option<ark_grpc::Client>>,
+    /// Counts failed connection attempts and the failed calls passed to [`ArkClient::check`].
+    errors: prometheus::IntCounter,
+}
+
+impl ArkClient {
+    pub fn new(
+        url: String,
+        retry_policy: ark_grpc::RetryPolicy,
+        timeouts: ark_grpc::Timeouts,
+        tls: Option<ark_grpc::TlsConfig>,
+    ) -> Self {
+        Self {
+            url,
+            retry_policy,
+            timeouts,
+            tls,
+            client: Mutex::new(None),
+            errors: prometheus::IntCounter::new("ark_errors", "Failed Ark server calls").unwrap(),
+        }
+    }
+
+    /// Count errors in `errors`, e.g. a counter exported as a metric.
+    pub fn with_error_counter(mut self, errors: prometheus::IntCounter) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// A connected client, connecting first if there is no live connection.
+    pub async fn get(&self) -> Result<ark_grpc::Client, ark_grpc::Error> {
+        let mut client = self.client.lock().await;
+        if let Some(client) = client.as_ref() {
+            return Ok(client.clone());
+        }
+
+        let mut new_client = ark_grpc::Client::new(self.url.clone())
+            .with_retry_policy(self.retry_policy)
+            .with_timeouts(self.timeouts);
+        if let Some(tls) = &self.tls {
+            new_client = new_client.with_tls(tls.clone());
+        }
+        if let Err(e) = new_client.connect().await {
+            self.errors.inc();
+            return Err(e);
+        }
+        *client = Some(new_client.clone());
+
+        Ok(new_client)
+    }
+
+    /// Drop the shared connection if `error` shows that it is broken, or that the server stopped
+    /// answering on it.
+    pub async fn check(&self, error: &ark_grpc::Error) {
+        self.errors.inc();
+        if error.is_transport() || error.is_timeout() {
+            tracing::warn!(error = %error, "Lost connection to Ark server, reconnecting on next use");
+            *self.client.lock().await = None;
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use actix_web::web;
+use futures::future::join_all;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::MissedTickBehavior;
+
+use crate::transactions::{finish_settlement, prepare_settlement, spendable_vtxos};
+use crate::types::*;
+
+/// Periodically settle the wallets selected by `auto_settle`/`auto_settle_wallets` whose VTXOs
+/// are about to expire, so that their funds are refreshed instead of lost.
+///
+/// The wallets due at a check are settled concurrently, their starts spread over
+/// `auto_settle_batch_window_secs`, so that one long round does not hold up the others. A wallet
+/// with a send or settlement already in flight is skipped until the next check. Once shutdown
+/// starts no new check or settlement begins; settlements already running are left to finish.
+pub async fn run_auto_settle(data: web::Data<AppState>) {
+    let config = &data.config;
+    if !config.auto_settle && config.auto_settle_wallets.is_empty() {
+        return;
+    }
+
+    tracing::info!(
+        interval_secs = config.auto_settle_interval_secs,
+        threshold_secs = config.auto_settle_threshold_secs,
+        batch_window_secs = config.auto_settle_batch_window_secs,
+        "Auto-settle enabled"
+    );
+
+    let period = Duration::from_secs(config.auto_settle_interval_secs.max(1));
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let window = Duration::from_secs(config.auto_settle_batch_window_secs);
+
+    loop {
+        tokio::select! {
+            _ = data.background.shutdown_started() => return,
+            _ = interval.tick() => {}
+        }
+
+        let wallets = data
+            .wallets
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|info| matches!(info.keys, WalletKeys::Full { .. }))
+            .filter(|info| config.auto_settles(&info.id))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let due = join_all(wallets.into_iter().map(|wallet_info| async {
+            is_due(&data, &wallet_info).await.then_some(wallet_info)
+        }))
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        let count = due.len();
+        join_all(due.iter().enumerate().map(|(i, wallet_info)| {
+            let data = &data;
+            async move {
+                tokio::time::sleep(stagger(window, count, i)).await;
+                auto_settle_wallet(data, wallet_info).await;
+            }
+        }))
+        .await;
+    }
+}
+
+/// Whether one of the VTXOs of `wallet_info` expires within `auto_settle_threshold_secs`.
+async fn is_due(data: &AppState, wallet_info: &WalletInfo) -> bool {
+    let vtxos = match spendable_vtxos(data, wallet_info).await {
+        Ok(vtxos) => vtxos,
+        Err(response) => {
+            tracing::warn!(
+                wallet_id = %wallet_info.id,
+                status = %response.status(),
+                "Auto-settle could not list VTXOs"
+            );
+            return false;
+        }
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let expiries = vtxos.spendable.iter().map(|(outpoint, _)| outpoint.expire_at);
+    expires_within(expiries, now, data.config.auto_settle_threshold_secs)
+}
+
+async fn auto_settle_wallet(data: &AppState, wallet_info: &WalletInfo) {
+    if data.background.is_shutting_down() {
+        return;
+    }
+
+    let Some(_spend_guard) = data.spend_locks.try_lock(&wallet_info.id) else {
+        tracing::info!(
+            wallet_id = %wallet_info.id,
+            "Wallet is busy, auto-settle postponed to the next check"
+        );
+        return;
+    };
+
+    let settlement = match prepare_settlement(data, wallet_info, None).await {
+        Ok(settlement) => settlement,
+        Err(response) => {
+            tracing::warn!(
+                wallet_id = %wallet_info.id,
+                status = %response.status(),
+                "Auto-settle could not prepare the settlement"
+            );
+            return;
+        }
+    };
+    let has_expired = settlement.has_expired;
+
+    let settle_result = settlement.run(|_| {}).await;
+    let (_, response) = finish_settlement(data, wallet_info, settle_result, has_expired).await;
+
+    match response.txid {
+        Some(txid) => tracing::info!(wallet_id = %wallet_info.id, %txid, "Auto-settled wallet"),
+        None => tracing::warn!(
+            wallet_id = %wallet_info.id,
+            error = response.error.as_deref().unwrap_or_default(),
+            "Auto-settle failed"
+        ),
+    }
+}
+
+/// Whether any of the `expiries` (Unix timestamps) is within `threshold_secs` of `now`, or
+/// already passed.
+fn expires_within(expiries: impl IntoIterator<Item = i64>, now: i64, threshold_secs: u64) -> bool {
+    let deadline = now.saturating_add(i64::try_from(threshold_secs).unwrap_or(i64::MAX));
+    expiries.into_iter().any(|expire_at| expire_at <= deadline)
+}
+
+/// How long after the start of a check the `index`th of `count` due wallets starts settling, so
+/// that their starts are spread evenly over `window`.
+fn stagger(window: Duration, count: usize, index: usize) -> Duration {
+    match u32::try_from(count) {
+        Ok(count) if count > 0 => window / count * index as u32,
+        _ => Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settles_only_when_a_vtxo_is_close_to_expiry() {
+        let now = 1_000_000;
+
+        assert!(!expires_within([], now, 3_600));
+        assert!(!expires_within([now + 7_200], now, 3_600));
+        assert!(expires_within([now + 7_200, now + 3_600], now, 3_600));
+        assert!(expires_within([now - 10], now, 3_600));
+    }
+
+    #[test]
+    fn settlements_are_spread_over_the_batch_window() {
+        let window = Duration::from_secs(60);
+
+        let starts = (0..4).map(|i| stagger(window, 4, i)).collect::<Vec<_>>();
+        assert_eq!(starts, [0, 15, 30, 45].map(Duration::from_secs));
+        assert_eq!(stagger(Duration::ZERO, 4, 3), Duration::ZERO);
+    }
+}
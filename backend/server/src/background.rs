@@ -0,0 +1,105 @@
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::runtime::Handle;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// Tasks that run beside the HTTP workers, and the signal telling them that the server is
+/// shutting down.
+///
+/// Tasks are spawned on the runtime the server was started from rather than on the worker they
+/// were started from, so that stopping the workers does not cut them off. Once shutdown has
+/// started no new spend is accepted, and [`BackgroundTasks::wait`] waits for the tasks still
+/// running.
+#[derive(Default)]
+pub struct BackgroundTasks {
+    shutdown: CancellationToken,
+    tasks: Mutex<JoinSet<()>>,
+    /// `None` to spawn on the current runtime.
+    runtime: Option<Handle>,
+}
+
+impl BackgroundTasks {
+    pub fn new(runtime: Handle) -> Self {
+        Self {
+            runtime: Some(runtime),
+            ..Default::default()
+        }
+    }
+
+    /// Run `task` until it finishes. It is expected to return soon after [`Self::shutdown_started`]
+    /// completes, or to finish whatever it is in the middle of.
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.tasks.lock().unwrap();
+        while tasks.try_join_next().is_some() {}
+        match &self.runtime {
+            Some(runtime) => tasks.spawn_on(task, runtime),
+            None => tasks.spawn(task),
+        };
+    }
+
+    /// Start shutting down: background loops stop and new spends are refused.
+    pub fn shut_down(&self) {
+        self.shutdown.cancel();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
+    /// Completes once shutdown has started.
+    pub async fn shutdown_started(&self) {
+        self.shutdown.cancelled().await
+    }
+
+    /// Wait for the tasks still running to finish, until `deadline`. The ones still running then
+    /// are aborted.
+    ///
+    /// Returns how many of them finished and how many were aborted.
+    pub async fn wait(&self, deadline: Instant) -> (usize, usize) {
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+
+        let mut finished = 0;
+        let _ = tokio::time::timeout_at(deadline, async {
+            while tasks.join_next().await.is_some() {
+                finished += 1;
+            }
+        })
+        .await;
+
+        (finished, tasks.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn wait_gives_up_on_tasks_still_running_at_the_deadline() {
+        let background = BackgroundTasks::default();
+
+        background.spawn(tokio::time::sleep(Duration::from_millis(50)));
+        background.spawn(std::future::pending());
+
+        let deadline = Instant::now() + Duration::from_millis(500);
+        assert_eq!(background.wait(deadline).await, (1, 1));
+        assert_eq!(background.wait(Instant::now()).await, (0, 0));
+    }
+
+    #[tokio::test]
+    async fn loops_are_told_when_shutdown_starts() {
+        let background = std::sync::Arc::new(BackgroundTasks::default());
+
+        let tasks = background.clone();
+        background.spawn(async move { tasks.shutdown_started().await });
+        assert!(!background.is_shutting_down());
+
+        background.shut_down();
+        assert!(background.is_shutting_down());
+        let deadline = Instant::now() + Duration::from_millis(500);
+        assert_eq!(background.wait(deadline).await, (1, 0));
+    }
+}
@@ -0,0 +1,124 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A small in-memory cache whose entries expire after a fixed time-to-live.
+///
+/// Hits and misses are counted so that the admin endpoints can report how useful each cache is.
+pub struct TtlCache<K, V> {
+    name: &'static str,
+    ttl: Duration,
+    inner: Mutex<CacheInner<K, V>>,
+}
+
+struct CacheInner<K, V> {
+    entries: HashMap<K, (Instant, V)>,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub ttl_secs: u64,
+    pub oldest_entry_age_secs: Option<u64>,
+}
+
+/// Operations the admin endpoints need on every cache, regardless of its key and value types.
+pub trait CacheAdmin: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn stats(&self) -> CacheStats;
+
+    /// Remove every entry, returning how many were removed.
+    fn flush(&self) -> usize;
+}
+
+impl<K, V> TtlCache<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    pub fn new(name: &'static str, ttl: Duration) -> Self {
+        Self {
+            name,
+            ttl,
+            inner: Mutex::new(CacheInner {
+                entries: HashMap::new(),
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let value = match inner.entries.get(key) {
+            Some((inserted_at, value)) if inserted_at.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                inner.entries.remove(key);
+                None
+            }
+            None => None,
+        };
+
+        match value {
+            Some(_) => inner.hits += 1,
+            None => inner.misses += 1,
+        }
+
+        value
+    }
+
+    pub fn remove(&self, key: &K) {
+        self.inner.lock().unwrap().entries.remove(key);
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .insert(key, (Instant::now(), value));
+    }
+}
+
+impl<K, V> CacheAdmin for TtlCache<K, V>
+where
+    K: Send,
+    V: Send,
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+
+        CacheStats {
+            name: self.name,
+            entries: inner.entries.len(),
+            hits: inner.hits,
+            misses: inner.misses,
+            ttl_secs: self.ttl.as_secs(),
+            oldest_entry_age_secs: inner
+                .entries
+                .values()
+                .map(|(inserted_at, _)| inserted_at.elapsed().as_secs())
+                .max(),
+        }
+    }
+
+    fn flush(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let removed = inner.entries.len();
+        inner.entries.clear();
+        removed
+    }
+}
@@ -0,0 +1,201 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::{Network, Transaction, Txid};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::types::*;
+
+/// How long a fetched chain tip is reused before asking Esplora again.
+pub const CHAIN_TIP_TTL: Duration = Duration::from_secs(10);
+
+/// The oldest a tip may be before we consider the Esplora backend to be lagging.
+const MAX_TIP_AGE: Duration = Duration::from_secs(2 * 60 * 60);
+
+#[get("/chain_status")]
+pub async fn chain_status(data: web::Data<AppState>) -> impl Responder {
+    let tip = match current_tip(&data).await {
+        Ok(tip) => tip,
+        Err(response) => return response,
+    };
+
+    let network = data.server_info().map(|info| info.network);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let tip_age_secs = now.saturating_sub(tip.time);
+
+    // Regtest blocks are only mined on demand, so an old tip says nothing about sync state.
+    let synced = match network {
+        Some(Network::Regtest) => true,
+        _ => tip_age_secs <= MAX_TIP_AGE.as_secs(),
+    };
+
+    HttpResponse::Ok().json(ChainStatusResponse {
+        height: tip.height,
+        tip_hash: tip.hash.to_string(),
+        tip_time: tip.time,
+        tip_age_secs,
+        synced,
+    })
+}
+
+/// Current fee rate estimates from Esplora, by confirmation target.
+#[get("/fee_rates")]
+pub async fn get_fee_rates(data: web::Data<AppState>) -> impl Responder {
+    match current_fee_rates(&data).await {
+        Ok(fee_rates) => HttpResponse::Ok().json(FeeRatesResponse { fee_rates }),
+        Err(response) => response,
+    }
+}
+
+/// Whether an on-chain transaction, e.g. a settlement's round transaction, has confirmed.
+///
+/// Answers 404 while Esplora does not know the transaction, i.e. before it reached Esplora's
+/// mempool. Ark transactions paying another Ark address never go on-chain, so they are never found.
+#[get("/tx_status/{txid}")]
+pub async fn tx_status(txid: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let txid = match Txid::from_str(&txid) {
+        Ok(txid) => txid,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid txid: {}", e)),
+    };
+
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
+        None => return HttpResponse::InternalServerError().body("Esplora client not available"),
+    };
+
+    match esplora_client.tx_status(&txid).await {
+        Ok(Some(status)) => HttpResponse::Ok().json(TxStatusResponse {
+            confirmed: status.confirmed,
+            block_height: status.block_height,
+            block_time: status.block_time,
+        }),
+        Ok(None) => HttpResponse::NotFound().body(format!("Transaction {} not found", txid)),
+        Err(e) => HttpResponse::InternalServerError()
+            .body(format!("Failed to fetch transaction status: {}", e)),
+    }
+}
+
+/// Broadcast a fully signed raw transaction, e.g. a unilateral exit, through Esplora.
+#[post("/broadcast")]
+pub async fn broadcast(
+    data: web::Data<AppState>,
+    req: web::Json<BroadcastRequest>,
+) -> impl Responder {
+    let tx = match deserialize_hex::<Transaction>(req.tx_hex.trim()) {
+        Ok(tx) => tx,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid transaction: {}", e)),
+    };
+
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
+        None => return HttpResponse::InternalServerError().body("Esplora client not available"),
+    };
+
+    match esplora_client.broadcast(&tx).await {
+        Ok(txid) => {
+            tracing::info!(%txid, "Broadcast transaction");
+            HttpResponse::Ok().json(BroadcastResponse {
+                txid: txid.to_string(),
+            })
+        }
+        Err(BroadcastError::Rejected { status, message }) => {
+            tracing::info!(txid = %tx.compute_txid(), status, %message, "Transaction rejected");
+            HttpResponse::BadRequest().body(message)
+        }
+        Err(BroadcastError::Unavailable(e)) => {
+            HttpResponse::BadGateway().body(format!("Failed to broadcast transaction: {}", e))
+        }
+    }
+}
+
+/// Return Esplora's fee rate estimates in sat/vB by confirmation target, reusing recently
+/// fetched ones if available.
+pub(crate) async fn current_fee_rates(data: &AppState) -> Result<BTreeMap<u16, f64>, HttpResponse> {
+    if let Some(fee_rates) = data.fee_rates.get(&()) {
+        return Ok(fee_rates);
+    }
+
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
+        None => {
+            return Err(
+                HttpResponse::InternalServerError().body("Esplora client not available")
+            );
+        }
+    };
+
+    let fee_rates = esplora_client.fee_estimates().await.map_err(|e| {
+        HttpResponse::InternalServerError().body(format!("Failed to fetch fee rates: {}", e))
+    })?;
+
+    data.fee_rates.insert((), fee_rates.clone());
+
+    Ok(fee_rates)
+}
+
+/// Return the chain tip as seen by Esplora, reusing a recently fetched one if available.
+pub(crate) async fn current_tip(data: &AppState) -> Result<ChainTip, HttpResponse> {
+    if let Some(tip) = data.chain_tip.get(&()) {
+        return Ok(tip);
+    }
+
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
+        None => {
+            return Err(
+                HttpResponse::InternalServerError().body("Esplora client not available")
+            );
+        }
+    };
+
+    let tip = esplora_client.chain_tip().await.map_err(|e| {
+        HttpResponse::InternalServerError().body(format!("Failed to fetch chain tip: {}", e))
+    })?;
+
+    data.chain_tip.insert((), tip);
+
+    Ok(tip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn malformed_transactions_are_not_broadcast() {
+        // Esplora is unreachable: a transaction that got that far would fail with a 502.
+        let data = web::Data::new(testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1"));
+        let app = test::init_service(App::new().app_data(data).service(broadcast)).await;
+
+        for tx_hex in ["", "not hex", "0200000001"] {
+            let response = test::call_service(
+                &app,
+                test::TestRequest::post()
+                    .uri("/broadcast")
+                    .set_json(serde_json::json!({ "tx_hex": tx_hex }))
+                    .to_request(),
+            )
+            .await;
+
+            assert_eq!(response.status(), actix_web::http::StatusCode::BAD_REQUEST, "{tx_hex}");
+        }
+    }
+    #[actix_web::test]
+    async fn tx_status_requires_a_txid() {
+        let data = web::Data::new(testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1"));
+        let app = test::init_service(App::new().app_data(data).service(tx_status)).await;
+
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/tx_status/abc").to_request())
+                .await;
+
+        assert_eq!(response.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+}
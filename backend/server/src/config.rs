@@ -0,0 +1,97 @@
+use schemars::schema::{RootSchema, Schema};
+use serde_json::Value;
+
+use crate::types::Config;
+
+pub fn config_schema() -> RootSchema {
+    schemars::schema_for!(Config)
+}
+
+/// Parse `ark.config.toml`, turning the common first-run mistakes into readable messages.
+pub fn parse_config(content: &str) -> Result<Config, String> {
+    if content.trim().is_empty() {
+        return Err("config file is empty; see --generate-example-config".to_string());
+    }
+
+    let table = content
+        .parse::<toml::Table>()
+        .map_err(|e| format!("invalid TOML: {}", e))?;
+
+    let schema = config_schema();
+    let missing = schema
+        .schema
+        .object
+        .as_deref()
+        .map(|object| {
+            object
+                .required
+                .iter()
+                .filter(|name| !table.contains_key(name.as_str()))
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    if !missing.is_empty() {
+        return Err(format!(
+            "missing required field(s): {}; see --generate-example-config",
+            missing.join(", ")
+        ));
+    }
+
+    toml::from_str::<Config>(content).map_err(|e| e.to_string())
+}
+
+/// Render a commented `ark.config.toml` from the [`Config`] schema.
+///
+/// Required options are written out with an example value; optional ones are commented out and
+/// show their default, so the file works as-is and documents everything that can be tuned.
+pub fn example_config() -> String {
+    let schema = config_schema();
+    let object = schema.schema.object.as_deref();
+
+    let mut out = String::from("# Ark wallet server configuration.\n");
+    let Some(object) = object else {
+        return out;
+    };
+
+    for (name, property) in object.properties.iter() {
+        let Schema::Object(property) = property else {
+            continue;
+        };
+
+        out.push('\n');
+        let metadata = property.metadata.as_deref();
+
+        if let Some(description) = metadata.and_then(|m| m.description.as_deref()) {
+            for line in description.lines().map(str::trim_end) {
+                match line.is_empty() {
+                    true => out.push_str("#\n"),
+                    false => out.push_str(&format!("# {}\n", line)),
+                }
+            }
+        }
+
+        let example = metadata.and_then(|m| m.examples.first());
+        let default = metadata.and_then(|m| m.default.as_ref());
+
+        if object.required.contains(name) {
+            let value = example.map(toml_value).unwrap_or_else(|| "\"\"".to_string());
+            out.push_str(&format!("{} = {}\n", name, value));
+        } else {
+            match default {
+                Some(Value::Null) | None => out.push_str(&format!("# {} =\n", name)),
+                Some(value) => out.push_str(&format!("# {} = {}\n", name, toml_value(value))),
+            }
+        }
+    }
+
+    out
+}
+
+fn toml_value(value: &Value) -> String {
+    match toml::Value::try_from(value) {
+        Ok(value) => value.to_string(),
+        Err(_) => value.to_string(),
+    }
+}
@@ -0,0 +1,544 @@
+use ark_core::ExplorerUtxo;
+use bitcoin::Amount;
+use futures::future::{BoxFuture, Shared};
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::cache::{CacheAdmin, CacheStats, TtlCache};
+
+/// How long a backend that just failed is skipped before we try it again.
+const BACKEND_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// How many output status requests are in flight at once unless configured otherwise.
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// Confirmed transactions Esplora returns per page of address history.
+const HISTORY_PAGE_SIZE: usize = 25;
+
+/// Stop paging through an address's history after this many pages rather than looping forever
+/// on a misbehaving backend.
+const MAX_HISTORY_PAGES: usize = 200;
+
+/// A lookup of an address's outputs that every request wanting them awaits.
+type OutpointFetch = Shared<BoxFuture<'static, Result<Vec<ExplorerUtxo>, String>>>;
+
+/// The outputs Esplora reported for each address, shared between requests.
+///
+/// Concurrent requests for an address that is not cached wait for a single lookup rather than
+/// each sending their own.
+pub struct OutpointCache {
+    outpoints: TtlCache<String, Vec<ExplorerUtxo>>,
+    fetches: Mutex<HashMap<String, OutpointFetch>>,
+}
+
+impl OutpointCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            outpoints: TtlCache::new("outpoints", ttl),
+            fetches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The outputs of `address`, from the cache or else from `client`.
+    pub async fn find_outpoints(
+        &self,
+        client: &EsploraClient,
+        address: &bitcoin::Address,
+    ) -> Result<Vec<ExplorerUtxo>, anyhow::Error> {
+        let key = address.to_string();
+        if let Some(outpoints) = self.outpoints.get(&key) {
+            return Ok(outpoints);
+        }
+
+        let fetch = self
+            .fetches
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| {
+                let client = client.clone();
+                let address = address.clone();
+                async move {
+                    client
+                        .find_outpoints(&address)
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                .boxed()
+                .shared()
+            })
+            .clone();
+
+        let result = fetch.clone().await;
+
+        // Only the lookup still registered may fill the cache: one that was invalidated while in
+        // flight may predate the spend that invalidated it.
+        let mut fetches = self.fetches.lock().unwrap();
+        if fetches.get(&key).is_some_and(|current| current.ptr_eq(&fetch)) {
+            fetches.remove(&key);
+            if let Ok(outpoints) = &result {
+                self.outpoints.insert(key, outpoints.clone());
+            }
+        }
+
+        result.map_err(anyhow::Error::msg)
+    }
+
+    /// Forget the outputs of `address`, e.g. because some were just spent.
+    pub fn invalidate(&self, address: &bitcoin::Address) {
+        let key = address.to_string();
+        self.fetches.lock().unwrap().remove(&key);
+        self.outpoints.remove(&key);
+    }
+}
+
+impl CacheAdmin for OutpointCache {
+    fn name(&self) -> &'static str {
+        self.outpoints.name()
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.outpoints.stats()
+    }
+
+    fn flush(&self) -> usize {
+        self.fetches.lock().unwrap().clear();
+        self.outpoints.flush()
+    }
+}
+
+/// Why [`EsploraClient::broadcast`] failed.
+pub enum BroadcastError {
+    /// A backend rejected the transaction, e.g. because it is invalid or conflicts with the
+    /// mempool. The message is the backend's, verbatim.
+    Rejected { status: u16, message: String },
+    /// No backend could be reached.
+    Unavailable(anyhow::Error),
+}
+
+#[derive(Clone, Copy)]
+pub struct ChainTip {
+    pub height: u32,
+    pub hash: bitcoin::BlockHash,
+    pub time: u64,
+}
+
+/// A set of Esplora backends queried in order of preference.
+///
+/// Every call goes to the first healthy backend and falls through to the next one on failure. A
+/// backend that fails is marked down for [`BACKEND_COOLDOWN`]; if every backend is marked down we
+/// try them all anyway rather than failing without a single request.
+#[derive(Clone)]
+pub struct EsploraClient {
+    backends: Arc<Vec<Backend>>,
+    max_concurrent_requests: usize,
+    /// Counts the calls a backend failed.
+    errors: prometheus::IntCounter,
+}
+
+struct Backend {
+    url: String,
+    client: Arc<esplora_client::AsyncClient>,
+    failed_at: Mutex<Option<Instant>>,
+}
+
+impl Backend {
+    fn is_healthy(&self) -> bool {
+        match *self.failed_at.lock().unwrap() {
+            Some(failed_at) => failed_at.elapsed() >= BACKEND_COOLDOWN,
+            None => true,
+        }
+    }
+}
+
+impl EsploraClient {
+    pub fn new(urls: &[String]) -> Result<Self, anyhow::Error> {
+        if urls.is_empty() {
+            return Err(anyhow::anyhow!("no Esplora URL configured"));
+        }
+
+        let backends = urls
+            .iter()
+            .map(|url| {
+                let client = esplora_client::Builder::new(url).build_async()?;
+                Ok(Backend {
+                    url: url.clone(),
+                    client: Arc::new(client),
+                    failed_at: Mutex::new(None),
+                })
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        Ok(Self {
+            backends: Arc::new(backends),
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            errors: prometheus::IntCounter::new("esplora_errors", "Failed Esplora calls").unwrap(),
+        })
+    }
+
+    /// Count failed calls in `errors`, e.g. a counter exported as a metric.
+    pub fn with_error_counter(mut self, errors: prometheus::IntCounter) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// Limit how many requests a single lookup (e.g. of an address's outputs) sends at once.
+    pub fn with_max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = max_concurrent_requests.max(1);
+        self
+    }
+
+    /// Run `f` against each backend in turn until one succeeds.
+    async fn with_backend<T, F, Fut>(&self, f: F) -> Result<T, anyhow::Error>
+    where
+        F: Fn(Arc<esplora_client::AsyncClient>) -> Fut,
+        Fut: Future<Output = Result<T, anyhow::Error>>,
+    {
+        let (healthy, down): (Vec<_>, Vec<_>) =
+            self.backends.iter().partition(|backend| backend.is_healthy());
+
+        let mut last_error = None;
+        for backend in healthy.into_iter().chain(down) {
+            match f(backend.client.clone()).await {
+                Ok(value) => {
+                    *backend.failed_at.lock().unwrap() = None;
+                    return Ok(value);
+                }
+                Err(e) => {
+                    tracing::warn!(url = %backend.url, error = %e, "Esplora backend failed");
+                    self.errors.inc();
+                    *backend.failed_at.lock().unwrap() = Some(Instant::now());
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no Esplora backend available")))
+    }
+
+    pub async fn chain_tip(&self) -> Result<ChainTip, anyhow::Error> {
+        self.with_backend(|client| async move {
+            let hash = client.get_tip_hash().await?;
+            let header = client.get_header_by_hash(&hash).await?;
+            let status = client.get_block_status(&hash).await?;
+
+            let height = status
+                .height
+                .ok_or_else(|| anyhow::anyhow!("tip block {hash} has no height"))?;
+
+            Ok(ChainTip {
+                height,
+                hash,
+                time: header.time as u64,
+            })
+        })
+        .await
+    }
+
+    pub async fn find_outpoints(
+        &self,
+        address: &bitcoin::Address,
+    ) -> Result<Vec<ExplorerUtxo>, anyhow::Error> {
+        self.with_backend(|client| async move {
+            let deposits = find_deposits(&client, address, self.max_concurrent_requests).await?;
+            Ok(deposits.into_iter().map(|(utxo, _)| utxo).collect())
+        })
+        .await
+    }
+
+    /// Fee rate estimates in sat/vB, by confirmation target in blocks.
+    pub async fn fee_estimates(&self) -> Result<BTreeMap<u16, f64>, anyhow::Error> {
+        self.with_backend(|client| async move {
+            Ok(client.get_fee_estimates().await?.into_iter().collect())
+        })
+        .await
+    }
+
+    /// Whether `txid` is confirmed, or `None` if the backend does not know the transaction.
+    pub async fn tx_status(
+        &self,
+        txid: &bitcoin::Txid,
+    ) -> Result<Option<esplora_client::TxStatus>, anyhow::Error> {
+        self.with_backend(|client| async move {
+            Ok(client.get_tx_info(txid).await?.map(|tx| tx.status))
+        })
+        .await
+    }
+
+    /// Submit `tx` to the network.
+    ///
+    /// A backend that rejects the transaction answered correctly, so it is neither marked down
+    /// nor skipped in favour of the next one.
+    pub async fn broadcast(
+        &self,
+        tx: &bitcoin::Transaction,
+    ) -> Result<bitcoin::Txid, BroadcastError> {
+        let result = self
+            .with_backend(|client| async move {
+                match client.broadcast(tx).await {
+                    Ok(()) => Ok(Ok(())),
+                    Err(esplora_client::Error::HttpResponse { status, message })
+                        if (400..500).contains(&status) =>
+                    {
+                        Ok(Err(BroadcastError::Rejected { status, message }))
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            })
+            .await
+            .map_err(BroadcastError::Unavailable)?;
+
+        result.map(|()| tx.compute_txid())
+    }
+
+    /// Like [`Self::find_outpoints`], but also returns the height of the block that confirmed
+    /// each output, if any.
+    pub async fn find_deposits(
+        &self,
+        address: &bitcoin::Address,
+    ) -> Result<Vec<(ExplorerUtxo, Option<u32>)>, anyhow::Error> {
+        self.with_backend(|client| async move {
+            find_deposits(&client, address, self.max_concurrent_requests).await
+        })
+        .await
+    }
+}
+
+async fn find_deposits(
+    esplora_client: &esplora_client::AsyncClient,
+    address: &bitcoin::Address,
+    max_concurrent_requests: usize,
+) -> Result<Vec<(ExplorerUtxo, Option<u32>)>, anyhow::Error> {
+    let script_pubkey = address.script_pubkey();
+    let txs = fetch_all_pages(
+        |last_seen| esplora_client.scripthash_txs(&script_pubkey, last_seen),
+        |tx: &esplora_client::Tx| tx.status.confirmed.then_some(tx.txid),
+    )
+    .await?;
+
+    let outputs = txs
+        .into_iter()
+        .flat_map(|tx| {
+            let txid = tx.txid;
+            tx.vout
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| v.scriptpubkey == script_pubkey)
+                .map(|(i, v)| {
+                    let utxo = ExplorerUtxo {
+                        outpoint: bitcoin::OutPoint {
+                            txid,
+                            vout: i as u32,
+                        },
+                        amount: Amount::from_sat(v.value),
+                        confirmation_blocktime: tx.status.block_time,
+                        is_confirmed: tx.status.confirmed,
+                        is_spent: false,
+                    };
+                    (utxo, tx.status.block_height)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // The order of the returned outputs does not matter to callers.
+    futures::stream::iter(outputs)
+        .map(|(output, block_height)| async move {
+            let outpoint = output.outpoint;
+            let status = esplora_client
+                .get_output_status(&outpoint.txid, outpoint.vout as u64)
+                .await?;
+
+            let is_spent = matches!(
+                status,
+                Some(esplora_client::OutputStatus { spent: true, .. })
+            );
+
+            Ok::<_, anyhow::Error>((ExplorerUtxo { is_spent, ..output }, block_height))
+        })
+        .buffer_unordered(max_concurrent_requests)
+        .try_collect()
+        .await
+}
+
+/// Collect every page of an address's transaction history.
+///
+/// The first page holds the unconfirmed transactions and the newest confirmed ones; each further
+/// page holds the confirmed transactions following the last confirmed one seen so far. A page with
+/// fewer than [`HISTORY_PAGE_SIZE`] confirmed transactions is the last one.
+async fn fetch_all_pages<T, E, F, Fut>(
+    mut fetch_page: F,
+    confirmed_txid: impl Fn(&T) -> Option<bitcoin::Txid>,
+) -> Result<Vec<T>, E>
+where
+    F: FnMut(Option<bitcoin::Txid>) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, E>>,
+{
+    let mut txs = Vec::new();
+    let mut last_seen = None;
+
+    for _ in 0..MAX_HISTORY_PAGES {
+        let page = fetch_page(last_seen).await?;
+        let confirmed = page.iter().filter_map(&confirmed_txid).collect::<Vec<_>>();
+        txs.extend(page);
+
+        if confirmed.len() < HISTORY_PAGE_SIZE {
+            return Ok(txs);
+        }
+        last_seen = confirmed.last().copied();
+    }
+
+    tracing::warn!(
+        max_pages = MAX_HISTORY_PAGES,
+        txs = txs.len(),
+        "Address history has too many pages, ignoring older transactions"
+    );
+
+    Ok(txs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+    use std::cell::Cell;
+
+    /// An address history served the way Esplora pages it: unconfirmed transactions first, then
+    /// confirmed ones newest first, [`HISTORY_PAGE_SIZE`] at a time.
+    struct MockHistory {
+        unconfirmed: Vec<Txid>,
+        confirmed: Vec<Txid>,
+        requests: Cell<usize>,
+    }
+
+    impl MockHistory {
+        fn new(unconfirmed: usize, confirmed: usize) -> Self {
+            let txid = |i: usize| {
+                let mut bytes = [0u8; 32];
+                bytes[..8].copy_from_slice(&(i as u64).to_le_bytes());
+                Txid::from_byte_array(bytes)
+            };
+
+            Self {
+                unconfirmed: (0..unconfirmed).map(txid).collect(),
+                confirmed: (unconfirmed..unconfirmed + confirmed).map(txid).collect(),
+                requests: Cell::new(0),
+            }
+        }
+
+        async fn page(&self, last_seen: Option<Txid>) -> Result<Vec<(Txid, bool)>, ()> {
+            self.requests.set(self.requests.get() + 1);
+
+            let start = match last_seen {
+                Some(last_seen) => {
+                    self.confirmed.iter().position(|t| *t == last_seen).unwrap() + 1
+                }
+                None => 0,
+            };
+            let confirmed = self.confirmed.iter().skip(start).take(HISTORY_PAGE_SIZE);
+
+            let unconfirmed = match last_seen {
+                Some(_) => &[][..],
+                None => &self.unconfirmed[..],
+            };
+
+            Ok(unconfirmed
+                .iter()
+                .map(|txid| (*txid, false))
+                .chain(confirmed.map(|txid| (*txid, true)))
+                .collect())
+        }
+    }
+
+    fn confirmed_txid(tx: &(Txid, bool)) -> Option<Txid> {
+        tx.1.then_some(tx.0)
+    }
+
+    #[tokio::test]
+    async fn fetches_every_page_of_history() {
+        let history = MockHistory::new(3, 2 * HISTORY_PAGE_SIZE + 7);
+
+        let txs = fetch_all_pages(|last_seen| history.page(last_seen), confirmed_txid)
+            .await
+            .unwrap();
+
+        assert_eq!(txs.len(), 3 + 2 * HISTORY_PAGE_SIZE + 7);
+        assert_eq!(history.requests.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn stops_after_a_full_last_page() {
+        let history = MockHistory::new(0, HISTORY_PAGE_SIZE);
+
+        let txs = fetch_all_pages(|last_seen| history.page(last_seen), confirmed_txid)
+            .await
+            .unwrap();
+
+        // The second, empty page is what tells us the history is complete.
+        assert_eq!(txs.len(), HISTORY_PAGE_SIZE);
+        assert_eq!(history.requests.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_pages() {
+        let history = MockHistory::new(0, (MAX_HISTORY_PAGES + 5) * HISTORY_PAGE_SIZE);
+
+        let txs = fetch_all_pages(|last_seen| history.page(last_seen), confirmed_txid)
+            .await
+            .unwrap();
+
+        assert_eq!(txs.len(), MAX_HISTORY_PAGES * HISTORY_PAGE_SIZE);
+        assert_eq!(history.requests.get(), MAX_HISTORY_PAGES);
+    }
+    #[actix_web::test]
+    async fn concurrent_lookups_of_an_address_share_one_fetch() {
+        use actix_web::{web, App, HttpResponse, HttpServer};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // An Esplora backend that knows no transactions, and takes a moment to say so.
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let server = HttpServer::new(move || {
+            let counter = counter.clone();
+            App::new().default_service(web::to(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    HttpResponse::Ok().json(Vec::<()>::new())
+                }
+            }))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        let server = server.run();
+        let handle = server.handle();
+        tokio::spawn(server);
+
+        let client = EsploraClient::new(&[url]).unwrap();
+        let cache = OutpointCache::new(Duration::from_secs(60));
+        let address = bitcoin::Address::p2tr_tweaked(
+            bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(
+                bitcoin::XOnlyPublicKey::from_slice(&[2u8; 32]).unwrap(),
+            ),
+            bitcoin::Network::Regtest,
+        );
+
+        let lookups = (0..3).map(|_| cache.find_outpoints(&client, &address));
+        let lookups = futures::future::join_all(lookups).await;
+        assert!(lookups.iter().all(|outpoints| outpoints.as_ref().unwrap().is_empty()));
+        assert!(cache.find_outpoints(&client, &address).await.is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        cache.invalidate(&address);
+        assert!(cache.find_outpoints(&client, &address).await.is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        handle.stop(false).await;
+    }
+}
@@ -0,0 +1,138 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::*;
+use crate::wallet::find_wallet;
+
+/// How many events are returned when the client does not ask for a specific number.
+const DEFAULT_EVENT_LIMIT: usize = 50;
+
+/// Append-only activity log per wallet.
+///
+/// Each wallet keeps at most `capacity` events; the oldest are dropped first. Event IDs increase
+/// monotonically across all wallets, so clients can poll with `since=<last seen id>`.
+pub struct EventLog {
+    capacity: usize,
+    inner: Mutex<EventLogInner>,
+}
+
+struct EventLogInner {
+    next_id: u64,
+    events: HashMap<String, VecDeque<WalletEvent>>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct WalletEvent {
+    pub id: u64,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: WalletEventKind,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WalletEventKind {
+    WalletCreated,
+    WalletImported,
+    SendSubmitted {
+        to_address: String,
+        amount: u64,
+        amount_btc: String,
+        txid: String,
+    },
+    SendFailed {
+        to_address: String,
+        amount: u64,
+        amount_btc: String,
+        error: String,
+    },
+    SettlementStarted { to_address: String },
+    SettlementFinished { txid: String },
+    SettlementFailed { error: String },
+    UnilateralExitBroadcast { txids: Vec<String> },
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(EventLogInner {
+                next_id: 1,
+                events: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn record(&self, wallet_id: &str, kind: WalletEventKind) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+
+        let events = inner.events.entry(wallet_id.to_string()).or_default();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(WalletEvent {
+            id,
+            timestamp,
+            kind,
+        });
+    }
+
+    /// Events for `wallet_id` with an ID greater than `since`, oldest first.
+    pub fn list(&self, wallet_id: &str, since: u64, limit: usize) -> Vec<WalletEvent> {
+        let inner = self.inner.lock().unwrap();
+
+        inner
+            .events
+            .get(wallet_id)
+            .map(|events| {
+                events
+                    .iter()
+                    .filter(|event| event.id > since)
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    pub since: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct EventsResponse {
+    pub wallet_id: String,
+    pub events: Vec<WalletEvent>,
+}
+
+#[get("/wallet/{wallet_id}/events")]
+pub async fn wallet_events(
+    wallet_id: web::Path<String>,
+    query: web::Query<EventsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let wallet_id = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info.id,
+        Err(response) => return response,
+    };
+
+    let limit = query.limit.unwrap_or(DEFAULT_EVENT_LIMIT);
+    let events = data
+        .events
+        .list(&wallet_id, query.since.unwrap_or_default(), limit);
+
+    HttpResponse::Ok().json(EventsResponse { wallet_id, events })
+}
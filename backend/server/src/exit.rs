@@ -0,0 +1,201 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use ark_core::unilateral_exit::{prepare_vtxo_tree_transactions, VtxoProvenance};
+use bitcoin::relative::LockTime;
+use bitcoin::{Sequence, Txid};
+use std::collections::HashMap;
+
+use crate::transactions::lock_wallet;
+use crate::types::*;
+use crate::wallet::{find_wallet, wallet_outpoints};
+
+/// Average time between blocks, to turn an exit delay in seconds into a number of blocks.
+const BLOCK_INTERVAL_SECS: u64 = 600;
+
+/// Start a unilateral exit of the wallet's VTXOs: broadcast the transactions of the VTXO tree
+/// branches that lead to them, so that once they confirm and the exit delay has passed the owner
+/// can spend them on-chain without the Ark server.
+///
+/// Transactions already on chain are not broadcast again, so the request can be repeated until
+/// every branch is out. VTXOs created by a redeem transaction that is not yet part of a settled
+/// round cannot be exited this way and are listed as skipped. The VTXO trees are still looked up
+/// on the Ark server, so the exit has to be started while it answers.
+#[post("/unilateral_exit/{wallet_id}")]
+pub async fn unilateral_exit(
+    wallet_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
+        None => return HttpResponse::InternalServerError().body("Esplora client not available"),
+    };
+
+    let _spend_guard = match lock_wallet(&data, &wallet_info.id) {
+        Ok(guard) => guard,
+        Err(response) => return response,
+    };
+
+    let outpoints = match wallet_outpoints(&data, &wallet_info).await {
+        Ok(outpoints) => outpoints,
+        Err(response) => return response,
+    };
+
+    let (in_round, out_of_round): (Vec<_>, Vec<_>) = outpoints
+        .vtxos
+        .spendable
+        .into_iter()
+        .partition(|(vtxo, _)| vtxo.redeem_tx.is_none());
+    if in_round.is_empty() {
+        return HttpResponse::BadRequest().body("Wallet has no VTXOs that can be exited");
+    }
+
+    let grpc_client = match data.ark_client.get().await {
+        Ok(client) => client,
+        Err(_) => {
+            return HttpResponse::InternalServerError().body("Failed to connect to Ark server");
+        }
+    };
+
+    let mut rounds = HashMap::new();
+    for (vtxo, _) in &in_round {
+        if rounds.contains_key(&vtxo.round_txid) {
+            continue;
+        }
+        match grpc_client.get_round(vtxo.round_txid.to_string()).await {
+            Ok(Some(round)) => {
+                rounds.insert(vtxo.round_txid, round);
+            }
+            Ok(None) => {
+                return HttpResponse::InternalServerError()
+                    .body(format!("Round {} not found on the Ark server", vtxo.round_txid));
+            }
+            Err(e) => {
+                data.ark_client.check(&e).await;
+                return HttpResponse::InternalServerError()
+                    .body(format!("Failed to fetch round {}: {}", vtxo.round_txid, e));
+            }
+        }
+    }
+
+    let provenances = in_round
+        .iter()
+        .map(|(vtxo, _)| VtxoProvenance::new(vtxo.outpoint, vtxo.round_txid))
+        .collect::<Vec<_>>();
+    let txs = match prepare_vtxo_tree_transactions(&provenances, rounds) {
+        Ok(txs) => txs,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to build exit transactions: {}", e));
+        }
+    };
+
+    // Parents come before their children, so each transaction is broadcast after its inputs.
+    let mut broadcast_txids = Vec::new();
+    let mut published_txids = Vec::new();
+    let mut confirmed_at = HashMap::<Txid, u32>::new();
+    for tx in &txs {
+        let txid = tx.compute_txid();
+        match esplora_client.tx_status(&txid).await {
+            Ok(Some(status)) => {
+                if let Some(height) = status.block_height {
+                    confirmed_at.insert(txid, height);
+                }
+                published_txids.push(txid.to_string());
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return HttpResponse::BadGateway()
+                    .body(format!("Failed to look up exit transaction {}: {}", txid, e));
+            }
+        }
+
+        let error = match esplora_client.broadcast(tx).await {
+            Ok(txid) => {
+                tracing::info!(wallet_id = %wallet_info.id, %txid, "Broadcast exit transaction");
+                broadcast_txids.push(txid.to_string());
+                continue;
+            }
+            Err(BroadcastError::Rejected { message, .. }) => message,
+            Err(BroadcastError::Unavailable(e)) => e.to_string(),
+        };
+        tracing::warn!(wallet_id = %wallet_info.id, %txid, %error, "Exit transaction not broadcast");
+        return HttpResponse::BadGateway().body(format!(
+            "Failed to broadcast exit transaction {} after broadcasting {:?}: {}",
+            txid, broadcast_txids, error
+        ));
+    }
+
+    let tip = match esplora_client.chain_tip().await {
+        Ok(tip) => tip.height,
+        Err(e) => {
+            return HttpResponse::BadGateway().body(format!("Failed to fetch chain tip: {}", e));
+        }
+    };
+
+    let vtxos = in_round
+        .iter()
+        .map(|(vtxo_outpoint, vtxo)| {
+            // A leaf that is not confirmed yet can be at the earliest in the next block.
+            let leaf_height =
+                confirmed_at.get(&vtxo_outpoint.outpoint.txid).copied().unwrap_or(tip + 1);
+            ExitingVtxo {
+                outpoint: vtxo_outpoint.outpoint.to_string(),
+                amount: vtxo_outpoint.amount.to_sat(),
+                amount_btc: btc(vtxo_outpoint.amount.to_sat()),
+                spendable_at_height: spendable_height(leaf_height, vtxo.exit_delay()),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if !broadcast_txids.is_empty() {
+        data.events.record(
+            &wallet_info.id,
+            WalletEventKind::UnilateralExitBroadcast {
+                txids: broadcast_txids.clone(),
+            },
+        );
+    }
+
+    HttpResponse::Ok().json(UnilateralExitResponse {
+        wallet_id: wallet_info.id,
+        earliest_spendable_height: vtxos.iter().map(|vtxo| vtxo.spendable_at_height).min(),
+        broadcast_txids,
+        published_txids,
+        vtxos,
+        skipped_vtxos: out_of_round
+            .iter()
+            .map(|(vtxo, _)| vtxo.outpoint.to_string())
+            .collect(),
+    })
+}
+
+/// The height from which an output confirmed at `confirmed_at` can be spent through a
+/// `exit_delay` CSV path. A delay in seconds is estimated at one block every 10 minutes.
+fn spendable_height(confirmed_at: u32, exit_delay: Sequence) -> u32 {
+    let blocks = match exit_delay.to_relative_lock_time() {
+        Some(LockTime::Blocks(height)) => u32::from(height.value()),
+        Some(LockTime::Time(time)) => {
+            (u64::from(time.value()) * 512).div_ceil(BLOCK_INTERVAL_SECS) as u32
+        }
+        None => 0,
+    };
+    confirmed_at.saturating_add(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_delays_are_counted_in_blocks_from_the_confirmation() {
+        assert_eq!(spendable_height(100, Sequence::from_height(144)), 244);
+        // 1024 seconds are a little under two blocks.
+        assert_eq!(spendable_height(100, Sequence::from_512_second_intervals(2)), 102);
+        assert_eq!(spendable_height(100, Sequence::from_512_second_intervals(1)), 101);
+    }
+}
@@ -0,0 +1,94 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::types::*;
+
+/// How long each dependency gets to answer a readiness probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyStatus {
+    Ok,
+    Down,
+}
+
+#[derive(Serialize)]
+pub struct HealthResponse {
+    pub status: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct ReadyResponse {
+    pub ready: bool,
+    pub ark: DependencyStatus,
+    pub esplora: DependencyStatus,
+}
+
+/// Liveness probe. It touches no dependency, so it answers as long as the server is running.
+#[get("/health")]
+pub async fn health() -> impl Responder {
+    HttpResponse::Ok().json(HealthResponse { status: "ok" })
+}
+
+/// Report whether both the Ark server and Esplora answer right now.
+///
+/// Unlike the state cached at startup, this issues a live request to each dependency over the
+/// shared connections, so a server that went away after we connected is reported as down.
+#[get("/ready")]
+pub async fn ready(data: web::Data<AppState>) -> impl Responder {
+    let (ark, esplora) = futures::join!(probe_ark(&data), probe_esplora(&data));
+
+    let response = ReadyResponse {
+        ready: ark == DependencyStatus::Ok && esplora == DependencyStatus::Ok,
+        ark,
+        esplora,
+    };
+
+    if response.ready {
+        HttpResponse::Ok().json(response)
+    } else {
+        HttpResponse::ServiceUnavailable().json(response)
+    }
+}
+
+async fn probe_ark(data: &AppState) -> DependencyStatus {
+    let probe = async {
+        let mut grpc_client = data.ark_client.get().await?;
+        grpc_client.get_info().await?;
+        Ok::<_, ark_grpc::Error>(())
+    };
+
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(Ok(())) => DependencyStatus::Ok,
+        Ok(Err(e)) => {
+            data.ark_client.check(&e).await;
+            tracing::warn!(error = %e, "Ark server readiness probe failed");
+            DependencyStatus::Down
+        }
+        Err(_) => {
+            tracing::warn!("Ark server readiness probe timed out");
+            DependencyStatus::Down
+        }
+    }
+}
+
+async fn probe_esplora(data: &AppState) -> DependencyStatus {
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
+        None => return DependencyStatus::Down,
+    };
+
+    match tokio::time::timeout(PROBE_TIMEOUT, esplora_client.chain_tip()).await {
+        Ok(Ok(_)) => DependencyStatus::Ok,
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Esplora readiness probe failed");
+            DependencyStatus::Down
+        }
+        Err(_) => {
+            tracing::warn!("Esplora readiness probe timed out");
+            DependencyStatus::Down
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use bitcoin::hashes::{sha256, Hash};
+use serde::Serialize;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::types::AppState;
+
+/// Header carrying the client's key for a spend, so that retrying the request cannot spend twice.
+pub const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Set on a response that was stored for an earlier request with the same key.
+pub const IDEMPOTENT_REPLAYED_HEADER: HeaderName =
+    HeaderName::from_static("idempotent-replayed");
+
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+tokio::task_local! {
+    /// Whether the request being handled has handed a spend to the Ark server.
+    static SUBMITTED: Rc<Cell<bool>>;
+}
+
+/// Note that the request being handled is about to hand a spend to the Ark server.
+///
+/// From then on its outcome is kept for its `Idempotency-Key` even if it fails or the client
+/// goes away, since the spend may have gone through. Does nothing outside [`idempotency`].
+pub fn mark_submitted() {
+    let _ = SUBMITTED.try_with(|submitted| submitted.set(true));
+}
+
+/// A refused `Idempotency-Key`, with a `code` of `INVALID_IDEMPOTENCY_KEY`,
+/// `IDEMPOTENCY_KEY_IN_USE`, `IDEMPOTENCY_OUTCOME_UNKNOWN` or `IDEMPOTENCY_KEY_REUSED`.
+#[derive(Serialize)]
+pub struct IdempotencyErrorResponse {
+    pub code: &'static str,
+    pub error: String,
+}
+
+/// Responses to spend requests, by endpoint and `Idempotency-Key`, kept for `ttl`.
+pub struct IdempotencyKeys {
+    ttl: Duration,
+    entries: Mutex<HashMap<(String, String), Entry>>,
+}
+
+struct Entry {
+    /// Hash of the request body, so that a key cannot be reused for a different spend.
+    fingerprint: sha256::Hash,
+    created_at: Instant,
+    state: EntryState,
+}
+
+enum EntryState {
+    /// The request is still being handled.
+    InFlight,
+    /// The request was dropped after submitting its spend, so whether it went through is unknown.
+    Abandoned,
+    Finished(StoredResponse),
+}
+
+#[derive(Clone, Debug)]
+struct StoredResponse {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: web::Bytes,
+}
+
+#[derive(Debug)]
+enum Lookup {
+    /// First request with the key; it is now marked as in flight.
+    New,
+    InFlight,
+    Abandoned,
+    Replay(StoredResponse),
+    /// The key was used before with a different request body.
+    Mismatch,
+}
+
+impl IdempotencyKeys {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn begin(&self, scope: &(String, String), fingerprint: sha256::Hash) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.created_at.elapsed() < self.ttl);
+
+        match entries.get(scope) {
+            Some(entry) if entry.fingerprint != fingerprint => Lookup::Mismatch,
+            Some(entry) => match &entry.state {
+                EntryState::InFlight => Lookup::InFlight,
+                EntryState::Abandoned => Lookup::Abandoned,
+                EntryState::Finished(response) => Lookup::Replay(response.clone()),
+            },
+            None => {
+                entries.insert(
+                    scope.clone(),
+                    Entry {
+                        fingerprint,
+                        created_at: Instant::now(),
+                        state: EntryState::InFlight,
+                    },
+                );
+                Lookup::New
+            }
+        }
+    }
+
+    /// Store the response to the request in flight under `scope`, or forget the key so that the
+    /// request can be retried.
+    fn finish(&self, scope: &(String, String), response: Option<StoredResponse>) {
+        let mut entries = self.entries.lock().unwrap();
+        match response {
+            Some(response) => {
+                if let Some(entry) = entries.get_mut(scope) {
+                    entry.state = EntryState::Finished(response);
+                }
+            }
+            None => {
+                entries.remove(scope);
+            }
+        }
+    }
+
+    /// Keep the key of a request under `scope` that was dropped after submitting its spend, so
+    /// that retrying it cannot spend again.
+    fn abandon(&self, scope: &(String, String)) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(scope) {
+            entry.state = EntryState::Abandoned;
+        }
+    }
+}
+
+/// Settles the key of a request that never finished, e.g. because the client went away: it is
+/// forgotten if nothing was submitted yet, and kept as abandoned otherwise.
+struct InFlight<'a> {
+    keys: &'a IdempotencyKeys,
+    scope: (String, String),
+    submitted: Rc<Cell<bool>>,
+    finished: bool,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if self.submitted.get() {
+            self.keys.abandon(&self.scope);
+        } else {
+            self.keys.finish(&self.scope, None);
+        }
+    }
+}
+
+/// Answer a repeated spend request carrying the same `Idempotency-Key` with the response to the
+/// first one instead of spending again.
+///
+/// Keys are scoped to the endpoint and expire after `idempotency_key_ttl_secs`. Requests without
+/// the header are handled as usual. A `409 Conflict` is not stored, since the spend did not start
+/// and retrying it with the same key should be possible; neither is a server error returned
+/// before the spend was submitted (see [`mark_submitted`]). A request dropped after submitting
+/// leaves its key abandoned, and retries are refused with `409 Conflict` until it expires.
+pub async fn idempotency(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let Some(key) = req.headers().get(&IDEMPOTENCY_KEY_HEADER).cloned() else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => key.to_string(),
+        _ => {
+            return Ok(req.into_response(idempotency_error(
+                StatusCode::BAD_REQUEST,
+                "INVALID_IDEMPOTENCY_KEY",
+                format!(
+                    "Idempotency-Key must be 1 to {} printable ASCII characters",
+                    MAX_IDEMPOTENCY_KEY_LEN
+                ),
+            )));
+        }
+    };
+    let Some(data) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+
+    let body = req.extract::<web::Bytes>().await?;
+    let fingerprint = sha256::Hash::hash(&body);
+    req.set_payload(Payload::from(body));
+
+    let scope = (req.path().to_string(), key);
+    match data.idempotency_keys.begin(&scope, fingerprint) {
+        Lookup::New => {}
+        Lookup::InFlight => {
+            return Ok(req.into_response(idempotency_error(
+                StatusCode::CONFLICT,
+                "IDEMPOTENCY_KEY_IN_USE",
+                "A request with this Idempotency-Key is still being handled".to_string(),
+            )));
+        }
+        Lookup::Abandoned => {
+            return Ok(req.into_response(idempotency_error(
+                StatusCode::CONFLICT,
+                "IDEMPOTENCY_OUTCOME_UNKNOWN",
+                "A request with this Idempotency-Key was interrupted after submitting its spend; \
+                 check the wallet's history before sending again with a new key"
+                    .to_string(),
+            )));
+        }
+        Lookup::Replay(stored) => {
+            let mut response = HttpResponse::build(stored.status);
+            if let Some(content_type) = stored.content_type {
+                response.insert_header((CONTENT_TYPE, content_type));
+            }
+            response.insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"));
+            return Ok(req.into_response(response.body(stored.body)));
+        }
+        Lookup::Mismatch => {
+            return Ok(req.into_response(idempotency_error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "IDEMPOTENCY_KEY_REUSED",
+                "This Idempotency-Key was already used for a different request".to_string(),
+            )));
+        }
+    }
+
+    let submitted = Rc::new(Cell::new(false));
+    let mut in_flight = InFlight {
+        keys: &data.idempotency_keys,
+        scope,
+        submitted: submitted.clone(),
+        finished: false,
+    };
+
+    let response = SUBMITTED.scope(submitted.clone(), next.call(req)).await?;
+    let (http_req, response) = response.into_parts();
+    let (response, body) = response.into_parts();
+    let body = actix_web::body::to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into()))?;
+
+    let status = response.status();
+    let retryable =
+        status == StatusCode::CONFLICT || (status.is_server_error() && !submitted.get());
+    let stored = (!retryable).then(|| StoredResponse {
+        status,
+        content_type: response.headers().get(CONTENT_TYPE).cloned(),
+        body: body.clone(),
+    });
+    data.idempotency_keys.finish(&in_flight.scope, stored);
+    in_flight.finished = true;
+
+    Ok(ServiceResponse::new(http_req, response.set_body(BoxBody::new(body))))
+}
+
+fn idempotency_error(status: StatusCode, code: &'static str, error: String) -> HttpResponse {
+    HttpResponse::build(status).json(IdempotencyErrorResponse { code, error })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn repeated_keys_replay_the_first_response() {
+        let data = web::Data::new(testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1"));
+        let sends = Arc::new(AtomicUsize::new(0));
+        let counter = sends.clone();
+        let app = test::init_service(App::new().app_data(data).service(
+            web::resource("/send").wrap(from_fn(idempotency)).route(web::post().to(
+                move |body: web::Bytes| {
+                    let send = counter.fetch_add(1, Ordering::SeqCst);
+                    async move { HttpResponse::Ok().body(format!("send {} of {:?}", send, body)) }
+                },
+            )),
+        ))
+        .await;
+
+        let send = |key: Option<&str>, body: &'static str| {
+            let mut request = test::TestRequest::post().uri("/send").set_payload(body);
+            if let Some(key) = key {
+                request = request.insert_header((IDEMPOTENCY_KEY_HEADER, key));
+            }
+            test::call_service(&app, request.to_request())
+        };
+
+        let first = send(Some("k1"), "pay 1000").await;
+        assert!(first.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        let first = test::read_body(first).await;
+
+        let retry = send(Some("k1"), "pay 1000").await;
+        assert_eq!(retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(), "true");
+        assert_eq!(test::read_body(retry).await, first);
+        assert_eq!(sends.load(Ordering::SeqCst), 1);
+
+        let reused = send(Some("k1"), "pay 2000").await;
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        send(Some("k2"), "pay 1000").await;
+        send(None, "pay 1000").await;
+        assert_eq!(sends.load(Ordering::SeqCst), 3);
+    }
+
+    #[actix_web::test]
+    async fn server_errors_are_only_replayed_once_the_spend_was_submitted() {
+        let data = web::Data::new(testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1"));
+        let app = test::init_service(App::new().app_data(data).service(
+            web::resource("/send").wrap(from_fn(idempotency)).route(web::post().to(
+                |body: web::Bytes| async move {
+                    if body.as_ref() == b"submit" {
+                        mark_submitted();
+                    }
+                    HttpResponse::InternalServerError().finish()
+                },
+            )),
+        ))
+        .await;
+
+        let send = |key: &'static str, body: &'static str| {
+            let request = test::TestRequest::post()
+                .uri("/send")
+                .insert_header((IDEMPOTENCY_KEY_HEADER, key))
+                .set_payload(body);
+            test::call_service(&app, request.to_request())
+        };
+
+        send("k1", "fail early").await;
+        let retry = send("k1", "fail early").await;
+        assert!(retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+
+        send("k2", "submit").await;
+        let retry = send("k2", "submit").await;
+        assert_eq!(retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(), "true");
+    }
+
+    #[actix_web::test]
+    async fn keys_of_requests_dropped_after_submitting_are_kept() {
+        let keys = IdempotencyKeys::new(Duration::from_secs(60));
+        let fingerprint = sha256::Hash::hash(b"pay 1000");
+        let in_flight = |scope: &(String, String), submitted: bool| {
+            assert!(matches!(keys.begin(scope, fingerprint), Lookup::New));
+            InFlight {
+                keys: &keys,
+                scope: scope.clone(),
+                submitted: Rc::new(Cell::new(submitted)),
+                finished: false,
+            }
+        };
+
+        let scope = ("/send".to_string(), "k1".to_string());
+        drop(in_flight(&scope, false));
+        drop(in_flight(&scope, true));
+        assert!(matches!(keys.begin(&scope, fingerprint), Lookup::Abandoned));
+    }
+}
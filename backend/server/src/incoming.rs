@@ -0,0 +1,304 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{get, web, HttpResponse, Responder};
+use bitcoin::OutPoint;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::MissedTickBehavior;
+
+use crate::types::*;
+use crate::wallet::{find_wallet, wallet_outpoints, WalletOutpoints};
+
+/// Payments buffered per wallet for subscribers that fall behind; older ones are skipped.
+const INCOMING_CHANNEL_CAPACITY: usize = 64;
+
+/// How often an idle stream sends a comment, so that proxies do not close it.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A VTXO or boarding output that appeared in a wallet.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename = "incoming")]
+pub struct IncomingPayment {
+    pub outpoint: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    /// `vtxo` or `boarding`.
+    pub source: &'static str,
+}
+
+/// One poller per wallet with subscribers to `GET /events/{wallet_id}`.
+#[derive(Default)]
+pub struct IncomingWatchers {
+    watchers: Mutex<HashMap<String, broadcast::Sender<IncomingPayment>>>,
+}
+
+impl IncomingWatchers {
+    /// Subscribe to the payments arriving at `wallet_id`, starting its poller if it has none.
+    fn subscribe(
+        &self,
+        data: &web::Data<AppState>,
+        wallet_id: &str,
+    ) -> broadcast::Receiver<IncomingPayment> {
+        let mut watchers = self.watchers.lock().unwrap();
+        if let Some(sender) = watchers.get(wallet_id) {
+            return sender.subscribe();
+        }
+
+        let (sender, receiver) = broadcast::channel(INCOMING_CHANNEL_CAPACITY);
+        watchers.insert(wallet_id.to_string(), sender.clone());
+        data.background
+            .spawn(watch_wallet(data.clone(), wallet_id.to_string(), sender));
+
+        receiver
+    }
+
+    /// Remove the poller of `wallet_id` if its last subscriber went away. Checked under the lock
+    /// that [`Self::subscribe`] takes, so that no new subscriber is left without a poller.
+    fn release_if_unused(&self, wallet_id: &str) -> bool {
+        let mut watchers = self.watchers.lock().unwrap();
+        match watchers.get(wallet_id) {
+            Some(sender) if sender.receiver_count() == 0 => {
+                watchers.remove(wallet_id);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Stream the payments arriving at a wallet as server-sent events, one
+/// `{"type":"incoming",...}` message per new VTXO or boarding output.
+///
+/// Outputs the wallet already had when the stream opened are not reported, nor are the change and
+/// settlement outputs of the wallet's own sends and settlements.
+#[get("/events/{wallet_id}")]
+pub async fn incoming_events(
+    wallet_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let receiver = data.incoming_watchers.subscribe(&data, &wallet_info.id);
+
+    let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            let message = match tokio::time::timeout(KEEP_ALIVE_INTERVAL, receiver.recv()).await {
+                Ok(Ok(payment)) => match serde_json::to_string(&payment) {
+                    Ok(json) => format!("data: {}\n\n", json),
+                    Err(_) => continue,
+                },
+                Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+                Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+                Err(_) => ": keep-alive\n\n".to_string(),
+            };
+            return Some((Ok::<_, Infallible>(web::Bytes::from(message)), receiver));
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(stream)
+}
+
+/// Poll the outputs of `wallet_id` every `incoming_poll_interval_secs` and send the new ones to
+/// the subscribers, until there are none left or the server shuts down.
+async fn watch_wallet(
+    data: web::Data<AppState>,
+    wallet_id: String,
+    sender: broadcast::Sender<IncomingPayment>,
+) {
+    let period = Duration::from_secs(data.config.incoming_poll_interval_secs.max(1));
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut seen: Option<HashSet<OutPoint>> = None;
+
+    loop {
+        tokio::select! {
+            // Dropping the last sender ends the subscribers' streams.
+            _ = data.background.shutdown_started() => {
+                data.incoming_watchers.watchers.lock().unwrap().remove(&wallet_id);
+                return;
+            }
+            _ = interval.tick() => {}
+        }
+
+        if data.incoming_watchers.release_if_unused(&wallet_id) {
+            tracing::debug!(%wallet_id, "Stopped watching for incoming payments");
+            return;
+        }
+
+        let Some(wallet_info) = data.wallets.lock().unwrap().get(&wallet_id).cloned() else {
+            data.incoming_watchers.watchers.lock().unwrap().remove(&wallet_id);
+            return;
+        };
+
+        let outpoints = match wallet_outpoints(&data, &wallet_info).await {
+            Ok(outpoints) => outpoints,
+            Err(response) => {
+                tracing::warn!(
+                    %wallet_id,
+                    status = %response.status(),
+                    "Failed to poll for incoming payments"
+                );
+                continue;
+            }
+        };
+
+        let current = unspent_outputs(&outpoints, &own_txids(&data, &wallet_id));
+        if let Some(seen) = &seen {
+            for (outpoint, payment) in &current {
+                if let Some(payment) = payment
+                    && !seen.contains(outpoint)
+                {
+                    let _ = sender.send(payment.clone());
+                }
+            }
+        }
+        seen = Some(current.into_keys().collect());
+    }
+}
+
+/// Every unspent output of the wallet, with the payment it is reported as. Outputs of the
+/// wallet's own sends and settlements, i.e. of one of `own_txids`, are not payments.
+fn unspent_outputs(
+    outpoints: &WalletOutpoints,
+    own_txids: &HashSet<String>,
+) -> HashMap<OutPoint, Option<IncomingPayment>> {
+    let is_own = |txid: &bitcoin::Txid| own_txids.contains(&txid.to_string());
+
+    let vtxos = outpoints
+        .vtxos
+        .spendable
+        .iter()
+        .chain(&outpoints.vtxos.expired)
+        .map(|(vtxo, _)| {
+            // A VTXO created in a round, rather than by a redeem transaction, comes from the
+            // settlement that made that round.
+            let own = is_own(&vtxo.outpoint.txid)
+                || (vtxo.redeem_tx.is_none() && is_own(&vtxo.round_txid));
+            (vtxo.outpoint, vtxo.amount, "vtxo", own)
+        });
+    let boarding = outpoints
+        .boarding
+        .spendable
+        .iter()
+        .chain(&outpoints.boarding.expired)
+        .chain(&outpoints.boarding.pending)
+        .map(|(outpoint, amount, _)| (*outpoint, *amount, "boarding", is_own(&outpoint.txid)));
+
+    vtxos
+        .chain(boarding)
+        .map(|(outpoint, amount, source, own)| {
+            let payment = (!own).then(|| IncomingPayment {
+                outpoint: outpoint.to_string(),
+                amount: amount.to_sat(),
+                amount_btc: btc(amount.to_sat()),
+                source,
+            });
+            (outpoint, payment)
+        })
+        .collect()
+}
+
+/// The transactions the wallet itself submitted, whose outputs are not incoming payments.
+fn own_txids(data: &AppState, wallet_id: &str) -> HashSet<String> {
+    data.events
+        .list(wallet_id, 0, usize::MAX)
+        .into_iter()
+        .filter_map(|event| match event.kind {
+            WalletEventKind::SendSubmitted { txid, .. }
+            | WalletEventKind::SettlementFinished { txid } => Some(txid),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use crate::wallet::wallet_outputs;
+    use ark_core::server::VtxoOutPoint;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::{Amount, Txid};
+
+    #[test]
+    fn own_outputs_are_not_incoming_payments() {
+        let state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        let owner = SecretKey::from_slice(&[2u8; 32])
+            .unwrap()
+            .x_only_public_key(&Secp256k1::new())
+            .0;
+        let (boarding_output, vtxo) =
+            wallet_outputs(&state.server_info().unwrap(), owner, None).ok().unwrap();
+
+        let txid = |byte: u8| Txid::hash(&[byte]);
+        let vtxo_out = |txid, round_txid, redeemed: bool| VtxoOutPoint {
+            outpoint: OutPoint { txid, vout: 0 },
+            spent: false,
+            round_txid,
+            spent_by: None,
+            expire_at: 0,
+            swept: false,
+            is_pending: false,
+            redeem_tx: redeemed.then(|| {
+                bitcoin::Psbt::from_unsigned_tx(bitcoin::Transaction {
+                    version: bitcoin::transaction::Version::TWO,
+                    lock_time: bitcoin::absolute::LockTime::ZERO,
+                    input: Vec::new(),
+                    output: Vec::new(),
+                })
+                .unwrap()
+            }),
+            amount: Amount::from_sat(1_000),
+            pubkey: String::new(),
+            created_at: 0,
+        };
+        let outpoints = WalletOutpoints {
+            vtxos: VirtualTxOutpoints {
+                spendable: vec![
+                    // Change of our own send.
+                    (vtxo_out(txid(1), txid(9), true), vtxo.clone()),
+                    // Output of our own settlement.
+                    (vtxo_out(txid(2), txid(3), false), vtxo.clone()),
+                    // A payment from a sender who was in the round we settled in.
+                    (vtxo_out(txid(4), txid(3), true), vtxo.clone()),
+                ],
+                expired: Vec::new(),
+            },
+            boarding: BoardingOutpoints {
+                spendable: Vec::new(),
+                expired: Vec::new(),
+                pending: vec![(
+                    OutPoint {
+                        txid: txid(5),
+                        vout: 1,
+                    },
+                    Amount::from_sat(20_000),
+                    boarding_output,
+                )],
+                spent: Vec::new(),
+            },
+            boarding_utxos: Vec::new(),
+        };
+        let own_txids = [txid(1), txid(3)].iter().map(Txid::to_string).collect();
+
+        let outputs = unspent_outputs(&outpoints, &own_txids);
+
+        let payment = |txid: Txid, vout| outputs[&OutPoint { txid, vout }].as_ref();
+        assert!(payment(txid(1), 0).is_none());
+        assert!(payment(txid(2), 0).is_none());
+        assert_eq!(payment(txid(4), 0).unwrap().source, "vtxo");
+        let deposit = payment(txid(5), 1).unwrap();
+        assert_eq!((deposit.source, deposit.amount), ("boarding", 20_000));
+    }
+}
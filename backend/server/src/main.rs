@@ -2,30 +2,89 @@ mod types;
 mod wallet;
 mod transactions;
 mod server;
+mod chain;
+mod esplora;
+mod exit;
+mod cache;
+mod address;
+mod admin;
+mod auto_settle;
+mod background;
+mod ark;
+mod events;
+mod config;
+mod health;
+mod info;
+mod storage;
+mod seed;
+mod signer;
+mod settle_jobs;
+mod settle_ws;
+mod spend_lock;
+mod spend_limits;
+mod unsigned_sends;
+mod message;
+mod request_id;
+mod idempotency;
+mod incoming;
+mod metrics;
+mod rate_limit;
+#[cfg(test)]
+mod testing;
 
+use clap::Parser;
 use std::fs;
 use std::io;
 
+#[derive(Parser)]
+#[command(about = "HTTP API for Ark wallets")]
+struct Cli {
+    /// Print the JSON schema of `ark.config.toml` and exit.
+    #[arg(long)]
+    print_config_schema: bool,
+
+    /// Print a commented example `ark.config.toml` and exit.
+    #[arg(long)]
+    generate_example_config: bool,
+}
+
 fn main() -> io::Result<()> {
-    server::init_tracing();
+    let cli = Cli::parse();
+
+    if cli.print_config_schema {
+        let schema = serde_json::to_string_pretty(&config::config_schema())?;
+        println!("{}", schema);
+        return Ok(());
+    }
+
+    if cli.generate_example_config {
+        print!("{}", config::example_config());
+        return Ok(());
+    }
 
     // Load configuration
     let config = match fs::read_to_string("ark.config.toml") {
-        Ok(content) => match toml::from_str::<types::Config>(&content) {
+        Ok(content) => match config::parse_config(&content) {
             Ok(config) => config,
             Err(e) => {
                 eprintln!("Failed to parse config: {}", e);
-                return Err(io::Error::new(io::ErrorKind::Other, "Config parse error"));
+                return Err(io::Error::other("Config parse error"));
             }
         },
         Err(e) => {
             eprintln!("Failed to read config file: {}", e);
-            return Err(io::Error::new(io::ErrorKind::Other, "Config read error"));
+            return Err(io::Error::other("Config read error"));
         }
     };
 
+    server::init_tracing(&config)?;
+
     // Start the server using tokio runtime
-    tokio::runtime::Builder::new_multi_thread()
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = config.runtime_threads {
+        runtime.worker_threads(threads.get());
+    }
+    runtime
         .enable_all()
         .build()
         .unwrap()
@@ -0,0 +1,187 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{schnorr, Message, Secp256k1};
+use bitcoin::XOnlyPublicKey;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::signer::{InMemorySigner, Signer};
+use crate::types::*;
+use crate::wallet::{find_wallet, signing_key};
+
+/// Tag of the BIP340 tagged hash that signed messages are committed to.
+pub const MESSAGE_TAG: &str = "ARKane/message";
+
+#[derive(Deserialize)]
+pub struct SignMessageRequest {
+    pub wallet_id: String,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct SignMessageResponse {
+    pub wallet_id: String,
+    pub message: String,
+    /// The 64-byte BIP340 Schnorr signature, in hex.
+    pub signature: String,
+    /// The x-only public key that verifies the signature, in hex.
+    pub pubkey: String,
+    pub scheme: MessageScheme,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyMessageRequest {
+    pub pubkey: String,
+    pub message: String,
+    pub signature: String,
+}
+
+#[derive(Serialize)]
+pub struct VerifyMessageResponse {
+    pub valid: bool,
+    pub scheme: MessageScheme,
+}
+
+/// How a message is turned into the digest that gets signed, so that anyone can verify a
+/// signature without this server.
+#[derive(Serialize)]
+pub struct MessageScheme {
+    pub tag: &'static str,
+    pub hash: &'static str,
+    pub signature: &'static str,
+    /// The digest of this particular message, in hex.
+    pub digest: String,
+}
+
+impl MessageScheme {
+    fn for_message(message: &str) -> Self {
+        Self {
+            tag: MESSAGE_TAG,
+            hash: "SHA256(SHA256(tag) || SHA256(tag) || utf8(message))",
+            signature: "BIP340 Schnorr signature of the digest, without auxiliary randomness",
+            digest: hex::encode(message_digest(message)),
+        }
+    }
+}
+
+/// The BIP340 tagged hash of `message` under [`MESSAGE_TAG`].
+fn message_digest(message: &str) -> [u8; 32] {
+    let tag = sha256::Hash::hash(MESSAGE_TAG.as_bytes());
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_byte_array());
+    engine.input(tag.as_byte_array());
+    engine.input(message.as_bytes());
+
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Sign an arbitrary message with the wallet's key, to prove ownership of it.
+#[post("/sign_message")]
+pub async fn sign_message(
+    req: web::Json<SignMessageRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &req.wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let signer = match signing_key(&data, &wallet_info) {
+        Ok(sk) => InMemorySigner::new(&sk),
+        Err(response) => return response,
+    };
+
+    let (signature, pubkey) =
+        signer.sign_schnorr(Message::from_digest(message_digest(&req.message)));
+
+    HttpResponse::Ok().json(SignMessageResponse {
+        wallet_id: wallet_info.id,
+        message: req.message.clone(),
+        signature: signature.to_string(),
+        pubkey: pubkey.to_string(),
+        scheme: MessageScheme::for_message(&req.message),
+    })
+}
+
+/// Check a signature produced by [`sign_message`], or by anyone following the same scheme.
+#[post("/verify_message")]
+pub async fn verify_message(req: web::Json<VerifyMessageRequest>) -> impl Responder {
+    let pubkey = match XOnlyPublicKey::from_str(&req.pubkey) {
+        Ok(pubkey) => pubkey,
+        Err(_) => {
+            return HttpResponse::BadRequest()
+                .body("Invalid public key: expected a 32-byte x-only key in hex");
+        }
+    };
+
+    let signature = match schnorr::Signature::from_str(&req.signature) {
+        Ok(signature) => signature,
+        Err(_) => {
+            return HttpResponse::BadRequest()
+                .body("Invalid signature: expected a 64-byte Schnorr signature in hex");
+        }
+    };
+
+    let valid = Secp256k1::verification_only()
+        .verify_schnorr(&signature, &Message::from_digest(message_digest(&req.message)), &pubkey)
+        .is_ok();
+
+    HttpResponse::Ok().json(VerifyMessageResponse {
+        valid,
+        scheme: MessageScheme::for_message(&req.message),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn signed_messages_verify_only_unchanged() {
+        let data = web::Data::new(testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1"));
+        let sk = testing::insert_wallet(&data, 2, testing::TestKeys::Full);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .service(sign_message)
+                .service(verify_message),
+        )
+        .await;
+
+        let signed: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/sign_message")
+                .set_json(serde_json::json!({ "wallet_id": testing::WALLET_ID, "message": "hello" }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(
+            signed["pubkey"],
+            sk.x_only_public_key(&Secp256k1::new()).0.to_string()
+        );
+
+        let verify = |message: &str| {
+            test::TestRequest::post()
+                .uri("/verify_message")
+                .set_json(serde_json::json!({
+                    "pubkey": signed["pubkey"],
+                    "message": message,
+                    "signature": signed["signature"],
+                }))
+                .to_request()
+        };
+
+        let valid: serde_json::Value = test::call_and_read_body_json(&app, verify("hello")).await;
+        assert_eq!(valid["valid"], true);
+        assert_eq!(valid["scheme"]["digest"], signed["scheme"]["digest"]);
+
+        let tampered: serde_json::Value =
+            test::call_and_read_body_json(&app, verify("hello!")).await;
+        assert_eq!(tampered["valid"], false);
+    }
+}
@@ -0,0 +1,225 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::time::{Duration, Instant};
+
+use crate::admin::bearer_token_matches;
+use crate::types::*;
+use crate::wallet::wallet_totals;
+
+/// How long the balances summed over every wallet are reused between scrapes, since computing
+/// them queries the Ark server and Esplora for each wallet.
+const BALANCE_METRICS_TTL: Duration = Duration::from_secs(60);
+
+/// The Prometheus metrics of the server, exposed at `GET /metrics`.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    /// Failed calls to the Ark server, handed to the [`ArkClient`].
+    pub ark_errors: IntCounter,
+    /// Failed calls to an Esplora backend, handed to the [`EsploraClient`].
+    pub esplora_errors: IntCounter,
+    pub settlements_in_flight: IntGauge,
+    wallets: IntGauge,
+    balance: IntGaugeVec,
+    /// Spendable, reserved, expired and pending sats over every wallet.
+    balances: TtlCache<(), [u64; 4]>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("ark_http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "ark_http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let backend_errors = IntCounterVec::new(
+            Opts::new("ark_backend_errors_total", "Failed calls to the Ark server or Esplora"),
+            &["backend"],
+        )
+        .unwrap();
+        let settlements_in_flight =
+            IntGauge::new("ark_settlements_in_flight", "Settlements taking part in a round")
+                .unwrap();
+        let wallets = IntGauge::new("ark_wallets", "Wallets loaded").unwrap();
+        let balance = IntGaugeVec::new(
+            Opts::new("ark_balance_sats", "Sats held over every wallet, refreshed every minute"),
+            &["state"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_request_duration.clone())).unwrap();
+        registry.register(Box::new(backend_errors.clone())).unwrap();
+        registry.register(Box::new(settlements_in_flight.clone())).unwrap();
+        registry.register(Box::new(wallets.clone())).unwrap();
+        registry.register(Box::new(balance.clone())).unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            ark_errors: backend_errors.with_label_values(&["ark"]),
+            esplora_errors: backend_errors.with_label_values(&["esplora"]),
+            settlements_in_flight,
+            wallets,
+            balance,
+            balances: TtlCache::new("balance_metrics", BALANCE_METRICS_TTL),
+        }
+    }
+}
+
+/// Counts a settlement as in flight until dropped.
+pub struct InFlight(IntGauge);
+
+impl InFlight {
+    pub fn start(gauge: &IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Count every request and time it, by route pattern rather than path so that wallet IDs do not
+/// each get their own series.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let response = next.call(req).await?;
+
+    if let Some(data) = response.request().app_data::<web::Data<AppState>>() {
+        let method = response.request().method().to_string();
+        let route = response
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        let status = response.status().as_u16().to_string();
+
+        let metrics = &data.metrics;
+        metrics
+            .http_requests
+            .with_label_values(&[&method, &route, &status])
+            .inc();
+        metrics
+            .http_request_duration
+            .with_label_values(&[&method, &route])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    Ok(response)
+}
+
+/// The metrics in the Prometheus text format.
+///
+/// Open to anyone unless `metrics_token` is set, since many scrapers cannot send credentials.
+#[get("/metrics")]
+pub async fn get_metrics(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if let Some(expected) = data.config.metrics_token.as_deref()
+        && !bearer_token_matches(&req, expected)
+    {
+        return HttpResponse::Unauthorized().body("Invalid or missing metrics token");
+    }
+
+    let metrics = &data.metrics;
+    metrics.wallets.set(data.wallets.lock().unwrap().len() as i64);
+
+    let balances = match metrics.balances.get(&()) {
+        Some(balances) => balances,
+        None => {
+            let totals = wallet_totals(&data).await;
+            let sum = |amount: fn(&WalletTotal) -> u64| totals.iter().map(amount).sum::<u64>();
+            let balances = [
+                sum(|wallet| wallet.spendable),
+                sum(|wallet| wallet.reserved),
+                sum(|wallet| wallet.expired),
+                sum(|wallet| wallet.pending),
+            ];
+            metrics.balances.insert((), balances);
+            balances
+        }
+    };
+    for (state, sats) in ["spendable", "reserved", "expired", "pending"].iter().zip(balances) {
+        metrics
+            .balance
+            .with_label_values(&[state])
+            .set(i64::try_from(sats).unwrap_or(i64::MAX));
+    }
+
+    let mut body = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&metrics.registry.gather(), &mut body) {
+        return HttpResponse::InternalServerError()
+            .body(format!("Failed to encode metrics: {}", e));
+    }
+
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn requests_are_counted_by_route() {
+        let mut state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        state.config.metrics_token = Some("scraper".to_string());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .wrap(from_fn(track_requests))
+                .service(get_metrics)
+                .service(crate::wallet::get_balance),
+        )
+        .await;
+
+        let balance_uri = format!("/get_balance/{}", testing::WALLET_ID);
+        test::call_service(&app, test::TestRequest::get().uri(&balance_uri).to_request()).await;
+
+        let unauthorized =
+            test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(unauthorized.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+
+        let body = test::call_and_read_body(
+            &app,
+            test::TestRequest::get()
+                .uri("/metrics")
+                .insert_header(("Authorization", "Bearer scraper"))
+                .to_request(),
+        )
+        .await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        let counted = r#"ark_http_requests_total{method="GET",route="/get_balance/{wallet_id}","#;
+        assert!(body.contains(&format!(r#"{}status="404"}} 1"#, counted)), "{}", body);
+        assert!(body.contains("ark_wallets 0"));
+        assert!(body.contains(r#"ark_balance_sats{state="spendable"} 0"#));
+    }
+}
@@ -0,0 +1,208 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::types::{AppState, RateLimit, RateLimitSettings};
+
+/// How often buckets that have refilled completely are dropped, so that clients that went away
+/// do not keep using memory.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A request refused because its client sent too many, with a `code` of `RATE_LIMITED`.
+#[derive(Serialize)]
+pub struct RateLimitedResponse {
+    pub code: &'static str,
+    pub error: String,
+    pub retry_after_secs: u64,
+}
+
+/// Token buckets per client IP: one for every route with an override in `routes`, and one shared
+/// by all other routes.
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    state: Mutex<Buckets>,
+}
+
+struct Buckets {
+    /// By client IP and the route with an override, or `None` for the shared bucket.
+    buckets: HashMap<(IpAddr, Option<String>), Bucket>,
+    last_pruned: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    /// Add the tokens earned since the last update, up to `limit.burst`.
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let earned = now.duration_since(self.updated_at).as_secs_f64() * limit.requests_per_second;
+        self.tokens = (self.tokens + earned).min(f64::from(limit.burst));
+        self.updated_at = now;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings,
+            state: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token for a request from `ip` to `route`, or return how long to wait for one.
+    fn acquire(&self, ip: IpAddr, route: Option<&str>, now: Instant) -> Result<(), Duration> {
+        let (route, limit) = match route.and_then(|route| self.settings.routes.get_key_value(route))
+        {
+            Some((route, limit)) => (Some(route.clone()), limit),
+            None => (None, &self.settings.default),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if now.duration_since(state.last_pruned) >= PRUNE_INTERVAL {
+            self.prune(&mut state.buckets, now);
+            state.last_pruned = now;
+        }
+
+        let bucket = state.buckets.entry((ip, route)).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            updated_at: now,
+        });
+        bucket.refill(limit, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let missing = 1.0 - bucket.tokens;
+        Err(Duration::from_secs_f64(missing / limit.requests_per_second))
+    }
+
+    fn prune(&self, buckets: &mut HashMap<(IpAddr, Option<String>), Bucket>, now: Instant) {
+        buckets.retain(|(_, route), bucket| {
+            let limit = route
+                .as_ref()
+                .and_then(|route| self.settings.routes.get(route))
+                .unwrap_or(&self.settings.default);
+            bucket.refill(limit, now);
+            bucket.tokens < f64::from(limit.burst)
+        });
+    }
+}
+
+/// Refuse requests from a client IP that is over its `rate_limit`, with `429 Too Many Requests`
+/// and a `Retry-After` header.
+///
+/// Routes are matched by their pattern, e.g. `/get_balance/{wallet_id}`. The client IP is the
+/// peer address, or the `Forwarded`/`X-Forwarded-For` one if `trust_forwarded_headers` is set.
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let Some(data) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+    let settings = &data.config.rate_limit;
+    if !settings.enabled {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    }
+
+    let ip = match settings.trust_forwarded_headers {
+        true => req
+            .connection_info()
+            .realip_remote_addr()
+            .and_then(|addr| addr.parse::<IpAddr>().ok()),
+        false => None,
+    }
+    .or_else(|| req.peer_addr().map(|addr| addr.ip()));
+    let Some(ip) = ip else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+
+    let route = req.match_pattern();
+    if let Err(wait) = data.rate_limiter.acquire(ip, route.as_deref(), Instant::now()) {
+        let retry_after_secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+        tracing::debug!(%ip, route = route.as_deref(), retry_after_secs, "Rate limited");
+
+        let response = HttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, retry_after_secs.to_string()))
+            .json(RateLimitedResponse {
+                code: "RATE_LIMITED",
+                error: "Too many requests from this client".to_string(),
+                retry_after_secs,
+            });
+        return Ok(req.into_response(response));
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn clients_over_their_limit_are_told_when_to_retry() {
+        let mut state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        state.config.rate_limit.enabled = true;
+        state.config.rate_limit.default = RateLimit {
+            requests_per_second: 1.0,
+            burst: 3,
+        };
+        state.config.rate_limit.routes = [(
+            "/create_wallet".to_string(),
+            RateLimit {
+                requests_per_second: 0.1,
+                burst: 1,
+            },
+        )]
+        .into();
+        state.rate_limiter = RateLimiter::new(state.config.rate_limit.clone());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .wrap(from_fn(rate_limit))
+                .route("/create_wallet", web::post().to(HttpResponse::Ok))
+                .route("/get_balance/{wallet_id}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let request = |ip: &str, create: bool| {
+            let request = match create {
+                true => test::TestRequest::post().uri("/create_wallet"),
+                false => test::TestRequest::get().uri(&format!("/get_balance/{}", ip)),
+            };
+            let addr = format!("{}:40000", ip).parse().unwrap();
+            test::call_service(&app, request.peer_addr(addr).to_request())
+        };
+
+        assert_eq!(request("10.0.0.1", true).await.status(), StatusCode::OK);
+        let refused = request("10.0.0.1", true).await;
+        assert_eq!(refused.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(refused.headers().get(RETRY_AFTER).unwrap(), "10");
+
+        // Other routes and other clients have their own buckets.
+        for _ in 0..3 {
+            assert_eq!(request("10.0.0.1", false).await.status(), StatusCode::OK);
+        }
+        assert_eq!(request("10.0.0.1", false).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(request("10.0.0.2", true).await.status(), StatusCode::OK);
+    }
+
+}
@@ -0,0 +1,99 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use tracing::Instrument;
+
+/// Header carrying the correlation ID of a request, both in the request and in the response.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-provided request ID that is kept rather than replaced by a fresh one.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Run every request in a tracing span carrying its correlation ID, and echo the ID back in the
+/// `X-Request-Id` response header.
+///
+/// The ID is taken from the request's `X-Request-Id` header if it is short and printable, so that
+/// a client or proxy can tie its own logs to ours; otherwise a random one is generated. Handlers
+/// fill in the span's `wallet_id` with [`record_wallet_id`] once they know it.
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+        wallet_id = tracing::field::Empty,
+    );
+
+    let mut response = next.call(req).instrument(span.clone()).await?;
+
+    let _entered = span.enter();
+    tracing::debug!(status = response.status().as_u16(), "Request finished");
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    Ok(response)
+}
+
+/// Attach `wallet_id` to the span of the request being handled.
+pub fn record_wallet_id(wallet_id: &str) {
+    tracing::Span::current().record("wallet_id", wallet_id);
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[actix_web::test]
+    async fn request_ids_are_echoed_or_generated() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(request_id))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/")
+                .insert_header((REQUEST_ID_HEADER, "client-42"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.headers().get(REQUEST_ID_HEADER).unwrap(), "client-42");
+
+        for header in [None, Some("has spaces"), Some(&*"x".repeat(65))] {
+            let mut request = test::TestRequest::get().uri("/");
+            if let Some(header) = header {
+                request = request.insert_header((REQUEST_ID_HEADER, header));
+            }
+            let response = test::call_service(&app, request.to_request()).await;
+
+            let generated = response.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap();
+            assert!(uuid::Uuid::parse_str(generated).is_ok(), "{:?}", header);
+        }
+    }
+}
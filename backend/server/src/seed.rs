@@ -0,0 +1,177 @@
+use anyhow::{anyhow, Context};
+use argon2::Argon2;
+use bip39::Mnemonic;
+use bitcoin::bip32::Xpriv;
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::Network;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Version of the [`EncryptedSeed`] envelope written by this build.
+const ENVELOPE_VERSION: u8 = 1;
+
+/// A wallet's secret key, stored either directly or as a BIP39 mnemonic, in plain text or
+/// encrypted under the configured passphrase.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum WalletSeed {
+    Encrypted(EncryptedSeed),
+    Mnemonic { mnemonic: String },
+    Plain(String),
+}
+
+/// A secret encrypted with ChaCha20-Poly1305 under a key derived from a passphrase with Argon2id.
+/// All byte fields are hex encoded.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EncryptedSeed {
+    version: u8,
+    #[serde(default)]
+    kind: SeedKind,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// What the ciphertext of an [`EncryptedSeed`] holds.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SeedKind {
+    /// The 32 bytes of the secret key.
+    #[default]
+    SecretKey,
+    /// The mnemonic phrase as UTF-8.
+    Mnemonic,
+}
+
+impl WalletSeed {
+    /// Store `sk`, encrypting it if a passphrase is given.
+    pub fn new(sk: &SecretKey, passphrase: Option<&str>) -> Result<Self, anyhow::Error> {
+        match passphrase {
+            Some(passphrase) => seal(&sk.secret_bytes(), SeedKind::SecretKey, passphrase),
+            None => Ok(Self::Plain(sk.display_secret().to_string())),
+        }
+    }
+
+    /// Store `mnemonic`, encrypting it if a passphrase is given.
+    pub fn from_mnemonic(
+        mnemonic: &Mnemonic,
+        passphrase: Option<&str>,
+    ) -> Result<Self, anyhow::Error> {
+        let phrase = mnemonic.to_string();
+        match passphrase {
+            Some(passphrase) => seal(phrase.as_bytes(), SeedKind::Mnemonic, passphrase),
+            None => Ok(Self::Mnemonic { mnemonic: phrase }),
+        }
+    }
+
+    pub fn secret_key(&self, passphrase: Option<&str>) -> Result<SecretKey, anyhow::Error> {
+        let envelope = match self {
+            Self::Plain(seed) => return Ok(SecretKey::from_str(seed)?),
+            Self::Mnemonic { mnemonic } => {
+                return mnemonic_secret_key(&Mnemonic::from_str(mnemonic)?);
+            }
+            Self::Encrypted(envelope) => envelope,
+        };
+
+        if envelope.version != ENVELOPE_VERSION {
+            return Err(anyhow!("unsupported seed envelope version {}", envelope.version));
+        }
+
+        let passphrase = passphrase.context("seed is encrypted but no passphrase is configured")?;
+
+        let salt = hex::decode(&envelope.salt)?;
+        let nonce = hex::decode(&envelope.nonce)?;
+        let ciphertext = hex::decode(&envelope.ciphertext)?;
+        if nonce.len() != 12 {
+            return Err(anyhow!("invalid seed nonce length"));
+        }
+
+        let plaintext = cipher(passphrase, &salt)?
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| anyhow!("failed to decrypt seed; wrong passphrase?"))?;
+
+        match envelope.kind {
+            SeedKind::SecretKey => Ok(SecretKey::from_slice(&plaintext)?),
+            SeedKind::Mnemonic => {
+                let phrase = String::from_utf8(plaintext)?;
+                mnemonic_secret_key(&Mnemonic::from_str(&phrase)?)
+            }
+        }
+    }
+}
+
+/// The wallet key behind a mnemonic: the BIP32 master key of its seed, without a BIP39
+/// passphrase. The network passed to BIP32 only affects serialization, not the key itself.
+pub fn mnemonic_secret_key(mnemonic: &Mnemonic) -> Result<SecretKey, anyhow::Error> {
+    let seed = mnemonic.to_seed("");
+    let master = Xpriv::new_master(Network::Bitcoin, &seed)?;
+
+    Ok(master.private_key)
+}
+
+fn seal(plaintext: &[u8], kind: SeedKind, passphrase: &str) -> Result<WalletSeed, anyhow::Error> {
+    let mut rng = rand::thread_rng();
+    let mut salt = [0u8; 16];
+    rng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; 12];
+    rng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow!("failed to encrypt seed"))?;
+
+    Ok(WalletSeed::Encrypted(EncryptedSeed {
+        version: ENVELOPE_VERSION,
+        kind,
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    }))
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, anyhow::Error> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("failed to derive seed key: {}", e))?;
+
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_seed_roundtrip() {
+        let sk = SecretKey::from_slice(&[7u8; 32]).unwrap();
+
+        let seed = WalletSeed::new(&sk, Some("hunter2")).unwrap();
+        let json = serde_json::to_string(&seed).unwrap();
+        assert!(!json.contains(&sk.display_secret().to_string()));
+
+        let seed = serde_json::from_str::<WalletSeed>(&json).unwrap();
+        assert_eq!(seed.secret_key(Some("hunter2")).unwrap(), sk);
+        assert!(seed.secret_key(Some("wrong")).is_err());
+        assert!(seed.secret_key(None).is_err());
+    }
+
+    #[test]
+    fn encrypted_mnemonic_roundtrip() {
+        let mnemonic = Mnemonic::from_entropy(&[1u8; 16]).unwrap();
+        let sk = mnemonic_secret_key(&mnemonic).unwrap();
+
+        let plain = WalletSeed::from_mnemonic(&mnemonic, None).unwrap();
+        assert_eq!(plain.secret_key(None).unwrap(), sk);
+
+        let encrypted = WalletSeed::from_mnemonic(&mnemonic, Some("hunter2")).unwrap();
+        let json = serde_json::to_string(&encrypted).unwrap();
+        assert!(!json.contains(&mnemonic.to_string()));
+
+        let encrypted = serde_json::from_str::<WalletSeed>(&json).unwrap();
+        assert_eq!(encrypted.secret_key(Some("hunter2")).unwrap(), sk);
+    }
+}
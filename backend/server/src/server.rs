@@ -1,85 +1,840 @@
 use actix_cors::Cors;
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, Uri};
+use actix_web::middleware::{from_fn, DefaultHeaders};
+use actix_web::dev::ServerHandle;
 use actix_web::{App, HttpServer, web};
 use anyhow::Result;
-use std::collections::HashMap;
+use bitcoin::{Amount, Network};
 use std::fs;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tracing_subscriber::EnvFilter;
 
-use crate::transactions::{faucet, send_to_ark_address, settle_funds};
-use crate::types::{AppState, Config, EsploraClient};
-use crate::wallet::{create_wallet, get_address, get_balance};
+use crate::address::{canonical_address, validate_address};
+use crate::admin::{cache_flush, cache_stats};
+use crate::auto_settle::run_auto_settle;
+use crate::chain::{broadcast, chain_status, get_fee_rates, tx_status, CHAIN_TIP_TTL};
+use crate::exit::unilateral_exit;
+use crate::transactions::{
+    build_unsigned_send, cancel_redeem, consolidate_vtxos, estimate_fee, faucet,
+    refresh_vtxos, send_batch, send_max, send_onchain, send_to_ark_address, settle_funds,
+    submit_signed_psbt,
+};
+use crate::events::wallet_events;
+use crate::health::{health, ready};
+use crate::settle_jobs::{settle_cancel, settle_status};
+use crate::settle_ws::settle_ws;
+use crate::info::{get_server_info, get_version, wallet_descriptors};
+use crate::message::{sign_message, verify_message};
+use crate::idempotency::IDEMPOTENT_REPLAYED_HEADER;
+use crate::incoming::incoming_events;
+use crate::metrics::{get_metrics, track_requests};
+use crate::rate_limit::rate_limit;
+use crate::request_id::{request_id, REQUEST_ID_HEADER};
+use crate::storage::{load_spends, load_wallets, quarantine_wallet, SPENDS_DIR, WALLETS_DIR};
+use crate::types::{
+    AppState, ArkClient, BackgroundTasks, Config, EsploraClient, EventLog, IdempotencyKeys,
+    IncomingWatchers, LogFormat, Metrics, OutpointCache, RateLimiter, SettleJobs, SpendLimits,
+    SpendLocks, TtlCache, UnsignedSends,
+};
+use crate::wallet::{
+    balance_detail, boarding_eta, create_wallet, funding_instructions, get_address, get_balance,
+    import_key, import_wallet, import_watch_only, list_addresses, list_boarding_outputs, list_vtxos,
+    list_wallets, quarantine_invalid_wallets, total_balance, vtxo_history,
+};
 
-pub async fn initialize_server(config: Config) -> Result<ark_core::server::Info> {
-    let mut grpc_client = ark_grpc::Client::new(config.ark_server_url.clone());
-    grpc_client.connect().await?;
+/// Lower bound for the server info refresh period, so a tiny round interval cannot make us
+/// poll the Ark server in a tight loop.
+const MIN_SERVER_INFO_REFRESH: Duration = Duration::from_secs(5);
+
+/// Delay before retrying a dependency that could not be reached; doubled after every failure up
+/// to [`CONNECT_RETRY_MAX_DELAY`].
+const CONNECT_RETRY_INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+const CONNECT_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub async fn initialize_server(ark_client: &ArkClient) -> Result<ark_core::server::Info> {
+    let mut grpc_client = ark_client.get().await?;
     let server_info = grpc_client.get_info().await?;
     Ok(server_info)
 }
 
-pub fn init_tracing() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            "debug,\
-             tower=info,\
-             hyper_util=info,\
-             hyper=info,\
-             h2=warn,\
-             reqwest=info,\
-             ark_core=info,\
-             rustls=info",
-        )
-        .init()
+/// Call `connect` until it succeeds, backing off between attempts. Gives up with the last error
+/// after `max_attempts` failures, or never if that is `None`.
+async fn connect_with_backoff<T, E, F, Fut>(
+    dependency: &str,
+    max_attempts: Option<u32>,
+    mut connect: F,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut delay = CONNECT_RETRY_INITIAL_DELAY;
+    let mut attempt = 1;
+
+    loop {
+        match connect().await {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                tracing::warn!(dependency, attempt, error = %e, "Failed to connect");
+                if max_attempts.is_some_and(|max_attempts| attempt >= max_attempts) {
+                    return Err(e);
+                }
+            }
+        }
+
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(CONNECT_RETRY_MAX_DELAY);
+        attempt += 1;
+    }
+}
+
+fn esplora_client(config: &Config, metrics: &Metrics) -> Result<EsploraClient> {
+    let esplora_urls = std::iter::once(config.esplora_url.clone())
+        .chain(config.esplora_urls.iter().cloned())
+        .collect::<Vec<_>>();
+
+    Ok(EsploraClient::new(&esplora_urls)?
+        .with_max_concurrent_requests(config.esplora_max_concurrent_requests)
+        .with_error_counter(metrics.esplora_errors.clone()))
+}
+
+/// Set up the Esplora client in the background if that failed at startup, until shutdown.
+async fn reconnect_esplora(data: web::Data<AppState>) {
+    if data.esplora_client().is_some() {
+        return;
+    }
+
+    let connect = || async { esplora_client(&data.config, &data.metrics) };
+    let client = tokio::select! {
+        _ = data.background.shutdown_started() => return,
+        client = connect_with_backoff("esplora", None, connect) => client,
+    };
+    if let Ok(client) = client {
+        tracing::info!("Esplora client is available");
+        *data.esplora_client.lock().unwrap() = Some(client);
+    }
+}
+
+/// Refuse server info that this configuration must not be used with.
+fn check_server_info(config: &Config, info: &ark_core::server::Info) -> std::io::Result<()> {
+    check_network_allowed(config, info.network)?;
+    if config.deterministic_nonces && info.network != Network::Regtest {
+        return Err(std::io::Error::other(format!(
+            "deterministic_nonces can only be enabled on regtest, not on {}",
+            info.network
+        )));
+    }
+    if config.faucet_enabled && info.network == Network::Bitcoin {
+        return Err(std::io::Error::other("faucet_enabled must not be set on mainnet"));
+    }
+
+    Ok(())
+}
+
+/// Re-fetch the Ark server info periodically so that changed parameters (dust, expiry, forfeit
+/// address) are picked up without a restart.
+///
+/// Runs every `server_info_refresh_secs`, or every round interval if that is unset. Failed
+/// fetches are logged and the previous info is kept. If the Ark server was unreachable at
+/// startup, it is first retried until it answers (unless `reconnect_in_background` is off). Stops
+/// once shutdown starts.
+async fn refresh_server_info(data: web::Data<AppState>) {
+    if data.server_info().is_none() {
+        if !data.config.reconnect_in_background {
+            tracing::warn!("Ark server unavailable at startup, not refreshing server info");
+            return;
+        }
+
+        let connect = || initialize_server(&data.ark_client);
+        let info = tokio::select! {
+            _ = data.background.shutdown_started() => return,
+            info = connect_with_backoff("ark", None, connect) => info,
+        };
+        let Ok(info) = info else {
+            return;
+        };
+        if let Err(e) = check_server_info(&data.config, &info) {
+            tracing::error!(error = %e, "Refusing to use the Ark server");
+            return;
+        }
+
+        tracing::info!(network = %info.network, "Connected to Ark server");
+        *data.server_info.lock().unwrap() = Some(info);
+    }
+
+    let round_interval = data.server_info().map(|info| info.round_interval);
+    let period = data
+        .config
+        .server_info_refresh_secs
+        .or_else(|| round_interval.and_then(|interval| u64::try_from(interval).ok()))
+        .unwrap_or_default()
+        .max(MIN_SERVER_INFO_REFRESH.as_secs());
+
+    let mut interval = tokio::time::interval(Duration::from_secs(period));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately, and the info was only just fetched.
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = data.background.shutdown_started() => return,
+            _ = interval.tick() => {}
+        }
+
+        let info = match initialize_server(&data.ark_client).await {
+            Ok(info) => info,
+            Err(e) => {
+                if let Some(e) = e.downcast_ref::<ark_grpc::Error>() {
+                    data.ark_client.check(e).await;
+                }
+                tracing::warn!(error = %e, "Failed to refresh Ark server info");
+                continue;
+            }
+        };
+
+        if let Err(e) = check_server_info(&data.config, &info) {
+            tracing::error!(error = %e, "Ignoring refreshed Ark server info");
+            continue;
+        }
+
+        let mut current = data.server_info.lock().unwrap();
+        if let Some(current) = current.as_ref() {
+            log_server_info_changes(current, &info);
+        }
+        *current = Some(info);
+    }
+}
+
+fn log_server_info_changes(old: &ark_core::server::Info, new: &ark_core::server::Info) {
+    let fields = [
+        ("pk", old.pk.to_string(), new.pk.to_string()),
+        ("network", old.network.to_string(), new.network.to_string()),
+        ("dust", old.dust.to_string(), new.dust.to_string()),
+        (
+            "vtxo_tree_expiry",
+            old.vtxo_tree_expiry.to_string(),
+            new.vtxo_tree_expiry.to_string(),
+        ),
+        (
+            "unilateral_exit_delay",
+            old.unilateral_exit_delay.to_string(),
+            new.unilateral_exit_delay.to_string(),
+        ),
+        (
+            "round_interval",
+            old.round_interval.to_string(),
+            new.round_interval.to_string(),
+        ),
+        (
+            "forfeit_address",
+            old.forfeit_address.to_string(),
+            new.forfeit_address.to_string(),
+        ),
+    ];
+
+    for (field, old, new) in fields.iter().filter(|(_, old, new)| old != new) {
+        tracing::warn!(field, %old, %new, "Ark server info changed");
+    }
+}
+
+/// Refuse to run against an Ark server whose network is not in `allowed_networks`.
+fn check_network_allowed(config: &Config, network: Network) -> std::io::Result<()> {
+    if config.allowed_networks.is_empty() {
+        return Ok(());
+    }
+
+    let allowed = config
+        .allowed_networks
+        .iter()
+        .map(|name| {
+            Network::from_str(name).map_err(|_| {
+                std::io::Error::other(format!("Unknown network in allowed_networks: {}", name))
+            })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    if !allowed.contains(&network) {
+        return Err(std::io::Error::other(format!(
+            "Ark server is on network {}, which is not in allowed_networks {:?}",
+            network, config.allowed_networks
+        )));
+    }
+
+    Ok(())
+}
+
+/// Refuse a `send_address_whitelist` entry that is not an Ark or on-chain address, rather than
+/// silently never matching it.
+fn check_send_address_whitelist(config: &Config) -> std::io::Result<()> {
+    for address in config.send_address_whitelist.iter() {
+        if canonical_address(address).is_none() {
+            return Err(std::io::Error::other(format!(
+                "Invalid address in send_address_whitelist: {}",
+                address
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuse a `rate_limit` that could never let a request through.
+fn check_rate_limit(config: &Config) -> std::io::Result<()> {
+    let settings = &config.rate_limit;
+    let limits = std::iter::once(("default", &settings.default))
+        .chain(settings.routes.iter().map(|(route, limit)| (route.as_str(), limit)));
+
+    for (route, limit) in limits {
+        if !(limit.requests_per_second > 0.0 && limit.requests_per_second.is_finite())
+            || limit.burst == 0
+        {
+            return Err(std::io::Error::other(format!(
+                "Invalid rate_limit for {}: requests_per_second and burst must be positive",
+                route
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// The address the HTTP API listens on, from `bind_address` and `port`.
+fn bind_addr(config: &Config) -> std::io::Result<SocketAddr> {
+    let ip = IpAddr::from_str(&config.bind_address).map_err(|_| {
+        std::io::Error::other(format!(
+            "Invalid bind_address {:?}, expected an IP address such as 127.0.0.1",
+            config.bind_address
+        ))
+    })?;
+
+    Ok(SocketAddr::new(ip, config.port))
+}
+
+/// How long browsers may cache the answer to a CORS preflight request, in seconds.
+const CORS_MAX_AGE: usize = 3600;
+
+/// The CORS settings from the config, checked once at startup so that a typo is reported right
+/// away instead of breaking every browser request.
+#[derive(Clone)]
+struct CorsPolicy {
+    any_origin: bool,
+    origins: Vec<String>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+}
+
+impl CorsPolicy {
+    fn from_config(config: &Config) -> std::io::Result<Self> {
+        let any_origin = config.cors_allowed_origins.iter().any(|origin| origin == "*");
+
+        let origins = config
+            .cors_allowed_origins
+            .iter()
+            .filter(|origin| *origin != "*")
+            .map(|origin| match Uri::from_str(origin) {
+                Ok(uri) if uri.scheme().is_some() && uri.host().is_some() => Ok(origin.clone()),
+                _ => Err(std::io::Error::other(format!(
+                    "Invalid origin in cors_allowed_origins: {}",
+                    origin
+                ))),
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        let methods = config
+            .cors_allowed_methods
+            .iter()
+            .map(|method| {
+                Method::from_str(&method.to_uppercase()).map_err(|_| {
+                    std::io::Error::other(format!(
+                        "Invalid method in cors_allowed_methods: {}",
+                        method
+                    ))
+                })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        let headers = config
+            .cors_allowed_headers
+            .iter()
+            .map(|name| {
+                HeaderName::from_str(name).map_err(|_| {
+                    std::io::Error::other(format!(
+                        "Invalid header in cors_allowed_headers: {}",
+                        name
+                    ))
+                })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            any_origin,
+            origins,
+            methods,
+            headers,
+        })
+    }
+
+    /// The middleware for one worker. Preflight `OPTIONS` requests are answered by it for every
+    /// route.
+    fn middleware(&self) -> Cors {
+        let cors = Cors::default()
+            .allowed_methods(self.methods.clone())
+            .allowed_headers(self.headers.clone())
+            .expose_headers([REQUEST_ID_HEADER, IDEMPOTENT_REPLAYED_HEADER, header::RETRY_AFTER])
+            .max_age(CORS_MAX_AGE);
+
+        if self.any_origin {
+            cors.allow_any_origin()
+        } else if self.origins.is_empty() {
+            cors.allowed_origin_fn(|origin, _| is_localhost_origin(origin))
+        } else {
+            self.origins
+                .iter()
+                .fold(cors, |cors, origin| cors.allowed_origin(origin))
+        }
+    }
+}
+
+/// Whether `origin` is a page served from this machine, on any port.
+fn is_localhost_origin(origin: &HeaderValue) -> bool {
+    let Some(uri) = origin.to_str().ok().and_then(|origin| Uri::from_str(origin).ok()) else {
+        return false;
+    };
+
+    matches!(uri.scheme_str(), Some("http" | "https"))
+        && matches!(uri.host(), Some("localhost" | "127.0.0.1" | "[::1]"))
+}
+
+/// Headers set on every response that does not already carry them.
+///
+/// Everything served here is wallet data, so nothing may be cached by intermediaries.
+fn response_headers(config: &Config) -> std::io::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    for (name, value) in config.response_headers.iter() {
+        let name = HeaderName::from_str(name).map_err(|e| {
+            std::io::Error::other(format!("Invalid response header name {}: {}", name, e))
+        })?;
+        let value = HeaderValue::from_str(value).map_err(|e| {
+            std::io::Error::other(format!("Invalid value for response header {}: {}", name, e))
+        })?;
+        headers.insert(name, value);
+    }
+
+    Ok(headers)
+}
+
+/// Log filter used when neither `RUST_LOG` nor `log_filter` is set.
+const DEFAULT_LOG_FILTER: &str = "debug,\
+     tower=info,\
+     hyper_util=info,\
+     hyper=info,\
+     h2=warn,\
+     reqwest=info,\
+     ark_core=info,\
+     rustls=info";
+
+/// Install the global tracing subscriber, filtered by `RUST_LOG`, else `log_filter`, else
+/// [`DEFAULT_LOG_FILTER`].
+pub fn init_tracing(config: &Config) -> std::io::Result<()> {
+    let filter = log_filter(std::env::var("RUST_LOG").ok(), config);
+    let env_filter = EnvFilter::try_new(&filter).map_err(|e| {
+        std::io::Error::other(format!("Invalid log filter {:?}: {}", filter, e))
+    })?;
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(env_filter);
+    match config.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+
+    Ok(())
+}
+
+fn log_filter(rust_log: Option<String>, config: &Config) -> String {
+    rust_log
+        .filter(|filter| !filter.trim().is_empty())
+        .or_else(|| config.log_filter.clone())
+        .unwrap_or_else(|| DEFAULT_LOG_FILTER.to_string())
 }
 
 pub async fn start_server(config: Config) -> std::io::Result<()> {
+    let bind_addr = bind_addr(&config)?;
+    let cors_policy = CorsPolicy::from_config(&config)?;
+    check_send_address_whitelist(&config)?;
+    check_rate_limit(&config)?;
+
+    let metrics = Metrics::new();
+
     // Initialize server connection
-    let server_info = match initialize_server(config.clone()).await {
-        Ok(info) => Some(Mutex::new(info)),
+    let ark_tls = config.ark_tls().map_err(std::io::Error::other)?;
+    let ark_client = ArkClient::new(
+        config.ark_server_url.clone(),
+        config.ark_retry_policy(),
+        config.ark_timeouts(),
+        ark_tls,
+    )
+    .with_error_counter(metrics.ark_errors.clone());
+    let startup_attempts = Some(config.startup_connect_attempts.max(1));
+    let connect = || initialize_server(&ark_client);
+    let server_info = match connect_with_backoff("ark", startup_attempts, connect).await {
+        Ok(info) => {
+            check_server_info(&config, &info)?;
+            Some(info)
+        }
         Err(e) => {
-            eprintln!("Failed to connect to Ark server: {}", e);
+            tracing::error!(error = %e, "Failed to connect to Ark server");
             None
         }
     };
 
     // Initialize Esplora client
-    let esplora_client = match EsploraClient::new(&config.esplora_url) {
-        Ok(client) => Some(Mutex::new(client)),
+    let connect = || async { esplora_client(&config, &metrics) };
+    let esplora_client = match connect_with_backoff("esplora", startup_attempts, connect).await {
+        Ok(client) => Some(client),
         Err(e) => {
-            eprintln!("Failed to create Esplora client: {}", e);
+            tracing::error!(error = %e, "Failed to create Esplora client");
             None
         }
     };
 
     // Create directory for wallet storage if it doesn't exist
-    if !Path::new("wallets").exists() {
-        fs::create_dir("wallets")?;
+    if !Path::new(WALLETS_DIR).exists() {
+        fs::create_dir(WALLETS_DIR)?;
+    }
+
+    if config.encryption_passphrase.is_none() {
+        tracing::warn!("No encryption_passphrase configured, storing wallet seeds in plain text");
+    }
+
+    let mut wallets = load_wallets(Path::new(WALLETS_DIR))?;
+
+    if config.validate_wallets_on_start {
+        if server_info.is_none() {
+            tracing::warn!("Ark server unavailable, only checking wallet seeds");
+        }
+
+        let loaded = wallets.len();
+        let quarantined = quarantine_invalid_wallets(
+            &mut wallets,
+            server_info.as_ref(),
+            config.encryption_passphrase.as_deref(),
+        );
+        for wallet_id in quarantined.iter() {
+            if let Err(e) = quarantine_wallet(Path::new(WALLETS_DIR), wallet_id) {
+                tracing::error!(%wallet_id, error = %e, "Failed to move wallet file to quarantine");
+            }
+        }
+        tracing::info!(
+            loaded,
+            quarantined = quarantined.len(),
+            "Validated persisted wallets"
+        );
     }
 
+    // Wallet files written before owner keys were stored get theirs now, so that imports can look
+    // for duplicates without decrypting every seed.
+    for wallet_info in wallets.values_mut() {
+        wallet_info.fill_in_owner(config.encryption_passphrase.as_deref());
+    }
+
+    let spends_dir = Path::new(WALLETS_DIR).join(SPENDS_DIR);
+    fs::create_dir_all(&spends_dir)?;
+    let spend_limits = SpendLimits::new(
+        config.max_send_per_tx.map(Amount::from_sat),
+        config.max_send_per_day.map(Amount::from_sat),
+        Some(spends_dir.clone()),
+        load_spends(&spends_dir)?,
+    );
+
+    let response_headers = response_headers(&config)?;
+
     // Set up application state
     let app_data = web::Data::new(AppState {
-        wallets: Mutex::new(HashMap::new()),
+        wallets: Mutex::new(wallets),
         config: config.clone(),
-        server_info,
-        esplora_client,
+        ark_client,
+        server_info: Mutex::new(server_info),
+        esplora_client: Mutex::new(esplora_client),
+        chain_tip: TtlCache::new("chain_tip", CHAIN_TIP_TTL),
+        fee_rates: TtlCache::new(
+            "fee_rates",
+            Duration::from_secs(config.fee_rates_cache_ttl_secs),
+        ),
+        outpoints: OutpointCache::new(Duration::from_secs(config.outpoint_cache_ttl_secs)),
+        events: EventLog::new(config.event_log_capacity),
+        spend_locks: SpendLocks::default(),
+        settle_jobs: SettleJobs::default(),
+        unsigned_sends: UnsignedSends::default(),
+        spend_limits,
+        idempotency_keys: IdempotencyKeys::new(Duration::from_secs(
+            config.idempotency_key_ttl_secs,
+        )),
+        incoming_watchers: IncomingWatchers::default(),
+        metrics,
+        rate_limiter: RateLimiter::new(config.rate_limit.clone()),
+        background: BackgroundTasks::new(tokio::runtime::Handle::current()),
     });
 
-    println!("Starting Ark API server on 127.0.0.1:8080");
+    app_data.background.spawn(refresh_server_info(app_data.clone()));
+    if config.reconnect_in_background {
+        app_data.background.spawn(reconnect_esplora(app_data.clone()));
+    }
+    app_data.background.spawn(run_auto_settle(app_data.clone()));
+
+    let faucet_enabled = config.faucet_enabled;
+    if faucet_enabled {
+        tracing::warn!(command = %config.faucet_command, "Faucet endpoint enabled");
+    }
+
+    let shutdown_data = app_data.clone();
 
     // Start HTTP server
-    HttpServer::new(move || {
-        let cors = Cors::permissive();
+    let mut server = HttpServer::new(move || {
+        let cors = cors_policy.middleware();
+        let default_headers = response_headers
+            .iter()
+            .fold(DefaultHeaders::new(), |headers, (name, value)| {
+                headers.add((name.clone(), value.clone()))
+            });
         App::new()
+            .wrap(default_headers)
+            .wrap(from_fn(rate_limit))
             .wrap(cors)
+            .wrap(from_fn(track_requests))
+            .wrap(from_fn(request_id))
             .app_data(app_data.clone())
             .service(create_wallet)
+            .service(import_key)
+            .service(import_wallet)
+            .service(import_watch_only)
+            .service(list_wallets)
             .service(get_address)
+            .service(list_addresses)
             .service(get_balance)
+            .service(total_balance)
+            .service(balance_detail)
+            .service(list_vtxos)
+            .service(list_boarding_outputs)
+            .service(funding_instructions)
+            .service(boarding_eta)
+            .service(vtxo_history)
             .service(send_to_ark_address)
-            .service(faucet)
+            .service(send_batch)
+            .service(send_max)
+            .service(send_onchain)
+            .service(build_unsigned_send)
+            .service(submit_signed_psbt)
+            .service(cancel_redeem)
+            .service(estimate_fee)
+            .service(sign_message)
+            .service(verify_message)
+            .service(validate_address)
+            .configure(|cfg| {
+                // Unregistered, the route answers 404 like any unknown path.
+                if faucet_enabled {
+                    cfg.service(faucet);
+                }
+            })
             .service(settle_funds)
+            .service(refresh_vtxos)
+            .service(consolidate_vtxos)
+            .service(settle_status)
+            .service(settle_cancel)
+            .service(settle_ws)
+            .service(chain_status)
+            .service(get_fee_rates)
+            .service(tx_status)
+            .service(broadcast)
+            .service(unilateral_exit)
+            .service(cache_stats)
+            .service(cache_flush)
+            .service(wallet_events)
+            .service(incoming_events)
+            .service(health)
+            .service(ready)
+            .service(get_server_info)
+            .service(wallet_descriptors)
+            .service(get_version)
+            .service(get_metrics)
     })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    // Signals are handled below, so that spends can finish before the workers stop.
+    .disable_signals()
+    .shutdown_timeout(config.shutdown_timeout_secs);
+    if let Some(workers) = config.http_workers {
+        server = server.workers(workers.get());
+    }
+    let server = server.bind(bind_addr).map_err(|e| {
+        std::io::Error::new(e.kind(), format!("Failed to bind {}: {}", bind_addr, e))
+    })?;
+
+    for addr in server.addrs() {
+        println!("Starting Ark API server on {}", addr);
+    }
+
+    let server = server.run();
+    let shutdown = tokio::spawn(shutdown_on_signal(server.handle(), shutdown_data.clone()));
+    server.await?;
+
+    // The workers are stopped; background tasks get the rest of the same timeout.
+    if shutdown_data.background.is_shutting_down() {
+        let _ = shutdown.await;
+    } else {
+        shutdown.abort();
+    }
+
+    // Wallet files are written while holding this lock, so once we have it none is half-written.
+    drop(shutdown_data.wallets.lock().unwrap());
+    tracing::info!("Shutdown complete");
+
+    Ok(())
+}
+
+/// On SIGINT or SIGTERM, stop accepting connections and spends, and give the requests and
+/// background tasks in flight up to `shutdown_timeout_secs` to finish.
+///
+/// The workers and the background tasks are waited for at the same time, so that both are done
+/// by the same deadline.
+async fn shutdown_on_signal(server: ServerHandle, data: web::Data<AppState>) {
+    shutdown_signal().await;
+    tracing::info!("Shutting down, no longer accepting connections or spends");
+    data.background.shut_down();
+    server.pause().await;
+
+    let deadline =
+        tokio::time::Instant::now() + Duration::from_secs(data.config.shutdown_timeout_secs);
+    let ((finished, abandoned), ()) =
+        tokio::join!(data.background.wait(deadline), server.stop(true));
+    if abandoned > 0 {
+        tracing::warn!(finished, abandoned, "Tasks still running at shutdown were abandoned");
+    } else {
+        tracing::info!(finished, "Background tasks finished");
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "Failed to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{HttpResponse, test};
+
+    #[actix_web::test]
+    async fn deterministic_nonces_are_only_allowed_on_regtest() {
+        let state = crate::testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        let mut config = state.config.clone();
+        config.deterministic_nonces = true;
+        let mut info = state.server_info().unwrap();
+        assert!(check_server_info(&config, &info).is_ok());
+
+        info.network = Network::Signet;
+        assert!(check_server_info(&config, &info).is_err());
+    }
+
+    #[actix_web::test]
+    async fn tls_is_used_when_enabled_or_implied_by_the_url() {
+        let mut config =
+            crate::testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1").config;
+        assert!(config.ark_tls().unwrap().is_none());
+
+        config.ark_server_url = "https://ark.example:443".to_string();
+        assert!(config.ark_tls().unwrap().is_some());
+
+        config.tls.ca_cert_path = Some("/nonexistent/ca.pem".to_string());
+        let error = config.ark_tls().unwrap_err();
+        assert!(error.contains("/nonexistent/ca.pem"), "{}", error);
+    }
+
+    #[actix_web::test]
+    async fn log_filter_prefers_rust_log_then_config() {
+        let mut config =
+            crate::testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1").config;
+        assert_eq!(log_filter(None, &config), DEFAULT_LOG_FILTER);
+        assert!(EnvFilter::try_new(DEFAULT_LOG_FILTER).is_ok());
+
+        config.log_filter = Some("info".to_string());
+        assert_eq!(log_filter(None, &config), "info");
+        assert_eq!(log_filter(Some(" ".to_string()), &config), "info");
+        assert_eq!(log_filter(Some("warn".to_string()), &config), "warn");
+    }
+
+    #[actix_web::test]
+    async fn preflight_is_answered_for_allowed_origins_only() {
+        let config = crate::testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1").config;
+        let policy = CorsPolicy::from_config(&config).unwrap();
+        let app = test::init_service(
+            App::new()
+                .wrap(policy.middleware())
+                .route("/send_to_ark_address", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let preflight = |origin: &str| {
+            test::TestRequest::default()
+                .method(Method::OPTIONS)
+                .uri("/send_to_ark_address")
+                .insert_header((header::ORIGIN, origin))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type"))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, preflight("http://localhost:3000")).await;
+        assert!(response.status().is_success());
+        assert_eq!(
+            response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "http://localhost:3000"
+        );
+
+        let response = test::call_service(&app, preflight("https://elsewhere.example")).await;
+        assert!(!response.status().is_success());
+        assert!(
+            response
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .is_none()
+        );
+    }
 }
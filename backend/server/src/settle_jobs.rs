@@ -0,0 +1,309 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+
+use crate::types::*;
+
+/// How long the outcome of a finished job stays available to pollers.
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// Settlements running in the background, by job ID.
+#[derive(Default)]
+pub struct SettleJobs {
+    jobs: Mutex<HashMap<String, SettleJob>>,
+}
+
+struct SettleJob {
+    wallet_id: String,
+    status: SettleJobStatus,
+    timeline: Vec<SettleTimelineEntry>,
+    finished_at: Option<Instant>,
+    cancellation: SettleCancellation,
+}
+
+/// Lets a settlement be cancelled until it submits its forfeit transactions. From then on the
+/// wallet's funds are committed to the round, and only one of [`Self::cancel`] and
+/// [`Self::commit`] can succeed.
+#[derive(Clone, Default)]
+pub struct SettleCancellation {
+    token: CancellationToken,
+    committed: Arc<Mutex<bool>>,
+}
+
+impl SettleCancellation {
+    /// Ask the settlement to stop. Returns `false` if it already committed to the round.
+    fn cancel(&self) -> bool {
+        let committed = self.committed.lock().unwrap();
+        if *committed {
+            return false;
+        }
+        self.token.cancel();
+        true
+    }
+
+    /// Pass the point of no return. Returns `false` if the settlement was cancelled first.
+    pub fn commit(&self) -> bool {
+        let mut committed = self.committed.lock().unwrap();
+        if self.token.is_cancelled() {
+            return false;
+        }
+        *committed = true;
+        true
+    }
+
+    /// Resolves once the settlement is cancelled.
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SettleJobStatus {
+    /// Inputs are registered and the job waits for the next round.
+    Pending,
+    /// The round started; the VTXO tree is being signed.
+    Signing,
+    /// Forfeit transactions and the round transaction are being signed.
+    Finalizing,
+    Done { txid: String },
+    Failed { error: String },
+    /// The job was cancelled before its funds were committed to a round.
+    Cancelled,
+}
+
+/// A step of the round protocol a settlement went through.
+#[derive(Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SettleProgress {
+    /// The round started and its VTXO tree is ready to be signed.
+    Signing { round_id: String },
+    /// Our nonces for the VTXO tree were submitted.
+    NoncesGenerated { round_id: String },
+    /// The VTXO tree is signed; forfeits and the round transaction are next.
+    Finalization { round_id: String },
+    /// The round transaction was broadcast.
+    Finalized { round_id: String, round_txid: String },
+}
+
+/// A step of a settlement and when it happened, so that users can match their settlement with the
+/// Ark server's round.
+#[derive(Clone, Serialize)]
+pub struct SettleTimelineEntry {
+    #[serde(flatten)]
+    pub progress: SettleProgress,
+    /// Unix time in seconds.
+    pub timestamp: u64,
+}
+
+impl SettleTimelineEntry {
+    pub fn now(progress: SettleProgress) -> Self {
+        Self {
+            progress,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+impl From<SettleProgress> for SettleJobStatus {
+    fn from(progress: SettleProgress) -> Self {
+        match progress {
+            SettleProgress::Signing { .. } | SettleProgress::NoncesGenerated { .. } => {
+                Self::Signing
+            }
+            SettleProgress::Finalization { .. } | SettleProgress::Finalized { .. } => {
+                Self::Finalizing
+            }
+        }
+    }
+}
+
+impl SettleJobStatus {
+    fn is_finished(&self) -> bool {
+        matches!(self, Self::Done { .. } | Self::Failed { .. } | Self::Cancelled)
+    }
+}
+
+impl SettleJobs {
+    /// Register a new job for `wallet_id` and return its ID, along with the handle its
+    /// settlement watches for cancellation.
+    pub fn create(&self, wallet_id: &str) -> (String, SettleCancellation) {
+        let cancellation = SettleCancellation::default();
+        let job_id = uuid::Uuid::new_v4().to_string();
+
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| {
+            job.finished_at
+                .is_none_or(|finished_at| finished_at.elapsed() < FINISHED_JOB_RETENTION)
+        });
+        jobs.insert(
+            job_id.clone(),
+            SettleJob {
+                wallet_id: wallet_id.to_string(),
+                status: SettleJobStatus::Pending,
+                timeline: Vec::new(),
+                finished_at: None,
+                cancellation: cancellation.clone(),
+            },
+        );
+
+        (job_id, cancellation)
+    }
+
+    pub fn update(&self, job_id: &str, status: SettleJobStatus) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(job_id) {
+            if job.status.is_finished() {
+                return;
+            }
+            if status.is_finished() {
+                job.finished_at = Some(Instant::now());
+            }
+            job.status = status;
+        }
+    }
+
+    /// Record that the job reached `progress` in the round.
+    pub fn progress(&self, job_id: &str, progress: SettleProgress) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(job_id) {
+            job.timeline.push(SettleTimelineEntry::now(progress.clone()));
+            if !job.status.is_finished() {
+                job.status = progress.into();
+            }
+        }
+    }
+
+    fn get(&self, job_id: &str) -> Option<SettleStatusResponse> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(job_id)
+            .map(|job| job.status_response(job_id))
+    }
+
+    /// Cancel the job unless it already finished or committed to a round.
+    fn cancel(&self, job_id: &str) -> Option<SettleCancelOutcome> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(job_id)?;
+
+        let outcome = if job.status.is_finished() {
+            SettleCancelOutcome::Finished
+        } else if job.cancellation.cancel() {
+            job.status = SettleJobStatus::Cancelled;
+            job.finished_at = Some(Instant::now());
+            SettleCancelOutcome::Cancelled
+        } else {
+            SettleCancelOutcome::Committed
+        };
+        Some(outcome)
+    }
+}
+
+impl SettleJob {
+    fn status_response(&self, job_id: &str) -> SettleStatusResponse {
+        SettleStatusResponse {
+            job_id: job_id.to_string(),
+            wallet_id: self.wallet_id.clone(),
+            status: self.status.clone(),
+            timeline: self.timeline.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SettleCancelOutcome {
+    Cancelled,
+    /// The job was over before it could be cancelled.
+    Finished,
+    /// The forfeit transactions were submitted; the job can no longer be cancelled.
+    Committed,
+}
+
+#[derive(Serialize)]
+pub struct SettleStatusResponse {
+    pub job_id: String,
+    pub wallet_id: String,
+    #[serde(flatten)]
+    pub status: SettleJobStatus,
+    /// The steps of the round the job went through so far.
+    pub timeline: Vec<SettleTimelineEntry>,
+}
+
+#[get("/settle_status/{job_id}")]
+pub async fn settle_status(job_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    match data.settle_jobs.get(&job_id) {
+        Some(response) => HttpResponse::Ok().json(response),
+        None => HttpResponse::NotFound().body("Settle job not found"),
+    }
+}
+
+#[derive(Serialize)]
+pub struct SettleCancelRefusedResponse {
+    pub code: &'static str,
+    pub error: String,
+    pub job: SettleStatusResponse,
+}
+
+/// Cancel a settlement running in the background and return the state it ended in.
+///
+/// A job that has not submitted its forfeit transactions yet leaves the round and ends up
+/// `cancelled`. Once they are submitted the wallet's funds are committed to the round, so the
+/// cancellation is refused with `409 Conflict` and the job runs to completion. A job that already
+/// finished is returned as is.
+#[post("/settle_cancel/{job_id}")]
+pub async fn settle_cancel(job_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let outcome = match data.settle_jobs.cancel(&job_id) {
+        Some(outcome) => outcome,
+        None => return HttpResponse::NotFound().body("Settle job not found"),
+    };
+    let Some(job) = data.settle_jobs.get(&job_id) else {
+        return HttpResponse::NotFound().body("Settle job not found");
+    };
+
+    match outcome {
+        SettleCancelOutcome::Cancelled => {
+            tracing::info!(job_id = %job_id, wallet_id = %job.wallet_id, "Cancelled settlement");
+            HttpResponse::Ok().json(job)
+        }
+        SettleCancelOutcome::Finished => HttpResponse::Ok().json(job),
+        SettleCancelOutcome::Committed => {
+            HttpResponse::Conflict().json(SettleCancelRefusedResponse {
+                code: "SETTLEMENT_COMMITTED",
+                error: "Forfeit transactions were submitted; the funds are committed to the round"
+                    .to_string(),
+                job,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settlements_can_only_be_cancelled_before_they_commit() {
+        let jobs = SettleJobs::default();
+
+        let (job_id, cancellation) = jobs.create("wallet");
+        assert_eq!(jobs.cancel(&job_id), Some(SettleCancelOutcome::Cancelled));
+        assert!(!cancellation.commit());
+        // The settlement reporting its failure does not overwrite the cancellation.
+        jobs.update(&job_id, SettleJobStatus::Failed { error: "cancelled".to_string() });
+        assert!(matches!(jobs.get(&job_id).unwrap().status, SettleJobStatus::Cancelled));
+
+        let (job_id, cancellation) = jobs.create("wallet");
+        assert!(cancellation.commit());
+        assert_eq!(jobs.cancel(&job_id), Some(SettleCancelOutcome::Committed));
+        assert!(matches!(jobs.get(&job_id).unwrap().status, SettleJobStatus::Pending));
+
+        jobs.update(&job_id, SettleJobStatus::Done { txid: "txid".to_string() });
+        assert_eq!(jobs.cancel(&job_id), Some(SettleCancelOutcome::Finished));
+        assert_eq!(jobs.cancel("unknown"), None);
+    }
+}
@@ -0,0 +1,114 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_ws::Message;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use crate::transactions::{finish_settlement, lock_wallet, prepare_settlement};
+use crate::types::*;
+use crate::wallet::find_wallet;
+
+#[derive(Deserialize)]
+pub struct SettleWsQuery {
+    pub to_address: Option<String>,
+}
+
+/// The last message on the socket, once the settlement is over.
+#[derive(Serialize)]
+struct SettleResult {
+    event: &'static str,
+    #[serde(flatten)]
+    response: SettleResponse,
+}
+
+/// Settle a wallet, streaming a JSON message per round event and then the outcome.
+///
+/// If the client disconnects the settlement still runs to the end, since aborting a round halfway
+/// could leave the wallet's funds in limbo; only the stream stops.
+#[get("/ws/settle/{wallet_id}")]
+pub async fn settle_ws(
+    req: HttpRequest,
+    body: web::Payload,
+    wallet_id: web::Path<String>,
+    query: web::Query<SettleWsQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let wallet_info = match find_wallet(&data, wallet_id.as_str()) {
+        Ok(info) => info,
+        Err(response) => return Ok(response),
+    };
+
+    let spend_guard = match lock_wallet(&data, &wallet_info.id) {
+        Ok(guard) => guard,
+        Err(response) => return Ok(response),
+    };
+
+    // Anything that makes the settlement impossible is reported before upgrading the connection.
+    let settlement =
+        match prepare_settlement(&data, &wallet_info, query.to_address.as_deref()).await {
+            Ok(settlement) => settlement,
+            Err(response) => return Ok(response),
+        };
+    let has_expired = settlement.has_expired;
+
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+
+    let (updates_tx, mut updates_rx) = mpsc::unbounded_channel::<String>();
+
+    let settle_data = data.clone();
+    let span = tracing::Span::current();
+    data.background.spawn(
+        async move {
+            let _spend_guard = spend_guard;
+
+            let settle_result = settlement
+                .run(|progress| {
+                    if let Ok(message) = serde_json::to_string(&progress) {
+                        let _ = updates_tx.send(message);
+                    }
+                })
+                .await;
+
+            let (_, response) =
+                finish_settlement(&settle_data, &wallet_info, settle_result, has_expired).await;
+            let result = SettleResult {
+                event: "result",
+                response,
+            };
+            if let Ok(message) = serde_json::to_string(&result) {
+                let _ = updates_tx.send(message);
+            }
+        }
+        .instrument(span),
+    );
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                update = updates_rx.recv() => match update {
+                    Some(message) => {
+                        if session.text(message).await.is_err() {
+                            return;
+                        }
+                    }
+                    // The settlement is over and its result was sent.
+                    None => break,
+                },
+                message = messages.next() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
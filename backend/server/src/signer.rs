@@ -0,0 +1,56 @@
+use bitcoin::key::{Keypair, Secp256k1};
+use bitcoin::secp256k1::{schnorr, Message, SecretKey};
+use bitcoin::XOnlyPublicKey;
+
+/// Produces the Schnorr signatures that spending a wallet's outputs requires.
+///
+/// Spend paths only ask for signatures through this trait, so the key does not have to live in
+/// this process.
+pub trait Signer: Send + Sync {
+    /// Sign `msg` and return the signature with the x-only public key that verifies it.
+    fn sign_schnorr(&self, msg: Message) -> (schnorr::Signature, XOnlyPublicKey);
+}
+
+/// Signs with a secret key held in memory, e.g. one decrypted from the wallet's stored seed.
+pub struct InMemorySigner {
+    keypair: Keypair,
+}
+
+impl InMemorySigner {
+    pub fn new(sk: &SecretKey) -> Self {
+        Self {
+            keypair: Keypair::from_secret_key(&Secp256k1::new(), sk),
+        }
+    }
+
+    /// The underlying keypair, for the few signing steps `ark_core` only performs with one.
+    pub fn keypair(&self) -> &Keypair {
+        &self.keypair
+    }
+}
+
+impl Signer for InMemorySigner {
+    fn sign_schnorr(&self, msg: Message) -> (schnorr::Signature, XOnlyPublicKey) {
+        let sig = Secp256k1::new().sign_schnorr_no_aux_rand(&msg, &self.keypair);
+        (sig, self.keypair.x_only_public_key().0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_memory_signatures_verify_against_the_returned_key() {
+        let sk = SecretKey::from_slice(&[2u8; 32]).unwrap();
+        let signer = InMemorySigner::new(&sk);
+        let msg = Message::from_digest([7u8; 32]);
+
+        let (sig, pk) = signer.sign_schnorr(msg);
+
+        assert_eq!(pk, sk.x_only_public_key(&Secp256k1::new()).0);
+        assert!(Secp256k1::verification_only().verify_schnorr(&sig, &msg, &pk).is_ok());
+        // Signing uses no auxiliary randomness, so it is deterministic.
+        assert_eq!(signer.sign_schnorr(msg).0, sig);
+    }
+}
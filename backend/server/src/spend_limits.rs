@@ -0,0 +1,169 @@
+use bitcoin::Amount;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::storage::persist_spends;
+
+/// Sends older than this no longer count against the daily limit.
+pub const SPEND_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// One send counted against a wallet's daily limit.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SpendRecord {
+    /// Unix timestamp in seconds.
+    pub timestamp: u64,
+    /// Paid to the recipients, in sats.
+    pub amount: u64,
+}
+
+/// A send refused because it would go over one of the configured limits.
+#[derive(Debug, PartialEq)]
+pub struct LimitExceeded {
+    /// `per_transaction` or `per_day`.
+    pub limit: &'static str,
+    /// What the wallet may still send right now.
+    pub remaining: Amount,
+}
+
+/// Caps on how much each wallet may send, per transaction and over a rolling 24 hours.
+///
+/// Sends are written to `dir` as they are recorded, so restarting the server does not reset the
+/// daily allowance.
+pub struct SpendLimits {
+    max_per_tx: Option<Amount>,
+    max_per_day: Option<Amount>,
+    /// `None` keeps the sends in memory only.
+    dir: Option<PathBuf>,
+    sends: Mutex<HashMap<String, Vec<SpendRecord>>>,
+}
+
+impl SpendLimits {
+    pub fn new(
+        max_per_tx: Option<Amount>,
+        max_per_day: Option<Amount>,
+        dir: Option<PathBuf>,
+        sends: HashMap<String, Vec<SpendRecord>>,
+    ) -> Self {
+        Self {
+            max_per_tx,
+            max_per_day,
+            dir,
+            sends: Mutex::new(sends),
+        }
+    }
+
+    /// Whether `wallet_id` may send `amount` now.
+    pub fn check(&self, wallet_id: &str, amount: Amount) -> Result<(), LimitExceeded> {
+        self.check_at(wallet_id, amount, now())
+    }
+
+    /// Count `amount` sent by `wallet_id` against its daily limit.
+    pub fn record(&self, wallet_id: &str, amount: Amount) {
+        self.record_at(wallet_id, amount, now())
+    }
+
+    fn check_at(&self, wallet_id: &str, amount: Amount, now: u64) -> Result<(), LimitExceeded> {
+        let remaining_today = self.max_per_day.map(|max_per_day| {
+            let sent_today = match self.sends.lock().unwrap().get_mut(wallet_id) {
+                Some(sends) => prune(sends, now)
+                    .iter()
+                    .map(|send| Amount::from_sat(send.amount))
+                    .sum(),
+                None => Amount::ZERO,
+            };
+            max_per_day.checked_sub(sent_today).unwrap_or(Amount::ZERO)
+        });
+        let Some(remaining) = [self.max_per_tx, remaining_today].into_iter().flatten().min() else {
+            return Ok(());
+        };
+
+        if self.max_per_tx.is_some_and(|max_per_tx| amount > max_per_tx) {
+            return Err(LimitExceeded {
+                limit: "per_transaction",
+                remaining,
+            });
+        }
+        if remaining_today.is_some_and(|today| amount > today) {
+            return Err(LimitExceeded {
+                limit: "per_day",
+                remaining,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn record_at(&self, wallet_id: &str, amount: Amount, now: u64) {
+        if self.max_per_day.is_none() {
+            return;
+        }
+
+        let mut sends = self.sends.lock().unwrap();
+        let wallet_sends = sends.entry(wallet_id.to_string()).or_default();
+        prune(wallet_sends, now);
+        wallet_sends.push(SpendRecord {
+            timestamp: now,
+            amount: amount.to_sat(),
+        });
+
+        if let Some(dir) = &self.dir
+            && let Err(e) = persist_spends(dir, wallet_id, wallet_sends)
+        {
+            tracing::error!(%wallet_id, error = %e, "Failed to persist spend limit counters");
+        }
+    }
+}
+
+/// Drop the sends that are outside the window ending at `now`.
+fn prune(sends: &mut Vec<SpendRecord>, now: u64) -> &[SpendRecord] {
+    sends.retain(|send| send.timestamp + SPEND_WINDOW.as_secs() > now);
+    sends
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daily_allowance_rolls_over_after_24_hours() {
+        let limits = SpendLimits::new(
+            Some(Amount::from_sat(6_000)),
+            Some(Amount::from_sat(10_000)),
+            None,
+            HashMap::new(),
+        );
+        let start = 1_700_000_000;
+
+        assert_eq!(
+            limits.check_at("a", Amount::from_sat(7_000), start),
+            Err(LimitExceeded {
+                limit: "per_transaction",
+                remaining: Amount::from_sat(6_000),
+            })
+        );
+
+        limits.record_at("a", Amount::from_sat(6_000), start);
+        assert_eq!(
+            limits.check_at("a", Amount::from_sat(5_000), start + 60),
+            Err(LimitExceeded {
+                limit: "per_day",
+                remaining: Amount::from_sat(4_000),
+            })
+        );
+        assert!(limits.check_at("a", Amount::from_sat(4_000), start + 60).is_ok());
+        assert!(limits.check_at("b", Amount::from_sat(5_000), start + 60).is_ok(), "other wallet");
+
+        let next_day = start + SPEND_WINDOW.as_secs();
+        assert!(limits.check_at("a", Amount::from_sat(6_000), next_day).is_ok());
+    }
+}
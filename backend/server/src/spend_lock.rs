@@ -0,0 +1,80 @@
+use bitcoin::OutPoint;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Wallets with a send or settlement in flight.
+///
+/// Two overlapping spends from the same wallet would select the same VTXOs, and one of them would
+/// fail halfway through. Every spending handler takes the wallet's lock first and refuses to run if
+/// it is already taken.
+///
+/// While a spend holds the lock it can reserve the outpoints it selected, so that balances
+/// reported in the meantime do not count them as spendable.
+#[derive(Default)]
+pub struct SpendLocks {
+    busy: Arc<Mutex<HashMap<String, Vec<OutPoint>>>>,
+}
+
+/// Held for the duration of a spend; releases the wallet and its reservations when dropped.
+pub struct SpendGuard {
+    busy: Arc<Mutex<HashMap<String, Vec<OutPoint>>>>,
+    wallet_id: String,
+}
+
+impl SpendLocks {
+    /// Lock `wallet_id` for spending, or `None` if a spend is already in progress.
+    pub fn try_lock(&self, wallet_id: &str) -> Option<SpendGuard> {
+        let mut busy = self.busy.lock().unwrap();
+        if busy.contains_key(wallet_id) {
+            return None;
+        }
+        busy.insert(wallet_id.to_string(), Vec::new());
+
+        Some(SpendGuard {
+            busy: self.busy.clone(),
+            wallet_id: wallet_id.to_string(),
+        })
+    }
+
+    /// Reserve `outpoints` for the spend in flight on `wallet_id`, until its guard is dropped.
+    ///
+    /// Does nothing if the wallet is not locked.
+    pub fn reserve(&self, wallet_id: &str, outpoints: impl IntoIterator<Item = OutPoint>) {
+        if let Some(reserved) = self.busy.lock().unwrap().get_mut(wallet_id) {
+            reserved.extend(outpoints);
+        }
+    }
+
+    /// The outpoints reserved by the spend in flight on `wallet_id`, if any.
+    pub fn reserved(&self, wallet_id: &str) -> Vec<OutPoint> {
+        self.busy
+            .lock()
+            .unwrap()
+            .get(wallet_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for SpendGuard {
+    fn drop(&mut self) {
+        self.busy.lock().unwrap().remove(&self.wallet_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_spend_per_wallet_at_a_time() {
+        let locks = SpendLocks::default();
+
+        let guard = locks.try_lock("a").unwrap();
+        assert!(locks.try_lock("a").is_none());
+        assert!(locks.try_lock("b").is_some());
+
+        drop(guard);
+        assert!(locks.try_lock("a").is_some());
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::spend_limits::SpendRecord;
+use crate::types::WalletInfo;
+
+/// Directory holding one `{wallet_id}.json` file per wallet.
+pub const WALLETS_DIR: &str = "wallets";
+
+/// Subdirectory of [`WALLETS_DIR`] holding the recent sends of each wallet with a daily limit.
+pub const SPENDS_DIR: &str = "spend_limits";
+
+/// Write `wallet_info` to `{dir}/{id}.json`.
+///
+/// The file is written to a temporary path first and then renamed over the final one, so a crash
+/// never leaves a half-written wallet behind.
+pub fn persist_wallet(dir: &Path, wallet_info: &WalletInfo) -> std::io::Result<()> {
+    write_json(dir, &wallet_info.id, wallet_info)
+}
+
+/// Write `value` to `{dir}/{name}.json` through a temporary file, as [`persist_wallet`] does.
+fn write_json(dir: &Path, name: &str, value: &impl serde::Serialize) -> std::io::Result<()> {
+    let path = dir.join(format!("{}.json", name));
+    let tmp_path = dir.join(format!(".{}.json.tmp", name));
+
+    let contents = serde_json::to_vec_pretty(value)?;
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(&contents)?;
+    file.sync_all()?;
+
+    fs::rename(&tmp_path, &path)
+}
+
+/// Read every `*.json` wallet file in `dir`, keyed by wallet ID.
+///
+/// Files that cannot be read or parsed are logged and skipped so that one bad file does not keep
+/// the server from starting.
+pub fn load_wallets(dir: &Path) -> std::io::Result<HashMap<String, WalletInfo>> {
+    let mut wallets = HashMap::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+
+        let wallet_info = match fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| Ok(serde_json::from_slice::<WalletInfo>(&contents)?))
+        {
+            Ok(wallet_info) => wallet_info,
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "Skipping bad wallet file");
+                continue;
+            }
+        };
+
+        wallets.insert(wallet_info.id.clone(), wallet_info);
+    }
+
+    tracing::info!(count = wallets.len(), "Loaded persisted wallets");
+
+    Ok(wallets)
+}
+
+/// Move a wallet's file into `{dir}/quarantine` so it is no longer loaded but not lost either.
+pub fn quarantine_wallet(dir: &Path, wallet_id: &str) -> std::io::Result<()> {
+    let quarantine_dir = dir.join("quarantine");
+    fs::create_dir_all(&quarantine_dir)?;
+
+    let file_name = format!("{}.json", wallet_id);
+    fs::rename(dir.join(&file_name), quarantine_dir.join(file_name))
+}
+
+/// Write the sends of `wallet_id` still counting against its daily limit to `{dir}/{id}.json`.
+pub fn persist_spends(dir: &Path, wallet_id: &str, sends: &[SpendRecord]) -> std::io::Result<()> {
+    write_json(dir, wallet_id, &sends)
+}
+
+/// Read the recent sends of every wallet from `dir`, keyed by wallet ID. Unreadable files are
+/// logged and skipped, like in [`load_wallets`].
+pub fn load_spends(dir: &Path) -> std::io::Result<HashMap<String, Vec<SpendRecord>>> {
+    let mut spends = HashMap::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let Some(wallet_id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        match fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| Ok(serde_json::from_slice::<Vec<SpendRecord>>(&contents)?))
+        {
+            Ok(records) => {
+                spends.insert(wallet_id.to_string(), records);
+            }
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "Skipping bad spends file");
+            }
+        }
+    }
+
+    Ok(spends)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{WalletKeys, WalletSeed};
+
+    #[test]
+    fn loads_persisted_wallets() {
+        let dir = std::env::temp_dir().join(format!("wallets-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+
+        let wallets = ["first", "second"].map(|id| WalletInfo {
+            id: id.to_string(),
+            keys: WalletKeys::Full {
+                seed: WalletSeed::Plain(format!("{}-seed", id)),
+            },
+            owner: None,
+            unilateral_exit_delay: None,
+        });
+        for wallet_info in wallets.iter() {
+            persist_wallet(&dir, wallet_info).unwrap();
+        }
+        fs::write(dir.join("broken.json"), "not json").unwrap();
+
+        let loaded = load_wallets(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        for wallet_info in wallets.iter() {
+            let WalletKeys::Full {
+                seed: WalletSeed::Plain(seed),
+            } = &loaded[&wallet_info.id].keys
+            else {
+                panic!("seed should be stored in plain text");
+            };
+            assert_eq!(*seed, format!("{}-seed", wallet_info.id));
+        }
+    }
+}
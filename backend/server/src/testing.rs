@@ -0,0 +1,225 @@
+//! Helpers shared by the handler tests.
+
+use bitcoin::key::Secp256k1;
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+use bitcoin::{Address, Network, Sequence};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::types::*;
+
+/// ID of the wallet a test sets up, in the UUID form handlers require.
+pub(crate) const WALLET_ID: &str = "6f1c8a52-3d4e-4b7a-9c0e-2f5d8b1a7e34";
+
+/// State for a regtest Ark server at `ark_server_url` whose info has already been fetched.
+///
+/// Requests to the Ark server are not retried, so tests against unreachable servers fail fast.
+pub(crate) fn app_state(ark_server_url: &str, esplora_url: &str) -> AppState {
+    let config = toml::from_str::<Config>(&format!(
+        "ark_server_url = \"{ark_server_url}\"\n\
+         esplora_url = \"{esplora_url}\"\n\
+         ark_retry_max_attempts = 1"
+    ))
+    .unwrap();
+
+    let secp = Secp256k1::new();
+    let server_sk = SecretKey::from_slice(&[1u8; 32]).unwrap();
+    let server_pk = PublicKey::from_secret_key(&secp, &server_sk);
+    let server_info = ark_core::server::Info {
+        pk: server_pk,
+        vtxo_tree_expiry: Sequence::from_512_second_intervals(2),
+        unilateral_exit_delay: Sequence::from_512_second_intervals(2),
+        round_interval: 10,
+        network: Network::Regtest,
+        dust: bitcoin::Amount::from_sat(330),
+        boarding_descriptor_template: String::new(),
+        vtxo_descriptor_templates: Vec::new(),
+        forfeit_address: Address::p2tr(
+            &secp,
+            server_pk.x_only_public_key().0,
+            None,
+            Network::Regtest,
+        ),
+    };
+
+    AppState {
+        wallets: Mutex::new(HashMap::new()),
+        ark_client: ArkClient::new(
+            config.ark_server_url.clone(),
+            config.ark_retry_policy(),
+            config.ark_timeouts(),
+            None,
+        ),
+        esplora_client: Mutex::new(Some(
+            EsploraClient::new(std::slice::from_ref(&config.esplora_url)).unwrap(),
+        )),
+        chain_tip: TtlCache::new("chain_tip", Duration::from_secs(10)),
+        fee_rates: TtlCache::new("fee_rates", Duration::from_secs(60)),
+        outpoints: OutpointCache::new(Duration::from_secs(config.outpoint_cache_ttl_secs)),
+        events: EventLog::new(config.event_log_capacity),
+        spend_locks: SpendLocks::default(),
+        settle_jobs: SettleJobs::default(),
+        unsigned_sends: UnsignedSends::default(),
+        spend_limits: SpendLimits::new(None, None, None, HashMap::new()),
+        idempotency_keys: IdempotencyKeys::new(Duration::from_secs(
+            config.idempotency_key_ttl_secs,
+        )),
+        incoming_watchers: IncomingWatchers::default(),
+        metrics: Metrics::new(),
+        rate_limiter: RateLimiter::new(config.rate_limit.clone()),
+        background: BackgroundTasks::default(),
+        server_info: Mutex::new(Some(server_info)),
+        config,
+    }
+}
+
+/// How a wallet set up by a test holds its key.
+#[derive(Clone, Copy)]
+pub(crate) enum TestKeys {
+    Full,
+    WatchOnly,
+}
+
+/// Store a wallet under [`WALLET_ID`] owned by the secret key `[byte; 32]`, and return that key.
+pub(crate) fn insert_wallet(state: &AppState, byte: u8, keys: TestKeys) -> SecretKey {
+    insert_wallet_with_id(state, WALLET_ID, byte, keys)
+}
+
+/// Like [`insert_wallet`], under the wallet ID `id`.
+pub(crate) fn insert_wallet_with_id(
+    state: &AppState,
+    id: &str,
+    byte: u8,
+    keys: TestKeys,
+) -> SecretKey {
+    let sk = SecretKey::from_slice(&[byte; 32]).unwrap();
+    let keys = match keys {
+        TestKeys::Full => WalletKeys::Full {
+            seed: WalletSeed::new(&sk, None).unwrap(),
+        },
+        TestKeys::WatchOnly => WalletKeys::WatchOnly {
+            pubkey: sk.x_only_public_key(&Secp256k1::new()).0.to_string(),
+        },
+    };
+    state.wallets.lock().unwrap().insert(
+        id.to_string(),
+        WalletInfo {
+            id: id.to_string(),
+            keys,
+            owner: Some(sk.x_only_public_key(&Secp256k1::new()).0.to_string()),
+            unilateral_exit_delay: None,
+        },
+    );
+
+    sk
+}
+
+/// An Ark server that lists the VTXOs `vtxos`, given as outpoint and amount, for every address
+/// and never answers any other request. A send against it hangs once it submits its redeem
+/// transaction, with its inputs already reserved. Returns the server's URL.
+pub(crate) async fn ark_server_listing(vtxos: &[(bitcoin::OutPoint, u64)]) -> String {
+    let response = grpc_message(&list_vtxos_response(vtxos));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let response = response.clone();
+            tokio::spawn(async move {
+                let Ok(mut connection) = h2::server::handshake(socket).await else {
+                    return;
+                };
+                while let Some(Ok((request, respond))) = connection.accept().await {
+                    if request.uri().path() == "/ark.v1.ExplorerService/ListVtxos" {
+                        answer_grpc(respond, response.clone());
+                    } else {
+                        // Keep the request open for as long as the connection lives.
+                        tokio::spawn(async move {
+                            let _respond = respond;
+                            std::future::pending::<()>().await
+                        });
+                    }
+                }
+            });
+        }
+    });
+
+    url
+}
+
+/// An Esplora backend that knows no transactions. Returns its URL.
+pub(crate) fn empty_esplora() -> String {
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    let server = HttpServer::new(|| {
+        App::new().default_service(web::to(|| async { HttpResponse::Ok().json(Vec::<()>::new()) }))
+    })
+    .workers(1)
+    .bind("127.0.0.1:0")
+    .unwrap();
+    let url = format!("http://{}", server.addrs()[0]);
+    tokio::spawn(server.run());
+
+    url
+}
+
+fn answer_grpc(mut respond: h2::server::SendResponse<bytes::Bytes>, message: bytes::Bytes) {
+    let response = http::Response::builder()
+        .header("content-type", "application/grpc")
+        .body(())
+        .unwrap();
+    let Ok(mut stream) = respond.send_response(response, false) else {
+        return;
+    };
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+    let _ = stream.send_data(message, false);
+    let _ = stream.send_trailers(trailers);
+}
+
+/// `message` framed as a gRPC message: uncompressed, with its length in front.
+fn grpc_message(message: &[u8]) -> bytes::Bytes {
+    let mut framed = vec![0];
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend_from_slice(message);
+    framed.into()
+}
+
+/// A protobuf-encoded `ark.v1.ListVtxosResponse` with `vtxos` as its spendable VTXOs.
+fn list_vtxos_response(vtxos: &[(bitcoin::OutPoint, u64)]) -> Vec<u8> {
+    fn varint(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+    fn bytes_field(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+        varint(buf, field << 3 | 2);
+        varint(buf, value.len() as u64);
+        buf.extend_from_slice(value);
+    }
+    fn varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+        varint(buf, field << 3);
+        varint(buf, value);
+    }
+
+    let expire_at = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() + 24 * 60 * 60;
+    let mut response = Vec::new();
+    for (outpoint, amount) in vtxos {
+        let mut encoded_outpoint = Vec::new();
+        bytes_field(&mut encoded_outpoint, 1, outpoint.txid.to_string().as_bytes());
+        varint_field(&mut encoded_outpoint, 2, outpoint.vout.into());
+
+        let mut vtxo = Vec::new();
+        bytes_field(&mut vtxo, 1, &encoded_outpoint);
+        bytes_field(&mut vtxo, 3, outpoint.txid.to_string().as_bytes());
+        varint_field(&mut vtxo, 5, expire_at);
+        varint_field(&mut vtxo, 9, *amount);
+
+        bytes_field(&mut response, 1, &vtxo);
+    }
+
+    response
+}
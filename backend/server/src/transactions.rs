@@ -1,87 +1,1004 @@
-use actix_web::{post, web, HttpResponse, Responder};
-use bitcoin::{Amount, Txid, XOnlyPublicKey};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::middleware::from_fn;
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::script::Instruction;
+use bitcoin::{Amount, Psbt, TapLeafHash, Transaction, Txid, Witness, XOnlyPublicKey};
 use bitcoin::key::{Keypair, Secp256k1};
 use bitcoin::secp256k1::{Message, PublicKey, SecretKey, schnorr};
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use std::str::FromStr;
-use std::process::Command;
+use std::sync::Mutex;
+use tokio::process::Command;
 use futures::StreamExt;
-use rand::thread_rng;
-
+use tracing::Instrument;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::address::canonical_address;
+use crate::chain::current_tip;
+use crate::idempotency::{idempotency, mark_submitted};
+use crate::metrics::InFlight;
+use crate::request_id::record_wallet_id;
+use crate::signer::{InMemorySigner, Signer};
+use crate::unsigned_sends::UNSIGNED_SEND_TTL;
 use crate::types::*;
-use ark_core::{ArkAddress, BoardingOutput, Vtxo};
-use ark_core::vtxo::list_virtual_tx_outpoints;
-use ark_core::boarding_output::list_boarding_outpoints;
+use crate::wallet::{
+    compute_wallet_balances, find_wallet, forget_outpoints, owner_pk, signing_key, wallet_outputs,
+    WalletOutpoints, EXPIRED_FUNDS_HINT,
+};
+use ark_core::ArkAddress;
+use ark_core::vtxo::{list_virtual_tx_outpoints, Vtxo};
 use ark_core::coin_select::select_vtxos;
-use ark_core::redeem::{self, build_redeem_transaction, sign_redeem_transaction};
+use ark_core::redeem::{
+    self, build_redeem_transaction_with_lock_time, estimate_redeem_fee, estimate_redeem_vsize,
+    sign_redeem_transaction, REDEEM_TX_FEE_RATE,
+};
 use ark_core::round::{self, create_and_sign_forfeit_txs, generate_nonce_tree, sign_round_psbt, sign_vtxo_tree};
-use ark_core::server::{RoundInput, RoundOutput, RoundStreamEvent};
+use ark_core::server::{RoundInput, RoundOutput, RoundStreamEvent, VtxoOutPoint};
 use ark_core::ExplorerUtxo;
 
-#[post("/send_to_ark_address")]
+#[post("/send_to_ark_address", wrap = "from_fn(idempotency)")]
 pub async fn send_to_ark_address(
     data: web::Data<AppState>,
+    query: web::Query<SendQuery>,
     req: web::Json<SendToArkAddressRequest>,
 ) -> impl Responder {
-    let wallets = data.wallets.lock().unwrap();
-    let wallet_info = match wallets.get(&req.wallet_id) {
-        Some(info) => info.clone(),
-        None => return HttpResponse::NotFound().body("Wallet not found"),
+    let wallet_info = match find_wallet(&data, &req.wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
     };
 
-    let server_info = match data.server_info.as_ref() {
-        Some(info) => info.lock().unwrap().clone(),
-        None => return HttpResponse::InternalServerError().body("Server not connected"),
+    let destination_address = match ArkAddress::decode(&req.address) {
+        Ok(address) => address,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid Ark address"),
+    };
+    if let Err(response) = check_address_network(&data, &destination_address) {
+        return response;
+    }
+    if let Err(response) =
+        check_output(&data, destination_address.encode(), Amount::from_sat(req.amount))
+    {
+        return response;
+    }
+
+    let recipients = [Recipient {
+        address: req.address.clone(),
+        ark_address: destination_address,
+        amount: Amount::from_sat(req.amount),
+    }];
+
+    if query.dry_run {
+        return match preview_redeem(&data, &wallet_info, &recipients, req.coin_selection).await {
+            Ok(preview) => HttpResponse::Ok().json(preview),
+            Err(response) => response,
+        };
+    }
+
+    let _spend_guard = match begin_spend(&data, &wallet_info.id, total_amount(&recipients)) {
+        Ok(guard) => guard,
+        Err(response) => return response,
+    };
+
+    let sent = match send_redeem(&data, &wallet_info, &recipients, req.coin_selection).await {
+        Ok(sent) => sent,
+        Err(response) => return response,
+    };
+
+    HttpResponse::Ok().json(SendToArkAddressResponse {
+        wallet_id: wallet_info.id,
+        to_address: req.address.clone(),
+        amount: req.amount,
+        amount_btc: btc(req.amount),
+        txid: sent.txid,
+        fee_paid: sent.fee_paid.to_sat(),
+        fee_paid_btc: btc(sent.fee_paid.to_sat()),
+        selected_outpoints: sent.selected_outpoints,
+    })
+}
+
+#[post("/send_batch", wrap = "from_fn(idempotency)")]
+pub async fn send_batch(
+    data: web::Data<AppState>,
+    req: web::Json<SendBatchRequest>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &req.wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    // Every address is checked before any VTXO is looked at, let alone signed for.
+    let recipients = match parse_recipients(&data, &req.outputs) {
+        Ok(recipients) => recipients,
+        Err(response) => return response,
+    };
+
+    let _spend_guard = match begin_spend(&data, &wallet_info.id, total_amount(&recipients)) {
+        Ok(guard) => guard,
+        Err(response) => return response,
+    };
+
+    let sent = match send_redeem(&data, &wallet_info, &recipients, req.coin_selection).await {
+        Ok(sent) => sent,
+        Err(response) => return response,
+    };
+
+    let amount = total_amount(&recipients).to_sat();
+    HttpResponse::Ok().json(SendBatchResponse {
+        wallet_id: wallet_info.id,
+        outputs: req.outputs.iter().map(SendOutputEntry::from).collect(),
+        amount,
+        amount_btc: btc(amount),
+        txid: sent.txid,
+        fee_paid: sent.fee_paid.to_sat(),
+        fee_paid_btc: btc(sent.fee_paid.to_sat()),
+        selected_outpoints: sent.selected_outpoints,
+    })
+}
+
+/// Select inputs and build the redeem transaction for a send, for an external signer to sign.
+///
+/// The server keeps track of the transaction it built; [`submit_signed_psbt`] only accepts that
+/// exact transaction back. Its inputs are reserved until then, until [`cancel_redeem`] is called
+/// with the returned `reservation_id`, or until it expires.
+#[post("/build_unsigned_send")]
+pub async fn build_unsigned_send(
+    data: web::Data<AppState>,
+    req: web::Json<SendBatchRequest>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &req.wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let _spend_guard = match lock_wallet(&data, &wallet_info.id) {
+        Ok(guard) => guard,
+        Err(response) => return response,
+    };
+
+    let recipients = match parse_recipients(&data, &req.outputs) {
+        Ok(recipients) => recipients,
+        Err(response) => return response,
+    };
+
+    let (vtxos, vtxo_inputs) =
+        match select_inputs(&data, &wallet_info, &recipients, req.coin_selection).await {
+            Ok(selected) => selected,
+            Err(response) => return response,
+        };
+
+    let change_address = match vtxos.change_address_for(&recipients, &vtxo_inputs) {
+        Ok(change_address) => change_address,
+        Err(response) => return response,
+    };
+
+    let psbt = match build_redeem(&data, &recipients, &vtxo_inputs, change_address).await {
+        Ok(psbt) => psbt,
+        Err(response) => return response,
+    };
+
+    let total_input: Amount = vtxo_inputs.iter().map(|input| input.amount()).sum();
+    let total_output: Amount = psbt.unsigned_tx.output.iter().map(|output| output.value).sum();
+    let fee = total_input.checked_sub(total_output).unwrap_or(Amount::ZERO);
+
+    let reservation_id =
+        data.unsigned_sends.insert(&wallet_info.id, &psbt.unsigned_tx, recipients, total_input);
+
+    HttpResponse::Ok().json(UnsignedSendResponse {
+        wallet_id: wallet_info.id,
+        reservation_id,
+        txid: psbt.unsigned_tx.compute_txid().to_string(),
+        selected_outpoints: vtxo_inputs
+            .iter()
+            .map(|input| input.outpoint().to_string())
+            .collect(),
+        total_input: total_input.to_sat(),
+        total_input_btc: btc(total_input.to_sat()),
+        fee: fee.to_sat(),
+        fee_btc: btc(fee.to_sat()),
+        expires_in_secs: UNSIGNED_SEND_TTL.as_secs(),
+        psbt: psbt.to_string(),
+    })
+}
+
+/// Submit a redeem transaction built by [`build_unsigned_send`] and signed by an external signer.
+#[post("/submit_signed_psbt", wrap = "from_fn(idempotency)")]
+pub async fn submit_signed_psbt(
+    data: web::Data<AppState>,
+    req: web::Json<SubmitSignedPsbtRequest>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &req.wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let psbt = match req.psbt.parse::<Psbt>() {
+        Ok(psbt) => psbt,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid PSBT: expected base64"),
+    };
+
+    // Taken before looking the send up, so that it cannot be cancelled while it is submitted.
+    let _spend_guard = match lock_wallet(&data, &wallet_info.id) {
+        Ok(guard) => guard,
+        Err(response) => return response,
+    };
+
+    let Some(unsigned_send) = data.unsigned_sends.get(&wallet_info.id, &psbt.unsigned_tx) else {
+        return HttpResponse::BadRequest().body(
+            "PSBT does not match a transaction built by /build_unsigned_send for this wallet, \
+             or it expired or was cancelled: its inputs and outputs must not be changed",
+        );
+    };
+
+    if let Some(i) = psbt.inputs.iter().position(|input| input.tap_script_sigs.is_empty()) {
+        return HttpResponse::BadRequest().body(format!("Input {} is not signed", i));
+    }
+    let recipients = &unsigned_send.recipients;
+    if let Err(response) = check_spend_limit(&data, &wallet_info.id, total_amount(recipients)) {
+        return response;
+    }
+    data.spend_locks.reserve(
+        &wallet_info.id,
+        psbt.unsigned_tx.input.iter().map(|input| input.previous_output),
+    );
+
+    let grpc_client = match data.ark_client.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().body("Failed to connect to Ark server"),
+    };
+
+    let unsigned_tx = psbt.unsigned_tx.clone();
+    let sent = match forward_redeem(
+        &data,
+        &wallet_info,
+        &grpc_client,
+        recipients,
+        unsigned_send.selected,
+        psbt,
+    )
+    .await
+    {
+        Ok(sent) => sent,
+        Err(response) => return response,
     };
+    data.unsigned_sends.remove(&unsigned_tx);
 
-    let esplora_client = match data.esplora_client.as_ref() {
-        Some(client) => client.lock().unwrap().clone(),
-        None => return HttpResponse::InternalServerError().body("Esplora client not available"),
+    HttpResponse::Ok().json(SendBatchResponse {
+        wallet_id: wallet_info.id,
+        outputs: recipients
+            .iter()
+            .map(|recipient| SendOutputEntry {
+                address: recipient.address.clone(),
+                amount: recipient.amount.to_sat(),
+                amount_btc: btc(recipient.amount.to_sat()),
+            })
+            .collect(),
+        amount: sent.amount.to_sat(),
+        amount_btc: btc(sent.amount.to_sat()),
+        txid: sent.txid,
+        fee_paid: sent.fee_paid.to_sat(),
+        fee_paid_btc: btc(sent.fee_paid.to_sat()),
+        selected_outpoints: sent.selected_outpoints,
+    })
+}
+
+/// Drop a transaction built by [`build_unsigned_send`] that will not be submitted, releasing the
+/// inputs it reserved before it expires.
+#[post("/cancel_redeem/{reservation_id}")]
+pub async fn cancel_redeem(
+    reservation_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let Some(wallet_id) = data.unsigned_sends.wallet_of(&reservation_id) else {
+        return HttpResponse::NotFound().body("No reservation with this ID, or it expired");
     };
 
-    let sk = match SecretKey::from_str(&wallet_info.seed) {
-        Ok(sk) => sk,
-        Err(_) => return HttpResponse::InternalServerError().body("Invalid wallet seed"),
+    // Taken before cancelling, so that a send being submitted cannot be cancelled under it.
+    let _spend_guard = match lock_wallet(&data, &wallet_id) {
+        Ok(guard) => guard,
+        Err(response) => return response,
+    };
+
+    let Some((txid, outpoints)) = data.unsigned_sends.cancel(&reservation_id) else {
+        return HttpResponse::NotFound().body("No reservation with this ID, or it expired");
+    };
+
+    tracing::info!(%wallet_id, reservation_id = %reservation_id, %txid, "Cancelled unsigned send");
+
+    HttpResponse::Ok().json(CancelRedeemResponse {
+        reservation_id: reservation_id.into_inner(),
+        wallet_id,
+        txid: txid.to_string(),
+        released_outpoints: outpoints.iter().map(|outpoint| outpoint.to_string()).collect(),
+    })
+}
+
+#[post("/send_max", wrap = "from_fn(idempotency)")]
+pub async fn send_max(data: web::Data<AppState>, req: web::Json<SendMaxRequest>) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &req.wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
     };
 
     let destination_address = match ArkAddress::decode(&req.address) {
         Ok(address) => address,
         Err(_) => return HttpResponse::BadRequest().body("Invalid Ark address"),
     };
+    if let Err(response) = check_address_network(&data, &destination_address) {
+        return response;
+    }
+    if let Err(response) = check_whitelisted(&data, destination_address.encode()) {
+        return response;
+    }
 
-    let amount = Amount::from_sat(req.amount);
+    let _spend_guard = match lock_wallet(&data, &wallet_info.id) {
+        Ok(guard) => guard,
+        Err(response) => return response,
+    };
 
-    let secp = Secp256k1::new();
-    let pk = PublicKey::from_secret_key(&secp, &sk);
+    let signer = match signing_key(&data, &wallet_info) {
+        Ok(sk) => InMemorySigner::new(&sk),
+        Err(response) => return response,
+    };
 
-    let vtxo = match Vtxo::new(
-        &secp,
-        server_info.pk.x_only_public_key().0,
-        pk.x_only_public_key().0,
-        vec![],
-        server_info.unilateral_exit_delay,
-        server_info.network,
-    ) {
-        Ok(vtxo) => vtxo,
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to create VTXO"),
+    let SpendableVtxos {
+        dust,
+        grpc_client,
+        spendable,
+        ..
+    } = match spendable_vtxos(&data, &wallet_info).await {
+        Ok(vtxos) => vtxos,
+        Err(response) => return response,
+    };
+
+    let vtxo_inputs = spendable
+        .into_iter()
+        .map(|(outpoint, vtxo)| redeem::VtxoInput::new(vtxo, outpoint.amount, outpoint.outpoint))
+        .collect::<Vec<_>>();
+
+    if vtxo_inputs.is_empty() {
+        return HttpResponse::BadRequest().body("No spendable VTXOs");
+    }
+
+    let fee = match estimate_redeem_fee(&vtxo_inputs, 1) {
+        Ok(fee) => fee,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to estimate redeem fee: {}", e));
+        }
+    };
+    let total: Amount = vtxo_inputs.iter().map(|input| input.amount()).sum();
+    let sendable = total.checked_sub(fee).unwrap_or(Amount::ZERO);
+    if sendable < dust {
+        return HttpResponse::BadRequest().json(InsufficientFundsResponse {
+            code: "BELOW_DUST",
+            error: format!(
+                "Spendable VTXOs total {} sats, leaving {} sats after an estimated fee of {} \
+                 sats, which is below the dust limit of {} sats",
+                total.to_sat(),
+                sendable.to_sat(),
+                fee.to_sat(),
+                dust.to_sat()
+            ),
+            selected: total.to_sat(),
+            selected_btc: btc(total.to_sat()),
+            required: (dust + fee).to_sat(),
+            required_btc: btc((dust + fee).to_sat()),
+            shortfall: (dust + fee - total).to_sat(),
+            shortfall_btc: btc((dust + fee - total).to_sat()),
+        });
+    }
+
+    // With no change output the redeem fee is deducted from the destination output, so it is
+    // given the whole input amount.
+    let recipients = [Recipient {
+        address: req.address.clone(),
+        ark_address: destination_address,
+        amount: total,
+    }];
+    if let Err(response) = check_spend_limit(&data, &wallet_info.id, total_amount(&recipients)) {
+        return response;
+    }
+
+    let sent = match submit_redeem(
+        &data,
+        &wallet_info,
+        &signer,
+        &grpc_client,
+        &recipients,
+        &vtxo_inputs,
+        None,
+    )
+    .await
+    {
+        Ok(sent) => sent,
+        Err(response) => return response,
+    };
+
+    HttpResponse::Ok().json(SendToArkAddressResponse {
+        wallet_id: wallet_info.id,
+        to_address: req.address.clone(),
+        amount: sent.amount.to_sat(),
+        amount_btc: btc(sent.amount.to_sat()),
+        txid: sent.txid,
+        fee_paid: sent.fee_paid.to_sat(),
+        fee_paid_btc: btc(sent.fee_paid.to_sat()),
+        selected_outpoints: sent.selected_outpoints,
+    })
+}
+
+/// Estimate the fee of sending `amount` from a wallet, by running coin selection and estimating
+/// the size of the signed redeem transaction. Works for watch-only wallets too.
+#[get("/estimate_fee/{wallet_id}")]
+pub async fn estimate_fee(
+    wallet_id: web::Path<String>,
+    query: web::Query<EstimateFeeQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, wallet_id.as_str()) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let server_info = match data.server_info() {
+        Some(info) => info,
+        None => return HttpResponse::InternalServerError().body("Server not connected"),
+    };
+
+    let amount = Amount::from_sat(query.amount);
+    if let Err(response) = check_dust(&data, amount) {
+        return response;
+    }
+
+    let owner = match owner_pk(&data, &wallet_info) {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    let (_, vtxo) = match wallet_outputs(&server_info, owner, wallet_info.unilateral_exit_delay) {
+        Ok(outputs) => outputs,
+        Err(response) => return response,
+    };
+
+    // Every output is P2TR, so paying ourselves weighs the same as paying anyone else.
+    let recipients = [Recipient {
+        address: vtxo.to_ark_address().encode(),
+        ark_address: vtxo.to_ark_address(),
+        amount,
+    }];
+
+    let (vtxos, vtxo_inputs) =
+        match select_inputs(&data, &wallet_info, &recipients, CoinSelection::Default).await {
+            Ok(selection) => selection,
+            Err(response) => return response,
+        };
+
+    let change = match redeem_change(&vtxo_inputs, &recipients, vtxos.dust) {
+        Ok(change) => change,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to estimate redeem fee: {}", e));
+        }
+    };
+    let num_outputs = recipients.len() + usize::from(change.is_some());
+    let (fee, vsize) = match estimate_redeem_fee(&vtxo_inputs, num_outputs)
+        .and_then(|fee| Ok((fee, estimate_redeem_vsize(&vtxo_inputs, num_outputs)?)))
+    {
+        Ok(estimate) => estimate,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to estimate redeem fee: {}", e));
+        }
+    };
+
+    // Without a change output, whatever is left over after the fee goes to the fee as well.
+    let total_input: Amount = vtxo_inputs.iter().map(|input| input.amount()).sum();
+    let dust_change = match change {
+        Some(_) => Amount::ZERO,
+        None => total_input.checked_sub(amount + fee).unwrap_or(Amount::ZERO),
+    };
+
+    HttpResponse::Ok().json(EstimateFeeResponse {
+        wallet_id: wallet_info.id,
+        amount: query.amount,
+        amount_btc: btc(query.amount),
+        inputs: vtxo_inputs.len(),
+        fee: fee.to_sat(),
+        fee_btc: btc(fee.to_sat()),
+        dust_change: dust_change.to_sat(),
+        dust_change_btc: btc(dust_change.to_sat()),
+        vsize: vsize as u64,
+        fee_rate_sat_per_vb: REDEEM_TX_FEE_RATE.to_sat_per_kwu() as f64 * 4.0 / 1000.0,
+        settle_fee: None,
+        settle_fee_reason: SETTLE_FEE_UNKNOWN,
+    })
+}
+
+/// Why [`estimate_fee`] cannot say what settling would cost.
+const SETTLE_FEE_UNKNOWN: &str = "Settlement fees depend on the minimum relay fee rate the Ark \
+                                  server only announces once a round is being finalized";
+
+/// Refuse an Ark address encoded for a different network than the Ark server's, before it gets
+/// anywhere near a transaction.
+fn check_address_network(data: &AppState, address: &ArkAddress) -> Result<(), HttpResponse> {
+    let Some(network) = data.server_info().map(|info| info.network) else {
+        // Without server info nothing can be sent anyway; that is reported further on.
+        return Ok(());
+    };
+
+    if address.is_valid_for_network(network) {
+        return Ok(());
+    }
+
+    Err(HttpResponse::BadRequest().json(AddressErrorResponse {
+        code: "ADDRESS_NETWORK_MISMATCH",
+        error: format!(
+            "Ark address {} is not for the server's network ({})",
+            address, network
+        ),
+    }))
+}
+
+/// Refuse with 400 a zero `amount`, or one below the Ark server's dust limit, which could not be a
+/// VTXO or an on-chain output.
+fn check_dust(data: &AppState, amount: Amount) -> Result<(), HttpResponse> {
+    let Some(dust) = data.server_info().map(|info| info.dust) else {
+        // Without server info nothing can be sent anyway; that is reported further on.
+        return Ok(());
+    };
+
+    if amount > Amount::ZERO && amount >= dust {
+        return Ok(());
+    }
+
+    let error = match amount {
+        Amount::ZERO => format!(
+            "Amount must be greater than zero, and at least the dust limit of {} sats",
+            dust.to_sat()
+        ),
+        _ => format!(
+            "Amount of {} sats is below the dust limit of {} sats",
+            amount.to_sat(),
+            dust.to_sat()
+        ),
+    };
+    Err(HttpResponse::BadRequest().json(AmountBelowDustResponse {
+        code: "AMOUNT_BELOW_DUST",
+        error,
+        amount: amount.to_sat(),
+        amount_btc: btc(amount.to_sat()),
+        dust: dust.to_sat(),
+        dust_btc: btc(dust.to_sat()),
+    }))
+}
+
+/// Refuse with 403 a destination that is not in `send_address_whitelist`, unless that is empty.
+/// `encoded` is the canonical encoding of an Ark or on-chain address.
+fn check_whitelisted(data: &AppState, encoded: String) -> Result<(), HttpResponse> {
+    let whitelist = &data.config.send_address_whitelist;
+    if whitelist.is_empty()
+        || whitelist
+            .iter()
+            .filter_map(|allowed| canonical_address(allowed))
+            .any(|allowed| allowed == encoded)
+    {
+        return Ok(());
+    }
+
+    Err(HttpResponse::Forbidden().json(AddressNotAllowedResponse {
+        code: "ADDRESS_NOT_WHITELISTED",
+        error: format!("Address {} is not in the send address whitelist", encoded),
+        address: encoded,
+    }))
+}
+
+/// Take the spend lock of `wallet_id`, or refuse with 409 if another spend holds it, or with 503
+/// once the server is shutting down.
+pub(crate) fn lock_wallet(data: &AppState, wallet_id: &str) -> Result<SpendGuard, HttpResponse> {
+    record_wallet_id(wallet_id);
+    if data.background.is_shutting_down() {
+        return Err(HttpResponse::ServiceUnavailable().body("Server is shutting down"));
+    }
+    data.spend_locks.try_lock(wallet_id).ok_or_else(|| {
+        HttpResponse::Conflict()
+            .body("A send or settlement is already in progress for this wallet")
+    })
+}
+
+/// The checks every send makes on each of its outputs, whether it pays an Ark or an on-chain
+/// address: the destination, in its canonical encoding, must be whitelisted and the amount must
+/// not be dust.
+fn check_output(data: &AppState, address: String, amount: Amount) -> Result<(), HttpResponse> {
+    check_whitelisted(data, address)?;
+    check_dust(data, amount)
+}
+
+/// Take the spend lock of `wallet_id` and refuse a send of `amount` that would go over its
+/// spending limits. Sends that know their amount before selecting inputs start here.
+fn begin_spend(
+    data: &AppState,
+    wallet_id: &str,
+    amount: Amount,
+) -> Result<SpendGuard, HttpResponse> {
+    let guard = lock_wallet(data, wallet_id)?;
+    check_spend_limit(data, wallet_id, amount)?;
+    Ok(guard)
+}
+
+/// Refuse with 403 a send of `amount` that would take `wallet_id` over a configured spending
+/// limit.
+fn check_spend_limit(data: &AppState, wallet_id: &str, amount: Amount) -> Result<(), HttpResponse> {
+    data.spend_limits.check(wallet_id, amount).map_err(|exceeded| {
+        HttpResponse::Forbidden().json(SpendLimitResponse {
+            code: "SPEND_LIMIT_EXCEEDED",
+            error: format!(
+                "Sending {} sats exceeds the {} spending limit; {} sats can be sent for now",
+                amount.to_sat(),
+                exceeded.limit,
+                exceeded.remaining.to_sat()
+            ),
+            limit: exceeded.limit,
+            remaining: exceeded.remaining.to_sat(),
+            remaining_btc: btc(exceeded.remaining.to_sat()),
+        })
+    })
+}
+
+/// One output of a send, with the address as the client gave it for the activity log.
+#[derive(Clone)]
+pub(crate) struct Recipient {
+    address: String,
+    ark_address: ArkAddress,
+    amount: Amount,
+}
+
+/// What a send pays `recipients` in total.
+fn total_amount(recipients: &[Recipient]) -> Amount {
+    recipients.iter().map(|recipient| recipient.amount).sum()
+}
+
+/// Decode and check the address of every output of a batch send.
+fn parse_recipients(
+    data: &AppState,
+    outputs: &[SendOutput],
+) -> Result<Vec<Recipient>, HttpResponse> {
+    if outputs.is_empty() {
+        return Err(HttpResponse::BadRequest().body("At least one output is required"));
+    }
+
+    let mut recipients = Vec::with_capacity(outputs.len());
+    for (i, output) in outputs.iter().enumerate() {
+        let ark_address = match ArkAddress::decode(&output.address) {
+            Ok(address) => address,
+            Err(_) => {
+                return Err(HttpResponse::BadRequest()
+                    .body(format!("Invalid Ark address in output {}: {}", i, output.address)));
+            }
+        };
+        check_address_network(data, &ark_address)?;
+        check_output(data, ark_address.encode(), Amount::from_sat(output.amount))?;
+        recipients.push(Recipient {
+            address: output.address.clone(),
+            ark_address,
+            amount: Amount::from_sat(output.amount),
+        });
+    }
+
+    Ok(recipients)
+}
+
+/// A redeem transaction the Ark server accepted.
+struct RedeemSent {
+    txid: String,
+    /// Total paid to the recipients.
+    amount: Amount,
+    fee_paid: Amount,
+    /// The VTXOs spent, as `txid:vout`.
+    selected_outpoints: Vec<String>,
+}
+
+/// Select VTXOs of `wallet_info` covering `recipients`, then build, sign and submit a redeem
+/// transaction paying each of them, with any change going back to the wallet.
+///
+/// Records a send event per recipient. Errors are returned as the response to send.
+async fn send_redeem(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+    recipients: &[Recipient],
+    coin_selection: CoinSelection,
+) -> Result<RedeemSent, HttpResponse> {
+    let signer = InMemorySigner::new(&signing_key(data, wallet_info)?);
+
+    let (vtxos, vtxo_inputs) = select_inputs(data, wallet_info, recipients, coin_selection).await?;
+    let change_address = vtxos.change_address_for(recipients, &vtxo_inputs)?;
+
+    submit_redeem(
+        data,
+        wallet_info,
+        &signer,
+        &vtxos.grpc_client,
+        recipients,
+        &vtxo_inputs,
+        change_address,
+    )
+    .await
+}
+
+/// Run coin selection and build the redeem transaction [`send_redeem`] would submit, without
+/// signing or submitting it.
+async fn preview_redeem(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+    recipients: &[Recipient],
+    coin_selection: CoinSelection,
+) -> Result<SendPreviewResponse, HttpResponse> {
+    let (vtxos, vtxo_inputs) = select_inputs(data, wallet_info, recipients, coin_selection).await?;
+
+    let change_address = vtxos.change_address_for(recipients, &vtxo_inputs)?;
+    let psbt = build_redeem(data, recipients, &vtxo_inputs, change_address).await?;
+
+    let total_input: Amount = vtxo_inputs.iter().map(|input| input.amount()).sum();
+    let total_output: Amount = psbt.unsigned_tx.output.iter().map(|output| output.value).sum();
+    let change = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .skip(recipients.len())
+        .map(|output| output.value)
+        .sum::<Amount>();
+    let fee = total_input.checked_sub(total_output).unwrap_or(Amount::ZERO);
+
+    Ok(SendPreviewResponse {
+        wallet_id: wallet_info.id.clone(),
+        outputs: recipients
+            .iter()
+            .map(|recipient| SendOutputEntry {
+                address: recipient.address.clone(),
+                amount: recipient.amount.to_sat(),
+                amount_btc: btc(recipient.amount.to_sat()),
+            })
+            .collect(),
+        selected_outpoints: vtxo_inputs
+            .iter()
+            .map(|input| input.outpoint().to_string())
+            .collect(),
+        total_input: total_input.to_sat(),
+        total_input_btc: btc(total_input.to_sat()),
+        change: change.to_sat(),
+        change_btc: btc(change.to_sat()),
+        change_omitted: change_address.is_none(),
+        fee: fee.to_sat(),
+        fee_btc: btc(fee.to_sat()),
+        psbt: psbt.to_string(),
+    })
+}
+
+/// Put `spendable` in the order `coin_selection` spends it in. Returns whether `select_vtxos`
+/// should sort by expiry itself instead, since it otherwise takes VTXOs in the order given.
+fn order_for_selection<T>(
+    spendable: &mut [(VtxoOutPoint, T)],
+    coin_selection: CoinSelection,
+) -> bool {
+    match coin_selection {
+        CoinSelection::Default => return true,
+        CoinSelection::Random => spendable.shuffle(&mut StdRng::from_entropy()),
+        CoinSelection::LargestFirst => {
+            spendable.sort_by_key(|(outpoint, _)| Reverse(outpoint.amount))
+        }
+        CoinSelection::SmallestFirst => spendable.sort_by_key(|(outpoint, _)| outpoint.amount),
+        CoinSelection::OldestFirst => spendable.sort_by_key(|(outpoint, _)| outpoint.created_at),
+    }
+
+    false
+}
+
+/// Pick VTXOs of `wallet_info` that cover `recipients` plus the fee of the redeem transaction
+/// paying them.
+async fn select_inputs(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+    recipients: &[Recipient],
+    coin_selection: CoinSelection,
+) -> Result<(SpendableVtxos, Vec<redeem::VtxoInput>), HttpResponse> {
+    let mut vtxos = spendable_vtxos(data, wallet_info).await?;
+    let mut spendable = std::mem::take(&mut vtxos.spendable);
+
+    let amount: Amount = recipients.iter().map(|recipient| recipient.amount).sum();
+
+    let sort_by_expiration_time = order_for_selection(&mut spendable, coin_selection);
+
+    let selection =
+        match select_with_fee(&spendable, recipients, vtxos.dust, sort_by_expiration_time) {
+            Ok(selection) => selection,
+            Err(e) => {
+                return Err(HttpResponse::InternalServerError()
+                    .body(format!("Failed to estimate redeem fee: {}", e)));
+            }
+        };
+    let Some((vtxo_inputs, fee)) = selection else {
+        return Err(HttpResponse::BadRequest().body("Insufficient funds or invalid amount"));
     };
 
-    let mut grpc_client = ark_grpc::Client::new(data.config.ark_server_url.clone());
-    if let Err(_) = grpc_client.connect().await {
-        return HttpResponse::InternalServerError().body("Failed to connect to Ark server");
+    let selected: Amount = vtxo_inputs.iter().map(|input| input.amount()).sum();
+    let required = amount + fee;
+    if selected < required {
+        return Err(HttpResponse::BadRequest().json(InsufficientFundsResponse {
+            code: "INSUFFICIENT_AFTER_DUST",
+            error: format!(
+                "Selected VTXOs total {} sats but {} sats are needed ({} sats plus an estimated \
+                 fee of {} sats)",
+                selected.to_sat(),
+                required.to_sat(),
+                amount.to_sat(),
+                fee.to_sat()
+            ),
+            selected: selected.to_sat(),
+            selected_btc: btc(selected.to_sat()),
+            required: required.to_sat(),
+            required_btc: btc(required.to_sat()),
+            shortfall: (required - selected).to_sat(),
+            shortfall_btc: btc((required - selected).to_sat()),
+        }));
+    }
+
+    Ok((vtxos, vtxo_inputs))
+}
+
+/// Run coin selection over `spendable` for `recipients` and the fee of the redeem transaction
+/// paying them, along with that fee.
+///
+/// Coin selection only looks at the amount it is given, so it is run again for the amount plus
+/// the fee of the inputs it picked until they cover both, or picking more is not possible. The
+/// last selection is returned even if it falls short; `None` means not even the amount is
+/// covered.
+fn select_with_fee(
+    spendable: &[(VtxoOutPoint, Vtxo)],
+    recipients: &[Recipient],
+    dust: Amount,
+    sort_by_expiration_time: bool,
+) -> Result<Option<(Vec<redeem::VtxoInput>, Amount)>, ark_core::Error> {
+    let amount: Amount = recipients.iter().map(|recipient| recipient.amount).sum();
+
+    let vtxo_outpoints = spendable
+        .iter()
+        .map(|(outpoint, _)| ark_core::coin_select::VtxoOutPoint {
+            outpoint: outpoint.outpoint,
+            expire_at: outpoint.expire_at,
+            amount: outpoint.amount,
+        })
+        .collect::<Vec<_>>();
+
+    let mut target = amount;
+    let mut selection = None;
+    while let Ok(selected_outpoints) =
+        select_vtxos(vtxo_outpoints.clone(), target, dust, sort_by_expiration_time)
+    {
+        let vtxo_inputs = spendable
+            .iter()
+            .filter(|(outpoint, _)| {
+                selected_outpoints
+                    .iter()
+                    .any(|o| o.outpoint == outpoint.outpoint)
+            })
+            .map(|(outpoint, vtxo)| {
+                redeem::VtxoInput::new(vtxo.clone(), outpoint.amount, outpoint.outpoint)
+            })
+            .collect::<Vec<_>>();
+        let fee = redeem_fee(&vtxo_inputs, recipients, dust)?;
+
+        let selected: Amount = vtxo_inputs.iter().map(|input| input.amount()).sum();
+        // The fee only grows with the inputs, so a target that does not grow picks nothing new.
+        let done = selected >= amount + fee || amount + fee <= target;
+        target = amount + fee;
+        selection = Some((vtxo_inputs, fee));
+        if done {
+            break;
+        }
+    }
+
+    Ok(selection)
+}
+
+/// What is needed to spend a wallet's VTXOs.
+pub(crate) struct SpendableVtxos {
+    dust: Amount,
+    change_address: ArkAddress,
+    grpc_client: ark_grpc::Client,
+    pub(crate) spendable: Vec<(VtxoOutPoint, Vtxo)>,
+}
+
+impl SpendableVtxos {
+    /// Where the change of a redeem transaction spending `vtxo_inputs` to `recipients` goes: the
+    /// wallet's own address, or nowhere if it would be below dust, leaving it to the fee.
+    fn change_address_for(
+        &self,
+        recipients: &[Recipient],
+        vtxo_inputs: &[redeem::VtxoInput],
+    ) -> Result<Option<&ArkAddress>, HttpResponse> {
+        match redeem_change(vtxo_inputs, recipients, self.dust) {
+            Ok(change) => Ok(change.map(|_| &self.change_address)),
+            Err(e) => Err(HttpResponse::InternalServerError()
+                .body(format!("Failed to estimate redeem fee: {}", e))),
+        }
+    }
+}
+
+/// The fee of a redeem transaction spending `vtxo_inputs` to `recipients`, with a change output
+/// unless the change would be below `dust`.
+fn redeem_fee(
+    vtxo_inputs: &[redeem::VtxoInput],
+    recipients: &[Recipient],
+    dust: Amount,
+) -> Result<Amount, ark_core::Error> {
+    if redeem_change(vtxo_inputs, recipients, dust)?.is_some() {
+        estimate_redeem_fee(vtxo_inputs, recipients.len() + 1)
+    } else {
+        estimate_redeem_fee(vtxo_inputs, recipients.len())
     }
+}
+
+/// The change a redeem transaction spending `vtxo_inputs` to `recipients` returns to the wallet,
+/// or `None` if it would be below `dust` and so cannot be a VTXO.
+fn redeem_change(
+    vtxo_inputs: &[redeem::VtxoInput],
+    recipients: &[Recipient],
+    dust: Amount,
+) -> Result<Option<Amount>, ark_core::Error> {
+    let fee = estimate_redeem_fee(vtxo_inputs, recipients.len() + 1)?;
+    let selected: Amount = vtxo_inputs.iter().map(|input| input.amount()).sum();
+    let amount: Amount = recipients.iter().map(|recipient| recipient.amount).sum();
+    let change = selected.checked_sub(amount + fee).unwrap_or(Amount::ZERO);
+
+    Ok((change >= dust).then_some(change))
+}
+
+/// Look up the VTXOs of `wallet_info` that can be spent in collaboration with the Ark server.
+///
+/// VTXOs reserved by a transaction built for an external signer are left out.
+pub(crate) async fn spendable_vtxos(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+) -> Result<SpendableVtxos, HttpResponse> {
+    let server_info = match data.server_info() {
+        Some(info) => info,
+        None => return Err(HttpResponse::InternalServerError().body("Server not connected")),
+    };
+
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
+        None => {
+            return Err(
+                HttpResponse::InternalServerError().body("Esplora client not available")
+            );
+        }
+    };
+
+    let owner = owner_pk(data, wallet_info)?;
+    let (_, vtxo) = wallet_outputs(&server_info, owner, wallet_info.unilateral_exit_delay)?;
+
+    let grpc_client = data.ark_client.get().await.map_err(|_| {
+        HttpResponse::InternalServerError().body("Failed to connect to Ark server")
+    })?;
 
     let vtxos = match grpc_client.list_vtxos(&vtxo.to_ark_address()).await {
         Ok(vtxos) => vtxos,
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to list VTXOs"),
+        Err(e) => {
+            data.ark_client.check(&e).await;
+            return Err(HttpResponse::InternalServerError().body("Failed to list VTXOs"));
+        }
     };
 
     let vtxo_address = vtxo.address();
-    let vtxo_explorer_outpoints = match esplora_client.find_outpoints(&vtxo_address).await {
+    let vtxo_explorer_outpoints = match data
+        .outpoints
+        .find_outpoints(&esplora_client, vtxo_address)
+        .await
+    {
         Ok(outpoints) => outpoints,
         Err(e) => {
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to fetch VTXO outpoints: {}", e));
+            return Err(HttpResponse::InternalServerError()
+                .body(format!("Failed to fetch VTXO outpoints: {}", e)));
         }
     };
 
@@ -103,86 +1020,265 @@ pub async fn send_to_ark_address(
     let virtual_tx_outpoints = match list_virtual_tx_outpoints(find_outpoints, spendable_vtxos) {
         Ok(outpoints) => outpoints,
         Err(_) => {
-            return HttpResponse::InternalServerError().body("Failed to get virtual tx outpoints");
+            return Err(
+                HttpResponse::InternalServerError().body("Failed to get virtual tx outpoints")
+            );
         }
     };
 
-    let vtxo_outpoints = virtual_tx_outpoints
-        .spendable
-        .iter()
-        .map(|(outpoint, _)| ark_core::coin_select::VtxoOutPoint {
-            outpoint: outpoint.outpoint,
-            expire_at: outpoint.expire_at,
-            amount: outpoint.amount,
-        })
-        .collect::<Vec<_>>();
-
-    let selected_outpoints = match select_vtxos(vtxo_outpoints, amount, server_info.dust, true) {
-        Ok(outpoints) => outpoints,
-        Err(_) => return HttpResponse::BadRequest().body("Insufficient funds or invalid amount"),
-    };
-
-    let vtxo_inputs = virtual_tx_outpoints
-        .spendable
-        .into_iter()
-        .filter(|(outpoint, _)| {
-            selected_outpoints
-                .iter()
-                .any(|o| o.outpoint == outpoint.outpoint)
-        })
-        .map(|(outpoint, vtxo)| redeem::VtxoInput::new(vtxo, outpoint.amount, outpoint.outpoint))
-        .collect::<Vec<_>>();
+    let reserved = data.unsigned_sends.reserved(&wallet_info.id);
+    let mut spendable = virtual_tx_outpoints.spendable;
+    spendable.retain(|(outpoint, _)| !reserved.contains(&outpoint.outpoint));
 
-    let change_address = vtxo.to_ark_address();
+    Ok(SpendableVtxos {
+        dust: server_info.dust,
+        change_address: vtxo.to_ark_address(),
+        grpc_client,
+        spendable,
+    })
+}
 
-    let secp = Secp256k1::new();
-    let kp = Keypair::from_secret_key(&secp, &sk);
+/// Build, sign and submit a redeem transaction spending `vtxo_inputs` to `recipients`.
+///
+/// Without a `change_address` everything left over after paying the recipients goes to the fee, and
+/// any part of the fee it does not cover is deducted from the recipients' outputs.
+async fn submit_redeem(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+    signer: &dyn Signer,
+    grpc_client: &ark_grpc::Client,
+    recipients: &[Recipient],
+    vtxo_inputs: &[redeem::VtxoInput],
+    change_address: Option<&ArkAddress>,
+) -> Result<RedeemSent, HttpResponse> {
+    data.spend_locks.reserve(
+        &wallet_info.id,
+        vtxo_inputs.iter().map(|input| input.outpoint()),
+    );
 
-    let mut redeem_psbt = match build_redeem_transaction(
-        &[(&destination_address, amount)],
-        Some(&change_address),
-        &vtxo_inputs,
-    ) {
-        Ok(psbt) => psbt,
-        Err(_) => {
-            return HttpResponse::InternalServerError().body("Failed to build redeem transaction");
-        }
-    };
+    let mut redeem_psbt = build_redeem(data, recipients, vtxo_inputs, change_address).await?;
 
     let sign_fn = |msg: Message| -> Result<(schnorr::Signature, XOnlyPublicKey), ark_core::Error> {
-        let sig = Secp256k1::new().sign_schnorr_no_aux_rand(&msg, &kp);
-        let pk = kp.x_only_public_key().0;
-        Ok((sig, pk))
+        Ok(signer.sign_schnorr(msg))
     };
 
     for (i, _) in vtxo_inputs.iter().enumerate() {
-        if let Err(_) = sign_redeem_transaction(sign_fn, &mut redeem_psbt, &vtxo_inputs, i) {
-            return HttpResponse::InternalServerError().body("Failed to sign redeem transaction");
+        if sign_redeem_transaction(sign_fn, &mut redeem_psbt, vtxo_inputs, i).is_err() {
+            return Err(
+                HttpResponse::InternalServerError().body("Failed to sign redeem transaction")
+            );
         }
     }
 
-    let psbt = match grpc_client.submit_redeem_transaction(redeem_psbt).await {
+    let selected = vtxo_inputs.iter().map(|input| input.amount()).sum();
+    forward_redeem(data, wallet_info, grpc_client, recipients, selected, redeem_psbt).await
+}
+
+/// Submit a signed redeem transaction spending VTXOs worth `selected` to `recipients`, and record
+/// the outcome in the activity log.
+async fn forward_redeem(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+    grpc_client: &ark_grpc::Client,
+    recipients: &[Recipient],
+    selected: Amount,
+    redeem_psbt: Psbt,
+) -> Result<RedeemSent, HttpResponse> {
+    let unsigned_tx = redeem_psbt.unsigned_tx.clone();
+    mark_submitted();
+    let mut psbt = match grpc_client.submit_redeem_transaction(redeem_psbt).await {
         Ok(psbt) => psbt,
-        Err(_) => {
-            return HttpResponse::InternalServerError().body("Failed to submit redeem transaction");
+        Err(e) => {
+            data.ark_client.check(&e).await;
+            let error = format!("Failed to submit redeem transaction: {}", e);
+            for (recipient, output) in recipients.iter().zip(unsigned_tx.output.iter()) {
+                data.events.record(
+                    &wallet_info.id,
+                    WalletEventKind::SendFailed {
+                        to_address: recipient.address.clone(),
+                        amount: output.value.to_sat(),
+                        amount_btc: btc(output.value.to_sat()),
+                        error: error.clone(),
+                    },
+                );
+            }
+            return Err(HttpResponse::InternalServerError().json(SendErrorResponse {
+                wallet_id: wallet_info.id.clone(),
+                submitted: false,
+                txid: None,
+                error,
+            }));
         }
     };
 
-    let txid = match psbt.extract_tx() {
-        Ok(tx) => tx.compute_txid().to_string(),
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to extract transaction"),
+    let tx = match psbt.clone().extract_tx() {
+        Ok(tx) => tx,
+        Err(e) => {
+            let finalized = psbt
+                .inputs
+                .iter()
+                .filter(|input| input.final_script_witness.is_some())
+                .count();
+            tracing::warn!(
+                error = %e,
+                inputs = psbt.inputs.len(),
+                finalized,
+                "Server returned a redeem PSBT that could not be extracted"
+            );
+
+            finalize_redeem_psbt(&mut psbt);
+
+            match psbt.clone().extract_tx() {
+                Ok(tx) => tx,
+                Err(e) => {
+                    // The server accepted the transaction, so the payment may well go through.
+                    // The TXID only commits to the unsigned transaction, so we can still hand it
+                    // out for the client to track instead of retrying the send.
+                    let txid = psbt.unsigned_tx.compute_txid().to_string();
+                    record_sent(data, wallet_info, recipients, &psbt.unsigned_tx);
+                    return Err(HttpResponse::BadGateway().json(SendErrorResponse {
+                        wallet_id: wallet_info.id.clone(),
+                        submitted: true,
+                        txid: Some(txid),
+                        error: format!(
+                            "Server returned a non-finalized redeem transaction: {}. The send \
+                             was submitted and may still succeed; do not retry blindly.",
+                            e
+                        ),
+                    }));
+                }
+            }
+        }
     };
 
-    HttpResponse::Ok().json(SendToArkAddressResponse {
-        wallet_id: wallet_info.id,
-        to_address: req.address.clone(),
-        amount: req.amount,
+    let txid = tx.compute_txid().to_string();
+    let output_value: Amount = tx.output.iter().map(|output| output.value).sum();
+    let fee_paid = selected.checked_sub(output_value).unwrap_or(Amount::ZERO);
+    let amount = tx.output.iter().take(recipients.len()).map(|output| output.value).sum();
+
+    record_sent(data, wallet_info, recipients, &tx);
+
+    Ok(RedeemSent {
         txid,
+        amount,
+        fee_paid,
+        selected_outpoints: tx
+            .input
+            .iter()
+            .map(|input| input.previous_output.to_string())
+            .collect(),
     })
 }
 
+/// Build the unsigned redeem transaction spending `vtxo_inputs` to `recipients`, using the
+/// configured locktime policy.
+async fn build_redeem(
+    data: &AppState,
+    recipients: &[Recipient],
+    vtxo_inputs: &[redeem::VtxoInput],
+    change_address: Option<&ArkAddress>,
+) -> Result<Psbt, HttpResponse> {
+    let lock_time = match data.config.redeem_locktime {
+        LocktimePolicy::Zero => LockTime::ZERO,
+        LocktimePolicy::CurrentHeight => {
+            let tip = current_tip(data).await?;
+            match LockTime::from_height(tip.height) {
+                Ok(lock_time) => lock_time,
+                Err(_) => {
+                    return Err(HttpResponse::InternalServerError()
+                        .body("Chain height is not a valid locktime"));
+                }
+            }
+        }
+    };
+
+    let outputs = recipients
+        .iter()
+        .map(|recipient| (&recipient.ark_address, recipient.amount))
+        .collect::<Vec<_>>();
+
+    match build_redeem_transaction_with_lock_time(
+        &outputs,
+        change_address,
+        vtxo_inputs,
+        lock_time,
+    ) {
+        Ok(psbt) => Ok(psbt),
+        Err(_) => {
+            Err(HttpResponse::InternalServerError().body("Failed to build redeem transaction"))
+        }
+    }
+}
+
+/// Record a send event per recipient, with the amount of the output paying it in `tx`, and count
+/// what the recipients were paid against the daily spending limit.
+fn record_sent(data: &AppState, wallet_info: &WalletInfo, recipients: &[Recipient], tx: &Transaction) {
+    let txid = tx.compute_txid().to_string();
+    let sent = tx.output.iter().take(recipients.len()).map(|output| output.value).sum();
+    data.spend_limits.record(&wallet_info.id, sent);
+
+    for (recipient, output) in recipients.iter().zip(tx.output.iter()) {
+        data.events.record(
+            &wallet_info.id,
+            WalletEventKind::SendSubmitted {
+                to_address: recipient.address.clone(),
+                amount: output.value.to_sat(),
+                amount_btc: btc(output.value.to_sat()),
+                txid: txid.clone(),
+            },
+        );
+    }
+}
+
+/// Finalize every script-path input that carries all of the signatures its tapscript needs.
+///
+/// The signatures are placed on the witness stack in the reverse order of the public keys in the
+/// script, followed by the script itself and its control block.
+fn finalize_redeem_psbt(psbt: &mut Psbt) {
+    for input in psbt.inputs.iter_mut() {
+        if input.final_script_witness.is_some() {
+            continue;
+        }
+
+        let Some((control_block, (script, leaf_version))) = input.tap_scripts.iter().next()
+        else {
+            continue;
+        };
+        let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+
+        let keys = script
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => {
+                    XOnlyPublicKey::from_slice(bytes.as_bytes()).ok()
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let signatures = keys
+            .iter()
+            .rev()
+            .map(|pk| input.tap_script_sigs.get(&(*pk, leaf_hash)))
+            .collect::<Option<Vec<_>>>();
+
+        let Some(signatures) = signatures else {
+            continue;
+        };
+
+        let mut witness = Witness::new();
+        for signature in signatures {
+            witness.push(signature.to_vec());
+        }
+        witness.push(script.as_bytes());
+        witness.push(control_block.serialize());
+
+        input.final_script_witness = Some(witness);
+    }
+}
+
 #[post("/faucet")]
-pub async fn faucet(req: web::Json<FaucetRequest>) -> impl Responder {
+pub async fn faucet(data: web::Data<AppState>, req: web::Json<FaucetRequest>) -> impl Responder {
     if req.onchain_address.is_empty() {
         return HttpResponse::BadRequest().json(FaucetResponse {
             success: false,
@@ -205,11 +1301,28 @@ pub async fn faucet(req: web::Json<FaucetRequest>) -> impl Responder {
         });
     }
 
-    let output = Command::new("nigiri")
-        .arg("faucet")
-        .arg(&req.onchain_address)
-        .arg(req.amount.to_string())
-        .output();
+    // The address ends up on a command line, so make sure it is nothing but an address.
+    if req.onchain_address.parse::<bitcoin::Address<_>>().is_err() {
+        return HttpResponse::BadRequest().json(FaucetResponse {
+            success: false,
+            address: req.onchain_address.clone(),
+            amount: req.amount,
+            txid: None,
+            error: Some("Invalid Bitcoin address".to_string()),
+            output: String::new(),
+        });
+    }
+
+    let amount = req.amount.to_string();
+    let args = data.config.faucet_args_template.iter().map(|arg| {
+        arg.replace("{address}", &req.onchain_address)
+            .replace("{amount}", &amount)
+    });
+
+    let output = Command::new(&data.config.faucet_command)
+        .args(args)
+        .output()
+        .await;
 
     match output {
         Ok(output) => {
@@ -238,127 +1351,503 @@ pub async fn faucet(req: web::Json<FaucetRequest>) -> impl Responder {
                 })
             }
         }
-        Err(e) => HttpResponse::InternalServerError().json(FaucetResponse {
-            success: false,
-            address: req.onchain_address.clone(),
+        Err(e) => HttpResponse::InternalServerError().json(FaucetResponse {
+            success: false,
+            address: req.onchain_address.clone(),
+            amount: req.amount,
+            txid: None,
+            error: Some(format!("Failed to execute command: {}", e)),
+            output: String::new(),
+        }),
+    }
+}
+
+fn extract_txid_from_output(output: &str) -> Option<String> {
+    let re = regex::Regex::new(r"[0-9a-f]{64}").ok()?;
+    re.find(output).map(|m| m.as_str().to_string())
+}
+
+#[post("/settle")]
+pub async fn settle_funds(
+    data: web::Data<AppState>,
+    query: web::Query<SettleQuery>,
+    req: web::Json<SettleRequest>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &req.wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let spend_guard = match lock_wallet(&data, &wallet_info.id) {
+        Ok(guard) => guard,
+        Err(response) => return response,
+    };
+
+    let settlement = match prepare_settlement(&data, &wallet_info, req.to_address.as_deref()).await
+    {
+        Ok(settlement) => settlement,
+        Err(response) => return response,
+    };
+    let has_expired = settlement.has_expired;
+
+    if query.background {
+        let (job_id, cancellation) = data.settle_jobs.create(&wallet_info.id);
+        let response = SettleJobResponse {
+            job_id: job_id.clone(),
+            wallet_id: wallet_info.id.clone(),
+        };
+
+        let settle_data = data.clone();
+        let span = tracing::Span::current();
+        data.background.spawn(
+            async move {
+                // The wallet stays locked until the round is over, not just until we respond.
+                let _spend_guard = spend_guard;
+
+                let settle_result = settlement
+                    .cancellable(cancellation)
+                    .run(|progress| settle_data.settle_jobs.progress(&job_id, progress))
+                    .await;
+
+                let (_, response) =
+                    finish_settlement(&settle_data, &wallet_info, settle_result, has_expired).await;
+                let status = match (response.txid, response.error) {
+                    (Some(txid), _) if response.success => SettleJobStatus::Done { txid },
+                    _ if response.code == Some(SETTLEMENT_CANCELLED) => SettleJobStatus::Cancelled,
+                    (_, error) => SettleJobStatus::Failed {
+                        error: error.unwrap_or_default(),
+                    },
+                };
+                settle_data.settle_jobs.update(&job_id, status);
+            }
+            .instrument(span),
+        );
+
+        return HttpResponse::Accepted().json(response);
+    }
+
+    let settle_result = settlement.run(|_| {}).await;
+
+    let (status, response) =
+        finish_settlement(&data, &wallet_info, settle_result, has_expired).await;
+    HttpResponse::build(status).json(response)
+}
+
+/// Exit funds to an on-chain address by joining a round with an on-chain output for `amount`.
+/// Whatever the wallet registers beyond that comes back to it as a new VTXO.
+#[post("/send_onchain", wrap = "from_fn(idempotency)")]
+pub async fn send_onchain(
+    data: web::Data<AppState>,
+    req: web::Json<SendOnchainRequest>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &req.wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let network = match data.server_info() {
+        Some(info) => info.network,
+        None => return HttpResponse::InternalServerError().body("Server not connected"),
+    };
+
+    let address = match req.onchain_address.parse::<bitcoin::Address<_>>() {
+        Ok(address) => address,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(AddressErrorResponse {
+                code: "INVALID_ADDRESS",
+                error: "Invalid Bitcoin address".to_string(),
+            });
+        }
+    };
+    let address = match address.require_network(network) {
+        Ok(address) => address,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(AddressErrorResponse {
+                code: "WRONG_NETWORK",
+                error: format!("Address is not valid on the server's network ({})", network),
+            });
+        }
+    };
+    let amount = Amount::from_sat(req.amount);
+    if let Err(response) = check_output(&data, address.to_string(), amount) {
+        return response;
+    }
+
+    let _spend_guard = match begin_spend(&data, &wallet_info.id, amount) {
+        Ok(guard) => guard,
+        Err(response) => return response,
+    };
+
+    let settlement = match prepare_settlement(&data, &wallet_info, None).await {
+        Ok(settlement) => settlement,
+        Err(response) => return response,
+    };
+    let has_expired = settlement.has_expired;
+
+    let available = settlement.spendable_amount();
+    if available < amount {
+        return HttpResponse::BadRequest().json(InsufficientFundsResponse {
+            code: "INSUFFICIENT_FUNDS",
+            error: format!(
+                "Wallet can settle {} sats, but {} sats were requested",
+                available.to_sat(),
+                amount.to_sat()
+            ),
+            selected: available.to_sat(),
+            selected_btc: btc(available.to_sat()),
+            required: amount.to_sat(),
+            required_btc: btc(amount.to_sat()),
+            shortfall: (amount - available).to_sat(),
+            shortfall_btc: btc((amount - available).to_sat()),
+        });
+    }
+
+    let settle_result = settlement.exit_to(address, amount).run(|_| {}).await;
+
+    let (status, response) =
+        finish_settlement(&data, &wallet_info, settle_result, has_expired).await;
+    if response.success {
+        data.spend_limits.record(&wallet_info.id, amount);
+    }
+    match (response.success, response.txid) {
+        (true, Some(txid)) => HttpResponse::Ok().json(SendOnchainResponse {
+            wallet_id: wallet_info.id,
+            onchain_address: req.onchain_address.clone(),
             amount: req.amount,
-            txid: None,
-            error: Some(format!("Failed to execute command: {}", e)),
-            output: String::new(),
+            amount_btc: btc(req.amount),
+            txid,
+            fee_paid: response.fee_paid.unwrap_or_default(),
+            fee_paid_btc: btc(response.fee_paid.unwrap_or_default()),
         }),
+        (_, txid) => HttpResponse::build(status).json(SettleResponse { txid, ..response }),
     }
 }
 
-fn extract_txid_from_output(output: &str) -> Option<String> {
-    let re = regex::Regex::new(r"[0-9a-f]{64}").ok()?;
-    re.find(output).map(|m| m.as_str().to_string())
-}
+/// Reset the expiry of a wallet's VTXOs by settling them back to the wallet in the next round.
+///
+/// Unlike `/settle`, boarding outputs are left alone, and with `max_vtxos` only the VTXOs closest
+/// to expiry are refreshed.
+#[post("/refresh/{wallet_id}")]
+pub async fn refresh_vtxos(
+    wallet_id: web::Path<String>,
+    data: web::Data<AppState>,
+    req: Option<web::Json<RefreshRequest>>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+    let max_vtxos = req.and_then(|req| req.into_inner().max_vtxos);
 
-#[post("/settle")]
-pub async fn settle_funds(data: web::Data<AppState>, req: web::Json<SettleRequest>) -> impl Responder {
-    let wallets = data.wallets.lock().unwrap();
-    let wallet_info = match wallets.get(&req.wallet_id) {
-        Some(info) => info.clone(),
-        None => return HttpResponse::NotFound().body("Wallet not found"),
+    let _spend_guard = match lock_wallet(&data, &wallet_info.id) {
+        Ok(guard) => guard,
+        Err(response) => return response,
     };
 
-    let server_info = match data.server_info.as_ref() {
-        Some(info) => info.lock().unwrap().clone(),
-        None => return HttpResponse::InternalServerError().body("Server not connected"),
+    let settlement = match prepare_settlement(&data, &wallet_info, None).await {
+        Ok(settlement) => settlement.only_vtxos(max_vtxos),
+        Err(response) => return response,
+    };
+    let has_expired = settlement.has_expired;
+    let previous_expiries = settlement.vtxo_expiries();
+
+    let settle_result = settlement.run(|_| {}).await;
+
+    let (status, response) =
+        finish_settlement(&data, &wallet_info, settle_result, has_expired).await;
+    match (response.success, response.txid) {
+        (true, Some(txid)) => {
+            let new_expiries = round_vtxo_expiries(&data, &wallet_info, &txid).await;
+            HttpResponse::Ok().json(RefreshResponse {
+                wallet_id: wallet_info.id,
+                txid,
+                refreshed: previous_expiries.len(),
+                previous_expiries,
+                new_expiries,
+            })
+        }
+        (_, txid) => HttpResponse::build(status).json(SettleResponse { txid, ..response }),
+    }
+}
+
+/// Merge a wallet's spendable VTXOs into a single one by settling them back to the wallet in the
+/// next round, so that later sends need fewer inputs.
+///
+/// Nothing happens if the wallet has fewer than `min_inputs` spendable VTXOs.
+#[post("/consolidate/{wallet_id}")]
+pub async fn consolidate_vtxos(
+    wallet_id: web::Path<String>,
+    data: web::Data<AppState>,
+    req: Option<web::Json<ConsolidateRequest>>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
     };
+    let min_inputs = req
+        .and_then(|req| req.into_inner().min_inputs)
+        .unwrap_or(DEFAULT_CONSOLIDATE_MIN_INPUTS);
 
-    let esplora_client = match data.esplora_client.as_ref() {
-        Some(client) => client.lock().unwrap().clone(),
-        None => return HttpResponse::InternalServerError().body("Esplora client not available"),
+    let _spend_guard = match lock_wallet(&data, &wallet_info.id) {
+        Ok(guard) => guard,
+        Err(response) => return response,
     };
 
-    let sk = match SecretKey::from_str(&wallet_info.seed) {
-        Ok(sk) => sk,
-        Err(_) => return HttpResponse::InternalServerError().body("Invalid wallet seed"),
+    let settlement = match prepare_settlement(&data, &wallet_info, None).await {
+        Ok(settlement) => settlement.only_vtxos(None),
+        Err(response) => return response,
     };
+    let has_expired = settlement.has_expired;
+    let inputs = settlement.vtxo_count();
 
-    let secp = Secp256k1::new();
-    let pk = PublicKey::from_secret_key(&secp, &sk);
+    if inputs < min_inputs.max(1) {
+        return HttpResponse::Ok().json(ConsolidateResponse {
+            wallet_id: wallet_info.id,
+            consolidated: 0,
+            txid: None,
+        });
+    }
 
-    let boarding_output = match BoardingOutput::new(
-        &secp,
-        server_info.pk.x_only_public_key().0,
-        pk.x_only_public_key().0,
-        server_info.unilateral_exit_delay,
-        server_info.network,
-    ) {
-        Ok(bo) => bo,
-        Err(_) => {
-            return HttpResponse::InternalServerError().body("Failed to create boarding output");
+    let settle_result = settlement.run(|_| {}).await;
+
+    let (status, response) =
+        finish_settlement(&data, &wallet_info, settle_result, has_expired).await;
+    match (response.success, response.txid) {
+        (true, Some(txid)) => HttpResponse::Ok().json(ConsolidateResponse {
+            wallet_id: wallet_info.id,
+            consolidated: inputs,
+            txid: Some(txid),
+        }),
+        (_, txid) => HttpResponse::build(status).json(SettleResponse { txid, ..response }),
+    }
+}
+
+/// When the wallet's VTXOs created in round `round_txid` expire, soonest first.
+///
+/// Empty if the VTXOs cannot be listed right now; the refresh itself already succeeded.
+async fn round_vtxo_expiries(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+    round_txid: &str,
+) -> Vec<i64> {
+    let vtxos = match spendable_vtxos(data, wallet_info).await {
+        Ok(vtxos) => vtxos,
+        Err(response) => {
+            tracing::warn!(
+                wallet_id = %wallet_info.id,
+                status = %response.status(),
+                "Could not list the refreshed VTXOs"
+            );
+            return Vec::new();
         }
     };
 
-    let vtxo = match Vtxo::new(
-        &secp,
-        server_info.pk.x_only_public_key().0,
-        pk.x_only_public_key().0,
-        vec![],
-        server_info.unilateral_exit_delay,
-        server_info.network,
-    ) {
-        Ok(vtxo) => vtxo,
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to create VTXO"),
-    };
+    let mut expiries = vtxos
+        .spendable
+        .iter()
+        .filter(|(outpoint, _)| outpoint.round_txid.to_string() == round_txid)
+        .map(|(outpoint, _)| outpoint.expire_at)
+        .collect::<Vec<_>>();
+    expiries.sort();
+    expiries
+}
+
+/// Everything needed to take part in a round for one wallet, gathered before the round starts.
+pub(crate) struct Settlement {
+    grpc_client: ark_grpc::Client,
+    server_info: ark_core::server::Info,
+    deterministic_nonces: bool,
+    sk: SecretKey,
+    vtxos: VirtualTxOutpoints,
+    boarding_outputs: BoardingOutpoints,
+    to_address: ArkAddress,
+    /// An on-chain output to pay in the round; only the rest goes to `to_address`.
+    onchain_output: Option<RoundOutput>,
+    /// Whether the wallet holds outputs that can no longer be settled.
+    pub(crate) has_expired: bool,
+    settlements_in_flight: prometheus::IntGauge,
+    cancellation: Option<SettleCancellation>,
+}
 
-    let mut grpc_client = ark_grpc::Client::new(data.config.ark_server_url.clone());
-    if let Err(_) = grpc_client.connect().await {
-        return HttpResponse::InternalServerError().body("Failed to connect to Ark server");
+impl Settlement {
+    /// Total value of the inputs the wallet will register in the round.
+    pub(crate) fn spendable_amount(&self) -> Amount {
+        self.vtxos.spendable_balance() + self.boarding_outputs.spendable_balance()
     }
 
-    let boarding_address = boarding_output.address();
-    let boarding_outpoints = match esplora_client.find_outpoints(&boarding_address).await {
-        Ok(outpoints) => outpoints,
-        Err(e) => {
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to fetch boarding outpoints: {}", e));
+    /// Pay `amount` to the on-chain `address` in the round, instead of settling everything to
+    /// the destination Ark address.
+    pub(crate) fn exit_to(mut self, address: bitcoin::Address, amount: Amount) -> Self {
+        self.onchain_output = Some(RoundOutput::new_on_chain(address, amount));
+        self
+    }
+
+    /// Register only the `max_vtxos` VTXOs closest to expiry, or all of them if `None`, and no
+    /// boarding outputs.
+    pub(crate) fn only_vtxos(mut self, max_vtxos: Option<usize>) -> Self {
+        self.boarding_outputs.spendable.clear();
+        self.vtxos.spendable.sort_by_key(|(outpoint, _)| outpoint.expire_at);
+        if let Some(max_vtxos) = max_vtxos {
+            self.vtxos.spendable.truncate(max_vtxos);
         }
-    };
+        self
+    }
 
-    let mut outpoint_cache = HashMap::new();
-    outpoint_cache.insert(boarding_address.to_string(), boarding_outpoints);
+    /// Let `cancellation` stop the settlement until it submits its forfeit transactions.
+    pub(crate) fn cancellable(mut self, cancellation: SettleCancellation) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
 
-    let find_outpoints =
-        move |address: &bitcoin::Address| -> Result<Vec<ExplorerUtxo>, ark_core::Error> {
-            let address_str = address.to_string();
-            match outpoint_cache.get(&address_str) {
-                Some(outpoints) => Ok(outpoints.clone()),
-                None => Ok(Vec::new()),
-            }
-        };
+    /// How many VTXOs the wallet will register in the round.
+    pub(crate) fn vtxo_count(&self) -> usize {
+        self.vtxos.spendable.len()
+    }
 
-    let vtxos = match grpc_client.list_vtxos(&vtxo.to_ark_address()).await {
-        Ok(vtxos) => vtxos,
-        Err(e) => {
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to list VTXOs: {}", e));
+    /// When each VTXO the wallet will register in the round expires, soonest first.
+    pub(crate) fn vtxo_expiries(&self) -> Vec<i64> {
+        let mut expiries = self
+            .vtxos
+            .spendable
+            .iter()
+            .map(|(outpoint, _)| outpoint.expire_at)
+            .collect::<Vec<_>>();
+        expiries.sort();
+        expiries
+    }
+
+    /// Randomness for the round's cosigner key and nonces.
+    ///
+    /// With `deterministic_nonces` it is seeded from [`DETERMINISTIC_NONCE_SEED`], the wallet's
+    /// key and everything the settlement registers. Settling the same outputs the same way is
+    /// reproducible, while every other settlement gets its own cosigner key and nonces.
+    fn rng(&self) -> StdRng {
+        if !self.deterministic_nonces {
+            return StdRng::from_entropy();
         }
-    };
 
-    let mut spendable_vtxos = HashMap::new();
-    spendable_vtxos.insert(vtxo.clone(), vtxos.spendable);
+        let mut engine = sha256::Hash::engine();
+        engine.input(&DETERMINISTIC_NONCE_SEED.to_be_bytes());
+        engine.input(&self.sk.secret_bytes());
+        for (outpoint, _) in self.vtxos.spendable.iter() {
+            engine.input(&bitcoin::consensus::serialize(&outpoint.outpoint));
+        }
+        for (outpoint, _, _) in self.boarding_outputs.spendable.iter() {
+            engine.input(&bitcoin::consensus::serialize(outpoint));
+        }
+        engine.input(self.to_address.encode().as_bytes());
+        if let Some(output) = &self.onchain_output {
+            engine.input(output.address().serialize().as_bytes());
+            engine.input(&output.amount().to_sat().to_be_bytes());
+        }
 
-    let virtual_tx_outpoints =
-        match list_virtual_tx_outpoints(find_outpoints.clone(), spendable_vtxos) {
-            Ok(outpoints) => outpoints,
-            Err(e) => {
-                return HttpResponse::InternalServerError()
-                    .body(format!("Failed to get virtual tx outpoints: {}", e));
-            }
-        };
+        StdRng::from_seed(sha256::Hash::from_engine(engine).to_byte_array())
+    }
 
-    let boarding_outpoints = match list_boarding_outpoints(find_outpoints, &[boarding_output]) {
-        Ok(outpoints) => outpoints,
-        Err(e) => {
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to get boarding outpoints: {}", e));
+    /// Join the next round, reporting each step of the round to `on_progress`.
+    pub(crate) async fn run(
+        mut self,
+        on_progress: impl Fn(SettleProgress),
+    ) -> Result<Option<SettleOutcome>, anyhow::Error> {
+        let _in_flight = InFlight::start(&self.settlements_in_flight);
+        mark_submitted();
+        let timeline = Mutex::new(Vec::new());
+        let cancellation = self.cancellation.take();
+        let mut rng = self.rng();
+        let settle = settle_internal(
+            &self.grpc_client,
+            &self.server_info,
+            &mut rng,
+            self.sk,
+            self.vtxos,
+            self.boarding_outputs,
+            self.to_address,
+            self.onchain_output,
+            cancellation.as_ref(),
+            |progress| {
+                timeline.lock().unwrap().push(SettleTimelineEntry::now(progress.clone()));
+                on_progress(progress)
+            },
+        );
+        // Dropping the settlement leaves the round; once it committed, cancelling is refused.
+        let outcome = match &cancellation {
+            Some(cancellation) => tokio::select! {
+                outcome = settle => outcome,
+                () = cancellation.cancelled() => Err(SettlementCancelled.into()),
+            },
+            None => settle.await,
+        }?;
+
+        Ok(outcome.map(|outcome| SettleOutcome {
+            timeline: timeline.into_inner().unwrap(),
+            ..outcome
+        }))
+    }
+}
+
+/// Look up what `wallet_info` can settle, and where to, defaulting to the wallet's own address.
+pub(crate) async fn prepare_settlement(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+    to_address: Option<&str>,
+) -> Result<Settlement, HttpResponse> {
+    let server_info = match data.server_info() {
+        Some(info) => info,
+        None => return Err(HttpResponse::InternalServerError().body("Server not connected")),
+    };
+
+    let to_address = match to_address.map(ArkAddress::decode) {
+        Some(Ok(address)) => {
+            check_address_network(data, &address)?;
+            check_whitelisted(data, address.encode())?;
+            Some(address)
+        }
+        Some(Err(_)) => {
+            return Err(HttpResponse::BadRequest().body("Invalid destination Ark address"));
+        }
+        None => None,
+    };
+
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
+        None => {
+            return Err(
+                HttpResponse::InternalServerError().body("Esplora client not available")
+            );
         }
     };
 
+    let sk = signing_key(data, wallet_info)?;
+
+    let secp = Secp256k1::new();
+    let pk = PublicKey::from_secret_key(&secp, &sk);
+
+    let owner = pk.x_only_public_key().0;
+    let (boarding_output, vtxo) =
+        wallet_outputs(&server_info, owner, wallet_info.unilateral_exit_delay)?;
+
+    let grpc_client = data.ark_client.get().await.map_err(|_| {
+        HttpResponse::InternalServerError().body("Failed to connect to Ark server")
+    })?;
+
+    let WalletOutpoints {
+        vtxos: mut virtual_tx_outpoints,
+        boarding: mut boarding_outpoints,
+        ..
+    } = compute_wallet_balances(data, &esplora_client, &grpc_client, boarding_output, &vtxo)
+        .await?;
+
+    // VTXOs reserved by a transaction built for an external signer are left for it.
+    let reserved = data.unsigned_sends.reserved(&wallet_info.id);
+    virtual_tx_outpoints
+        .spendable
+        .retain(|(outpoint, _)| !reserved.contains(&outpoint.outpoint));
+
+    if data.config.allow_pending_boarding_in_settle {
+        let pending = std::mem::take(&mut boarding_outpoints.pending);
+        boarding_outpoints.spendable.extend(pending);
+    }
+
     let vtxo_spendable = virtual_tx_outpoints.spendable_balance().to_sat();
     let vtxo_expired = virtual_tx_outpoints.expired_balance().to_sat();
     let boarding_spendable = boarding_outpoints.spendable_balance().to_sat();
@@ -366,94 +1855,288 @@ pub async fn settle_funds(data: web::Data<AppState>, req: web::Json<SettleReques
     let boarding_pending = boarding_outpoints.pending_balance().to_sat();
     let total_spendable = vtxo_spendable + boarding_spendable;
 
-    println!("=== SETTLEMENT BALANCE INFORMATION ===");
-    println!("Wallet ID: {}", wallet_info.id);
-    println!("VTXO spendable balance: {} sats", vtxo_spendable);
-    println!("VTXO expired balance: {} sats", vtxo_expired);
-    println!("Boarding spendable balance: {} sats", boarding_spendable);
-    println!("Boarding expired balance: {} sats", boarding_expired);
-    println!("Boarding pending balance: {} sats", boarding_pending);
-    println!("Total spendable balance: {} sats", total_spendable);
-    println!("=======================================");
-
-    println!(
-        "Number of spendable VTXOs: {}",
-        virtual_tx_outpoints.spendable.len()
-    );
-    println!(
-        "Number of spendable boarding outputs: {}",
-        boarding_outpoints.spendable.len()
+    tracing::debug!(
+        wallet_id = %wallet_info.id,
+        vtxo_spendable,
+        vtxo_expired,
+        boarding_spendable,
+        boarding_expired,
+        boarding_pending,
+        total_spendable,
+        spendable_vtxos = virtual_tx_outpoints.spendable.len(),
+        spendable_boarding_outputs = boarding_outpoints.spendable.len(),
+        "Settlement balances, in sats"
     );
 
-    let to_address = match &req.to_address {
-        Some(addr) => match ArkAddress::decode(addr) {
-            Ok(address) => address,
-            Err(_) => return HttpResponse::BadRequest().body("Invalid destination Ark address"),
+    let to_address = to_address.unwrap_or_else(|| vtxo.to_ark_address());
+
+    tracing::info!(wallet_id = %wallet_info.id, %to_address, "Settling");
+
+    data.events.record(
+        &wallet_info.id,
+        WalletEventKind::SettlementStarted {
+            to_address: to_address.to_string(),
         },
-        None => vtxo.to_ark_address(),
-    };
+    );
 
-    println!("Settlement destination address: {}", to_address);
+    let has_expired =
+        !virtual_tx_outpoints.expired.is_empty() || !boarding_outpoints.expired.is_empty();
 
-    let settle_result = settle_internal(
-        &grpc_client,
-        &server_info,
+    data.spend_locks.reserve(
+        &wallet_info.id,
+        virtual_tx_outpoints.spendable.iter().map(|(outpoint, _)| outpoint.outpoint),
+    );
+
+    Ok(Settlement {
+        grpc_client,
+        server_info,
+        deterministic_nonces: data.config.deterministic_nonces,
         sk,
-        virtual_tx_outpoints,
-        boarding_outpoints,
+        vtxos: virtual_tx_outpoints,
+        boarding_outputs: boarding_outpoints,
         to_address,
-    )
-    .await;
+        onchain_output: None,
+        has_expired,
+        settlements_in_flight: data.metrics.settlements_in_flight.clone(),
+        cancellation: None,
+    })
+}
+
+/// Record the outcome of a settlement and turn it into the response for the client.
+pub(crate) async fn finish_settlement(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+    settle_result: Result<Option<SettleOutcome>, anyhow::Error>,
+    has_expired: bool,
+) -> (StatusCode, SettleResponse) {
+    if let Err(e) = &settle_result
+        && let Some(e) = e.downcast_ref::<ark_grpc::Error>()
+    {
+        data.ark_client.check(e).await;
+    }
 
     match settle_result {
-        Ok(Some(txid)) => {
-            println!("Settlement successful! TXID: {}", txid);
-            HttpResponse::Ok().json(SettleResponse {
-                wallet_id: wallet_info.id,
-                success: true,
-                txid: Some(txid.to_string()),
-                error: None,
-            })
+        Ok(Some(SettleOutcome {
+            round_txid: txid,
+            round_id,
+            fee_paid,
+            round_fee,
+            vtxo_inputs,
+            boarding_inputs,
+            amount,
+            to_address,
+            timeline,
+        })) => {
+            tracing::info!(wallet_id = %wallet_info.id, %txid, "Settlement succeeded");
+            forget_outpoints(data, wallet_info);
+            data.events.record(
+                &wallet_info.id,
+                WalletEventKind::SettlementFinished {
+                    txid: txid.to_string(),
+                },
+            );
+            (
+                StatusCode::OK,
+                SettleResponse {
+                    wallet_id: wallet_info.id.clone(),
+                    success: true,
+                    txid: Some(txid.to_string()),
+                    fee_paid: Some(fee_paid.to_sat()),
+                    fee_paid_btc: Some(btc(fee_paid.to_sat())),
+                    round_fee: round_fee.map(|fee| fee.to_sat()),
+                    round_fee_btc: round_fee.map(|fee| btc(fee.to_sat())),
+                    round_id: Some(round_id),
+                    vtxo_inputs: Some(vtxo_inputs),
+                    boarding_inputs: Some(boarding_inputs),
+                    amount: Some(amount.to_sat()),
+                    amount_btc: Some(btc(amount.to_sat())),
+                    to_address: Some(to_address.to_string()),
+                    timeline,
+                    code: None,
+                    error: None,
+                },
+            )
+        }
+        Ok(None) if has_expired => {
+            tracing::warn!(
+                wallet_id = %wallet_info.id,
+                "Settlement failed: only expired outputs available"
+            );
+            data.events.record(
+                &wallet_info.id,
+                WalletEventKind::SettlementFailed {
+                    error: "Only expired outputs available".to_string(),
+                },
+            );
+            (
+                StatusCode::OK,
+                settle_failure(wallet_info, None, EXPIRED_FUNDS_HINT.to_string()),
+            )
         }
         Ok(None) => {
-            println!("Settlement failed: No spendable outputs available");
-            HttpResponse::Ok().json(SettleResponse {
-                wallet_id: wallet_info.id,
-                success: false,
-                txid: None,
-                error: Some(
+            tracing::warn!(
+                wallet_id = %wallet_info.id,
+                "Settlement failed: no spendable outputs available"
+            );
+            data.events.record(
+                &wallet_info.id,
+                WalletEventKind::SettlementFailed {
+                    error: "No spendable outputs available".to_string(),
+                },
+            );
+            (
+                StatusCode::OK,
+                settle_failure(
+                    wallet_info,
+                    None,
                     "No boarding outputs or VTXOs can be settled at the moment".to_string(),
                 ),
-            })
+            )
+        }
+        Err(e) if e.is::<SettlementCancelled>() => {
+            tracing::info!(wallet_id = %wallet_info.id, "Settlement cancelled");
+            data.events.record(
+                &wallet_info.id,
+                WalletEventKind::SettlementFailed {
+                    error: e.to_string(),
+                },
+            );
+            (
+                StatusCode::OK,
+                settle_failure(wallet_info, Some(SETTLEMENT_CANCELLED), e.to_string()),
+            )
+        }
+        Err(e) if e.is::<ForfeitAddressChanged>() => {
+            tracing::error!(wallet_id = %wallet_info.id, error = %e, "Aborted settlement");
+            data.events.record(
+                &wallet_info.id,
+                WalletEventKind::SettlementFailed {
+                    error: e.to_string(),
+                },
+            );
+            (
+                StatusCode::BAD_GATEWAY,
+                settle_failure(wallet_info, Some("FORFEIT_ADDRESS_CHANGED"), e.to_string()),
+            )
         }
         Err(e) => {
-            println!("Settlement error: {}", e);
-            HttpResponse::InternalServerError().json(SettleResponse {
-                wallet_id: wallet_info.id,
-                success: false,
-                txid: None,
-                error: Some(format!("Failed to settle: {}", e)),
-            })
+            tracing::error!(wallet_id = %wallet_info.id, error = %e, "Settlement failed");
+            data.events.record(
+                &wallet_info.id,
+                WalletEventKind::SettlementFailed {
+                    error: e.to_string(),
+                },
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                settle_failure(wallet_info, None, format!("Failed to settle: {}", e)),
+            )
         }
     }
 }
 
+fn settle_failure(
+    wallet_info: &WalletInfo,
+    code: Option<&'static str>,
+    error: String,
+) -> SettleResponse {
+    SettleResponse {
+        wallet_id: wallet_info.id.clone(),
+        success: false,
+        txid: None,
+        fee_paid: None,
+        fee_paid_btc: None,
+        round_fee: None,
+        round_fee_btc: None,
+        round_id: None,
+        vtxo_inputs: None,
+        boarding_inputs: None,
+        amount: None,
+        amount_btc: None,
+        to_address: None,
+        timeline: Vec::new(),
+        code,
+        error: Some(error),
+    }
+}
+
+/// The Ark server reports a different forfeit address than the one in our cached server info.
+#[derive(Debug)]
+struct ForfeitAddressChanged {
+    cached: bitcoin::Address,
+    current: bitcoin::Address,
+}
+
+impl std::fmt::Display for ForfeitAddressChanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "forfeit address changed from {} to {}; refusing to sign forfeit transactions",
+            self.cached, self.current
+        )
+    }
+}
+
+impl std::error::Error for ForfeitAddressChanged {}
+
+/// Error code of a settlement that was cancelled before committing to the round.
+const SETTLEMENT_CANCELLED: &str = "SETTLEMENT_CANCELLED";
+
+/// The settlement was cancelled before it submitted its forfeit transactions.
+#[derive(Debug)]
+struct SettlementCancelled;
+
+impl std::fmt::Display for SettlementCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "settlement cancelled before forfeit transactions were submitted")
+    }
+}
+
+impl std::error::Error for SettlementCancelled {}
+
+/// Mixed into the seed of settlement randomness when `deterministic_nonces` is enabled, see
+/// [`Settlement::rng`].
+const DETERMINISTIC_NONCE_SEED: u64 = 0;
+
+/// Consolidating fewer VTXOs than this gains nothing, unless the client asks otherwise.
+const DEFAULT_CONSOLIDATE_MIN_INPUTS: usize = 2;
+
+pub(crate) struct SettleOutcome {
+    round_txid: Txid,
+    round_id: String,
+    /// What the wallet's inputs were worth minus what it got back in the round.
+    fee_paid: Amount,
+    /// The fee of the whole round transaction, shared by all participants.
+    round_fee: Option<Amount>,
+    vtxo_inputs: usize,
+    boarding_inputs: usize,
+    /// Total value of the wallet's inputs.
+    amount: Amount,
+    to_address: ArkAddress,
+    /// Filled in by [`Settlement::run`].
+    timeline: Vec<SettleTimelineEntry>,
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn settle_internal(
     grpc_client: &ark_grpc::Client,
     server_info: &ark_core::server::Info,
+    rng: &mut StdRng,
     sk: SecretKey,
     vtxos: VirtualTxOutpoints,
     boarding_outputs: BoardingOutpoints,
     to_address: ArkAddress,
-) -> Result<Option<Txid>, anyhow::Error> {
+    onchain_output: Option<RoundOutput>,
+    cancellation: Option<&SettleCancellation>,
+    on_progress: impl Fn(SettleProgress),
+) -> Result<Option<SettleOutcome>, anyhow::Error> {
     let secp = Secp256k1::new();
-    let mut rng = thread_rng();
 
     if vtxos.spendable.is_empty() && boarding_outputs.spendable.is_empty() {
         return Ok(None);
     }
 
-    let cosigner_kp = Keypair::new(&secp, &mut rng);
+    let cosigner_kp = Keypair::new(&secp, rng);
 
     let round_inputs = {
         let boarding_inputs = boarding_outputs
@@ -476,8 +2159,25 @@ async fn settle_internal(
         .await?;
 
     let spendable_amount = boarding_outputs.spendable_balance() + vtxos.spendable_balance();
-
-    let round_outputs = vec![RoundOutput::new_virtual(to_address, spendable_amount)];
+    let vtxo_input_count = vtxos.spendable.len();
+    let boarding_input_count = boarding_outputs.spendable.len();
+
+    let round_outputs = match onchain_output {
+        None => vec![RoundOutput::new_virtual(to_address, spendable_amount)],
+        Some(onchain_output) => {
+            let change = spendable_amount
+                .checked_sub(onchain_output.amount())
+                .ok_or_else(|| anyhow::anyhow!("on-chain output exceeds the settled amount"))?;
+
+            // Change below dust cannot be a VTXO, so it is left to the round as a fee.
+            let mut outputs = vec![onchain_output];
+            if change >= server_info.dust {
+                outputs.push(RoundOutput::new_virtual(to_address, change));
+            }
+            outputs
+        }
+    };
+    let output_amount: Amount = round_outputs.iter().map(|output| output.amount()).sum();
     grpc_client
         .register_outputs_for_next_round(
             payment_id.clone(),
@@ -502,12 +2202,15 @@ async fn settle_internal(
     };
 
     let round_id = round_signing_event.id;
+    on_progress(SettleProgress::Signing {
+        round_id: round_id.clone(),
+    });
 
-    let unsigned_vtxo_tree = round_signing_event
-        .unsigned_vtxo_tree
-        .expect("to have an unsigned VTXO tree");
+    let unsigned_vtxo_tree = round_signing_event.unsigned_vtxo_tree.ok_or_else(|| {
+        anyhow::anyhow!("Round signing event of round {} has no VTXO tree", round_id)
+    })?;
 
-    let nonce_tree = generate_nonce_tree(&mut rng, &unsigned_vtxo_tree, cosigner_kp.public_key())?;
+    let nonce_tree = generate_nonce_tree(rng, &unsigned_vtxo_tree, cosigner_kp.public_key())?;
 
     grpc_client
         .submit_tree_nonces(
@@ -528,6 +2231,9 @@ async fn settle_internal(
     };
 
     let round_id = round_signing_nonces_generated_event.id;
+    on_progress(SettleProgress::NoncesGenerated {
+        round_id: round_id.clone(),
+    });
     let agg_pub_nonce_tree = round_signing_nonces_generated_event.tree_nonces;
 
     let partial_sig_tree = sign_vtxo_tree(
@@ -558,7 +2264,9 @@ async fn settle_internal(
         }
     };
 
-    let round_id = round_finalization_event.id;
+    on_progress(SettleProgress::Finalization {
+        round_id: round_finalization_event.id.clone(),
+    });
 
     let vtxo_inputs = vtxos
         .spendable
@@ -566,9 +2274,21 @@ async fn settle_internal(
         .map(|(outpoint, vtxo)| round::VtxoInput::new(vtxo, outpoint.amount, outpoint.outpoint))
         .collect::<Vec<_>>();
 
-    let keypair = Keypair::from_secret_key(&secp, &sk);
+    // The forfeit transactions hand our VTXOs to this address, so make sure the server has not
+    // switched it since we cached its info.
+    let current_info = grpc_client.clone().get_info().await?;
+    if current_info.forfeit_address != server_info.forfeit_address {
+        return Err(ForfeitAddressChanged {
+            cached: server_info.forfeit_address.clone(),
+            current: current_info.forfeit_address,
+        }
+        .into());
+    }
+
+    // Forfeit transactions can only be signed with a keypair by `ark_core` for now.
+    let signer = InMemorySigner::new(&sk);
     let signed_forfeit_psbts = create_and_sign_forfeit_txs(
-        &keypair,
+        signer.keypair(),
         vtxo_inputs.as_slice(),
         round_finalization_event.connector_tree,
         &round_finalization_event.connectors_index,
@@ -583,6 +2303,9 @@ async fn settle_internal(
         .map(|(outpoint, _, boarding_output)| round::OnChainInput::new(boarding_output, outpoint))
         .collect::<Vec<_>>();
 
+    // Only computable if the server filled in the previous outputs of every round input.
+    let round_fee = round_finalization_event.round_tx.fee().ok();
+
     let round_psbt = if round_inputs.is_empty() {
         None
     } else {
@@ -590,7 +2313,7 @@ async fn settle_internal(
 
         let sign_for_pk_fn =
             |_: &XOnlyPublicKey, msg: &Message| -> Result<schnorr::Signature, ark_core::Error> {
-                Ok(secp.sign_schnorr_no_aux_rand(msg, &keypair))
+                Ok(signer.sign_schnorr(*msg).0)
             };
 
         sign_round_psbt(sign_for_pk_fn, &mut round_psbt, &onchain_inputs)?;
@@ -598,6 +2321,14 @@ async fn settle_internal(
         Some(round_psbt)
     };
 
+    // Past this point our VTXOs are forfeited to the server, so the settlement must see the round
+    // through.
+    if let Some(cancellation) = cancellation
+        && !cancellation.commit()
+    {
+        return Err(SettlementCancelled.into());
+    }
+
     grpc_client
         .submit_signed_forfeit_txs(signed_forfeit_psbts, round_psbt)
         .await?;
@@ -612,6 +2343,614 @@ async fn settle_internal(
         }
     };
 
-    let round_id = round_finalized_event.id;
-    Ok(Some(round_finalized_event.round_txid))
-} 
\ No newline at end of file
+    on_progress(SettleProgress::Finalized {
+        round_id: round_finalized_event.id.clone(),
+        round_txid: round_finalized_event.round_txid.to_string(),
+    });
+
+    Ok(Some(SettleOutcome {
+        round_txid: round_finalized_event.round_txid,
+        round_id: round_finalized_event.id,
+        fee_paid: spendable_amount.checked_sub(output_amount).unwrap_or(Amount::ZERO),
+        round_fee,
+        vtxo_inputs: vtxo_input_count,
+        boarding_inputs: boarding_input_count,
+        amount: spendable_amount,
+        to_address,
+        timeline: Vec::new(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::{test, App};
+    use futures::future::{self, Either};
+    use std::net::TcpListener;
+    use bitcoin::hashes::Hash;
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn concurrent_sends_from_one_wallet_are_refused() {
+        // The kernel accepts connections to this listener, but nothing ever answers them. A send
+        // that gets as far as talking to the Ark server hangs there while holding the spend lock.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let data = web::Data::new(testing::app_state(&url, &url));
+
+        let sk = testing::insert_wallet(&data, 2, testing::TestKeys::Full);
+
+        let server_info = data.server_info().unwrap();
+        let owner = sk.x_only_public_key(&Secp256k1::new()).0;
+        let (_, vtxo) = wallet_outputs(&server_info, owner, None).ok().unwrap();
+        let address = vtxo.to_ark_address().encode();
+
+        let app =
+            test::init_service(App::new().app_data(data.clone()).service(send_to_ark_address))
+                .await;
+
+        let send = || {
+            test::call_service(
+                &app,
+                test::TestRequest::post()
+                    .uri("/send_to_ark_address")
+                    .set_json(serde_json::json!({
+                        "wallet_id": testing::WALLET_ID,
+                        "address": address,
+                        "amount": 1_000,
+                    }))
+                    .to_request(),
+            )
+        };
+
+        let sends = future::select(Box::pin(send()), Box::pin(send()));
+        let (refused, mut in_flight) = match tokio::time::timeout(Duration::from_secs(10), sends)
+            .await
+            .expect("one of the sends to be refused")
+        {
+            Either::Left((response, other)) | Either::Right((response, other)) => {
+                (response, other)
+            }
+        };
+
+        assert_eq!(refused.status(), actix_web::http::StatusCode::CONFLICT);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(500), &mut in_flight)
+                .await
+                .is_err(),
+            "the other send should still be in progress"
+        );
+        assert!(data.spend_locks.try_lock(testing::WALLET_ID).is_none());
+    }
+
+    #[actix_web::test]
+    async fn spends_are_refused_once_shutdown_started() {
+        let data = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        assert!(lock_wallet(&data, testing::WALLET_ID).is_ok());
+
+        data.background.shut_down();
+        let refused = lock_wallet(&data, testing::WALLET_ID).err().unwrap();
+        assert_eq!(refused.status(), actix_web::http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn cancelling_a_reservation_releases_its_inputs() {
+        let data = web::Data::new(testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1"));
+        let outpoint = bitcoin::OutPoint {
+            txid: Txid::all_zeros(),
+            vout: 1,
+        };
+        let tx = Transaction {
+            version: bitcoin::transaction::Version::non_standard(3),
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                previous_output: outpoint,
+                ..Default::default()
+            }],
+            output: Vec::new(),
+        };
+        let selected = Amount::from_sat(1_000);
+        let reservation_id =
+            data.unsigned_sends.insert(testing::WALLET_ID, &tx, Vec::new(), selected);
+
+        let app =
+            test::init_service(App::new().app_data(data.clone()).service(cancel_redeem)).await;
+        let cancel = || {
+            test::call_service(
+                &app,
+                test::TestRequest::post()
+                    .uri(&format!("/cancel_redeem/{}", reservation_id))
+                    .to_request(),
+            )
+        };
+
+        // A send being submitted holds the wallet's spend lock; it cannot be cancelled under it.
+        let guard = lock_wallet(&data, testing::WALLET_ID).ok().unwrap();
+        assert_eq!(cancel().await.status(), StatusCode::CONFLICT);
+        drop(guard);
+
+        let response = cancel().await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["wallet_id"], testing::WALLET_ID);
+        assert_eq!(body["txid"], tx.compute_txid().to_string());
+        assert_eq!(body["released_outpoints"], serde_json::json!([outpoint.to_string()]));
+        assert!(data.unsigned_sends.reserved(testing::WALLET_ID).is_empty());
+
+        assert_eq!(cancel().await.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn settlement(state: &AppState, deterministic_nonces: bool) -> Settlement {
+        let sk = SecretKey::from_slice(&[2; 32]).unwrap();
+        let server_info = state.server_info().unwrap();
+        let owner = sk.x_only_public_key(&Secp256k1::new()).0;
+        let (_, vtxo) = wallet_outputs(&server_info, owner, None).ok().unwrap();
+
+        Settlement {
+            grpc_client: ark_grpc::Client::new(state.config.ark_server_url.clone()),
+            server_info,
+            deterministic_nonces,
+            sk,
+            vtxos: VirtualTxOutpoints {
+                spendable: Vec::new(),
+                expired: Vec::new(),
+            },
+            boarding_outputs: BoardingOutpoints {
+                spendable: Vec::new(),
+                expired: Vec::new(),
+                pending: Vec::new(),
+                spent: Vec::new(),
+            },
+            to_address: vtxo.to_ark_address(),
+            onchain_output: None,
+            has_expired: false,
+            settlements_in_flight: state.metrics.settlements_in_flight.clone(),
+            cancellation: None,
+        }
+    }
+
+    /// The cosigner key a settlement would use, and its public nonce for a VTXO tree with one
+    /// node that key cosigns.
+    fn cosigner_key_and_nonce(settlement: &Settlement) -> (PublicKey, Vec<u8>) {
+        let mut rng = settlement.rng();
+        let cosigner_pk = Keypair::new(&Secp256k1::new(), &mut rng).public_key();
+
+        let tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: Vec::new(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        // ark-core finds a node's cosigners under keys with this prefix.
+        let key = bitcoin::psbt::raw::Key {
+            type_value: 0,
+            key: vec![111, 115, 105, 103, 110, 101, 114, 0],
+        };
+        psbt.inputs[0].unknown.insert(key, cosigner_pk.serialize().to_vec());
+        let tree = ark_core::server::TxTree {
+            levels: vec![ark_core::server::TxTreeLevel {
+                nodes: vec![ark_core::server::TxTreeNode {
+                    txid: psbt.unsigned_tx.compute_txid(),
+                    tx: psbt,
+                    parent_txid: Txid::all_zeros(),
+                }],
+            }],
+        };
+        let nonces = generate_nonce_tree(&mut rng, &tree, cosigner_pk).unwrap();
+
+        let nonce = nonces.to_pub_nonce_tree().get(0, 0).unwrap();
+
+        (cosigner_pk, nonce.serialize().to_vec())
+    }
+
+    #[actix_web::test]
+    async fn deterministic_nonces_only_repeat_for_the_same_settlement() {
+        let state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+
+        let (cosigner_pk, nonces) = cosigner_key_and_nonce(&settlement(&state, true));
+        assert_eq!(
+            cosigner_key_and_nonce(&settlement(&state, true)),
+            (cosigner_pk, nonces.clone())
+        );
+
+        // Paying out differently, as with the same inputs in a later round, changes both.
+        let address = state.server_info().unwrap().forfeit_address;
+        let exit = settlement(&state, true).exit_to(address, Amount::from_sat(1_000));
+        let (exit_pk, exit_nonces) = cosigner_key_and_nonce(&exit);
+        assert_ne!(exit_pk, cosigner_pk);
+        assert_ne!(exit_nonces, nonces);
+
+        let (random_pk, _) = cosigner_key_and_nonce(&settlement(&state, false));
+        assert_ne!(random_pk, cosigner_key_and_nonce(&settlement(&state, false)).0);
+    }
+
+    #[actix_web::test]
+    async fn onchain_address_must_match_server_network() {
+        let data = web::Data::new(testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1"));
+        testing::insert_wallet(&data, 3, testing::TestKeys::Full);
+
+        let app = test::init_service(App::new().app_data(data).service(send_onchain)).await;
+
+        // A mainnet address, while the test server is on regtest.
+        let response: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/send_onchain")
+                .set_json(serde_json::json!({
+                    "wallet_id": testing::WALLET_ID,
+                    "onchain_address": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+                    "amount": 10_000,
+                }))
+                .to_request(),
+        )
+        .await;
+
+        assert_eq!(response["code"], "WRONG_NETWORK");
+    }
+
+    #[actix_web::test]
+    async fn onchain_sends_count_against_the_spending_limit() {
+        let mut state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        state.spend_limits =
+            SpendLimits::new(Some(Amount::from_sat(5_000)), None, None, HashMap::new());
+        let sk = testing::insert_wallet(&state, 2, testing::TestKeys::Full);
+        let owner = sk.x_only_public_key(&Secp256k1::new()).0;
+        let (boarding_output, vtxo) =
+            wallet_outputs(&state.server_info().unwrap(), owner, None).ok().unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(send_onchain)
+                .service(send_to_ark_address),
+        )
+        .await;
+
+        let mut refusals = Vec::new();
+        for (uri, address_field, address) in [
+            ("/send_onchain", "onchain_address", boarding_output.address().to_string()),
+            ("/send_to_ark_address", "address", vtxo.to_ark_address().encode()),
+        ] {
+            let response = test::call_service(
+                &app,
+                test::TestRequest::post()
+                    .uri(uri)
+                    .set_json(serde_json::json!({
+                        "wallet_id": testing::WALLET_ID,
+                        address_field: address,
+                        "amount": 10_000,
+                    }))
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+            refusals.push(test::read_body_json::<serde_json::Value, _>(response).await);
+        }
+
+        assert_eq!(refusals[0]["code"], "SPEND_LIMIT_EXCEEDED");
+        assert_eq!(refusals[0], refusals[1]);
+    }
+
+    #[actix_web::test]
+    async fn sends_to_addresses_off_the_whitelist_are_refused() {
+        let mut state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        let server_info = state.server_info().unwrap();
+        let ark_address = |byte| {
+            let sk = SecretKey::from_slice(&[byte; 32]).unwrap();
+            let owner = sk.x_only_public_key(&Secp256k1::new()).0;
+            let (_, vtxo) = wallet_outputs(&server_info, owner, None).ok().unwrap();
+            vtxo.to_ark_address().encode()
+        };
+        state.config.send_address_whitelist = vec![ark_address(4)];
+
+        testing::insert_wallet(&state, 2, testing::TestKeys::Full);
+
+        let app =
+            test::init_service(App::new().app_data(web::Data::new(state)).service(send_batch))
+                .await;
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/send_batch")
+                .set_json(serde_json::json!({
+                    "wallet_id": testing::WALLET_ID,
+                    "outputs": [
+                        { "address": ark_address(4), "amount": 1_000 },
+                        { "address": ark_address(5), "amount": 1_000 },
+                    ],
+                }))
+                .to_request(),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "ADDRESS_NOT_WHITELISTED");
+        assert_eq!(body["address"], ark_address(5));
+    }
+
+    #[actix_web::test]
+    async fn onchain_sends_to_addresses_off_the_whitelist_are_refused() {
+        let mut state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        let server_info = state.server_info().unwrap();
+        let onchain_address = |byte| {
+            let sk = SecretKey::from_slice(&[byte; 32]).unwrap();
+            let owner = sk.x_only_public_key(&Secp256k1::new()).0;
+            let (boarding_output, _) = wallet_outputs(&server_info, owner, None).ok().unwrap();
+            boarding_output.address().to_string()
+        };
+        state.config.send_address_whitelist = vec![onchain_address(4).to_uppercase()];
+
+        testing::insert_wallet(&state, 2, testing::TestKeys::Full);
+
+        let app =
+            test::init_service(App::new().app_data(web::Data::new(state)).service(send_onchain))
+                .await;
+
+        let send = |address: String, amount: u64| {
+            test::call_service(
+                &app,
+                test::TestRequest::post()
+                    .uri("/send_onchain")
+                    .set_json(serde_json::json!({
+                        "wallet_id": testing::WALLET_ID,
+                        "onchain_address": address,
+                        "amount": amount,
+                    }))
+                    .to_request(),
+            )
+        };
+
+        let refused = send(onchain_address(5), 10_000).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = test::read_body_json(refused).await;
+        assert_eq!(body["code"], "ADDRESS_NOT_WHITELISTED");
+        assert_eq!(body["address"], onchain_address(5));
+
+        // The whitelisted address gets past the whitelist, only to be held to the dust limit.
+        let below_dust = send(onchain_address(4), 1).await;
+        assert_eq!(below_dust.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(below_dust).await;
+        assert_eq!(body["code"], "AMOUNT_BELOW_DUST");
+    }
+
+    #[actix_web::test]
+    async fn amounts_below_dust_are_refused() {
+        let data = web::Data::new(testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1"));
+        let sk = testing::insert_wallet(&data, 2, testing::TestKeys::Full);
+        let owner = sk.x_only_public_key(&Secp256k1::new()).0;
+        let (_, vtxo) = wallet_outputs(&data.server_info().unwrap(), owner, None).ok().unwrap();
+        let address = vtxo.to_ark_address().encode();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(data)
+                .service(send_to_ark_address)
+                .service(estimate_fee),
+        )
+        .await;
+        let send = |amount: u64| {
+            test::TestRequest::post()
+                .uri("/send_to_ark_address")
+                .set_json(serde_json::json!({
+                    "wallet_id": testing::WALLET_ID,
+                    "address": address,
+                    "amount": amount,
+                }))
+                .to_request()
+        };
+
+        // The test server's dust limit is 330 sats.
+        for amount in [0, 329] {
+            let response = test::call_service(&app, send(amount)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(body["code"], "AMOUNT_BELOW_DUST");
+            assert_eq!(body["dust"], 330);
+
+            let estimate = test::TestRequest::get()
+                .uri(&format!("/estimate_fee/{}?amount={}", testing::WALLET_ID, amount))
+                .to_request();
+            let response = test::call_service(&app, estimate).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // Past the check, the send fails on the unreachable Ark server instead.
+        let response = test::call_service(&app, send(330)).await;
+        assert!(response.status().is_server_error());
+    }
+
+    #[actix_web::test]
+    async fn change_below_dust_is_left_to_the_fee() {
+        let state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        let server_info = state.server_info().unwrap();
+        let dust = server_info.dust;
+        let owner = SecretKey::from_slice(&[2u8; 32])
+            .unwrap()
+            .x_only_public_key(&Secp256k1::new())
+            .0;
+        let (_, vtxo) = wallet_outputs(&server_info, owner, None).ok().unwrap();
+        let recipients = [Recipient {
+            address: vtxo.to_ark_address().encode(),
+            ark_address: vtxo.to_ark_address(),
+            amount: Amount::from_sat(10_000),
+        }];
+        let inputs = |amount| {
+            vec![redeem::VtxoInput::new(
+                vtxo.clone(),
+                Amount::from_sat(amount),
+                bitcoin::OutPoint {
+                    txid: Txid::all_zeros(),
+                    vout: 0,
+                },
+            )]
+        };
+        let fee = estimate_redeem_fee(&inputs(0), 2).unwrap();
+        let outputs = |vtxo_inputs: &[redeem::VtxoInput], change_address| {
+            let outputs = [(&recipients[0].ark_address, recipients[0].amount)];
+            build_redeem_transaction_with_lock_time(
+                &outputs,
+                change_address,
+                vtxo_inputs,
+                LockTime::ZERO,
+            )
+            .unwrap()
+            .unsigned_tx
+            .output
+            .iter()
+            .map(|output| output.value.to_sat())
+            .collect::<Vec<_>>()
+        };
+
+        let change_address = vtxo.to_ark_address();
+        let just_above = inputs((recipients[0].amount + fee + dust).to_sat());
+        assert_eq!(redeem_change(&just_above, &recipients, dust).unwrap(), Some(dust));
+        assert_eq!(outputs(&just_above, Some(&change_address)), vec![10_000, dust.to_sat()]);
+
+        // The recipient still gets the full amount; the would-be change pays the fee.
+        let just_below = inputs((recipients[0].amount + fee + dust).to_sat() - 1);
+        assert_eq!(redeem_change(&just_below, &recipients, dust).unwrap(), None);
+        assert_eq!(outputs(&just_below, None), vec![10_000]);
+        assert_eq!(
+            redeem_fee(&just_below, &recipients, dust).unwrap(),
+            estimate_redeem_fee(&just_below, 1).unwrap()
+        );
+    }
+
+    #[actix_web::test]
+    async fn coin_selection_picks_more_vtxos_to_cover_the_fee() {
+        let state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        let server_info = state.server_info().unwrap();
+        let dust = server_info.dust;
+        let owner = SecretKey::from_slice(&[2u8; 32])
+            .unwrap()
+            .x_only_public_key(&Secp256k1::new())
+            .0;
+        let (_, vtxo) = wallet_outputs(&server_info, owner, None).ok().unwrap();
+        let spendable = (0..21)
+            .map(|vout| {
+                let outpoint = VtxoOutPoint {
+                    outpoint: bitcoin::OutPoint {
+                        txid: Txid::all_zeros(),
+                        vout,
+                    },
+                    spent: false,
+                    round_txid: Txid::all_zeros(),
+                    spent_by: None,
+                    expire_at: 0,
+                    swept: false,
+                    is_pending: false,
+                    redeem_tx: None,
+                    amount: Amount::from_sat(if vout < 20 { 600 } else { 50_000 }),
+                    pubkey: String::new(),
+                    created_at: 0,
+                };
+                (outpoint, vtxo.clone())
+            })
+            .collect::<Vec<_>>();
+        // The first 20 VTXOs cover the amount with change above dust, but not their own fee.
+        let recipients = [Recipient {
+            address: vtxo.to_ark_address().encode(),
+            ark_address: vtxo.to_ark_address(),
+            amount: Amount::from_sat(12_000) - dust,
+        }];
+
+        let (vtxo_inputs, fee) =
+            select_with_fee(&spendable, &recipients, dust, false).unwrap().unwrap();
+
+        assert_eq!(vtxo_inputs.len(), 21);
+        let selected: Amount = vtxo_inputs.iter().map(|input| input.amount()).sum();
+        assert!(selected >= recipients[0].amount + fee);
+    }
+
+    #[actix_web::test]
+    async fn settled_rounds_are_reported_with_a_receipt() {
+        let state = testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1");
+        let owner = SecretKey::from_slice(&[2u8; 32])
+            .unwrap()
+            .x_only_public_key(&Secp256k1::new())
+            .0;
+        let (_, vtxo) = wallet_outputs(&state.server_info().unwrap(), owner, None).ok().unwrap();
+        let wallet_info = WalletInfo {
+            id: testing::WALLET_ID.to_string(),
+            keys: WalletKeys::WatchOnly {
+                pubkey: owner.to_string(),
+            },
+            owner: Some(owner.to_string()),
+            unilateral_exit_delay: None,
+        };
+        let outcome = SettleOutcome {
+            round_txid: Txid::all_zeros(),
+            round_id: "round-1".to_string(),
+            fee_paid: Amount::from_sat(200),
+            round_fee: None,
+            vtxo_inputs: 2,
+            boarding_inputs: 1,
+            amount: Amount::from_sat(30_000),
+            to_address: vtxo.to_ark_address(),
+            timeline: vec![SettleTimelineEntry {
+                progress: SettleProgress::Signing {
+                    round_id: "round-1".to_string(),
+                },
+                timestamp: 1_700_000_000,
+            }],
+        };
+
+        let (status, response) =
+            finish_settlement(&state, &wallet_info, Ok(Some(outcome)), false).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let response = serde_json::to_value(response).unwrap();
+        assert_eq!(response["success"], true);
+        assert_eq!(response["round_id"], "round-1");
+        assert_eq!(response["vtxo_inputs"], 2);
+        assert_eq!(response["boarding_inputs"], 1);
+        assert_eq!(response["amount"], 30_000);
+        assert_eq!(response["to_address"], vtxo.to_ark_address().to_string());
+        assert_eq!(response["fee_paid"], 200);
+        assert_eq!(
+            response["timeline"],
+            serde_json::json!([
+                {"event": "signing", "round_id": "round-1", "timestamp": 1_700_000_000}
+            ])
+        );
+
+        let (_, failed) = finish_settlement(&state, &wallet_info, Ok(None), false).await;
+        assert_eq!(serde_json::to_value(failed).unwrap()["round_id"], serde_json::Value::Null);
+    }
+
+    #[actix_web::test]
+    async fn coin_selection_strategies_order_vtxos() {
+        let vtxo = |vout, amount, created_at| VtxoOutPoint {
+            outpoint: bitcoin::OutPoint {
+                txid: Txid::all_zeros(),
+                vout,
+            },
+            spent: false,
+            round_txid: Txid::all_zeros(),
+            spent_by: None,
+            expire_at: 0,
+            swept: false,
+            is_pending: false,
+            redeem_tx: None,
+            amount: Amount::from_sat(amount),
+            pubkey: String::new(),
+            created_at,
+        };
+        let order = |coin_selection| {
+            let mut spendable =
+                vec![(vtxo(0, 5_000, 10), ()), (vtxo(1, 1_000, 30), ()), (vtxo(2, 9_000, 20), ())];
+            let sort_by_expiration_time = order_for_selection(&mut spendable, coin_selection);
+            let vouts = spendable.iter().map(|(o, _)| o.outpoint.vout).collect::<Vec<_>>();
+            (sort_by_expiration_time, vouts)
+        };
+
+        assert_eq!(order(CoinSelection::Default), (true, vec![0, 1, 2]));
+        assert_eq!(order(CoinSelection::LargestFirst), (false, vec![2, 0, 1]));
+        assert_eq!(order(CoinSelection::SmallestFirst), (false, vec![1, 0, 2]));
+        assert_eq!(order(CoinSelection::OldestFirst), (false, vec![0, 2, 1]));
+    }
+}
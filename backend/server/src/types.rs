@@ -1,43 +1,554 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
 use std::sync::Mutex;
-use bitcoin::Txid;
-use ark_core::ArkAddress;
-use ark_core::ExplorerUtxo;
-use bitcoin::Amount;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Amount, Denomination, Sequence, XOnlyPublicKey};
+use std::str::FromStr;
 
 pub use ark_core::vtxo::VirtualTxOutpoints;
 pub use ark_core::boarding_output::BoardingOutpoints;
+pub use crate::ark::ArkClient;
+pub use crate::background::BackgroundTasks;
+pub use crate::cache::{CacheAdmin, CacheStats, TtlCache};
+pub use crate::esplora::{BroadcastError, ChainTip, EsploraClient, OutpointCache};
+pub use crate::events::{EventLog, WalletEventKind};
+pub use crate::idempotency::IdempotencyKeys;
+pub use crate::incoming::IncomingWatchers;
+pub use crate::metrics::Metrics;
+pub use crate::rate_limit::RateLimiter;
+pub use crate::seed::WalletSeed;
+pub use crate::settle_jobs::{
+    SettleCancellation, SettleJobStatus, SettleJobs, SettleProgress, SettleTimelineEntry,
+};
+pub use crate::spend_lock::{SpendGuard, SpendLocks};
+pub use crate::spend_limits::SpendLimits;
+pub use crate::unsigned_sends::UnsignedSends;
 
-#[derive(Clone)]
-pub struct ArkAddressCli(pub ArkAddress);
+#[derive(Deserialize, Clone, JsonSchema)]
+pub struct Config {
+    /// gRPC endpoint of the Ark server.
+    #[schemars(example = "example_ark_server_url")]
+    pub ark_server_url: String,
+    /// TLS for the connection to the Ark server. Plaintext unless enabled or the URL is `https`.
+    #[serde(default)]
+    pub tls: TlsSettings,
+    /// IP address the HTTP API listens on. Use `0.0.0.0` to listen on every interface.
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    /// Port the HTTP API listens on.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Threads handling HTTP requests. One per CPU core when unset.
+    #[serde(default)]
+    pub http_workers: Option<NonZeroUsize>,
+    /// Worker threads of the async runtime, which also run settlements and background tasks. One
+    /// per CPU core when unset.
+    #[serde(default)]
+    pub runtime_threads: Option<NonZeroUsize>,
+    /// Origins browsers may call the API from, e.g. `https://wallet.example.com`; `*` allows any
+    /// origin. When empty, any `localhost` or `127.0.0.1` origin is allowed, for development.
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+    /// HTTP methods allowed in cross-origin requests.
+    #[serde(default = "default_cors_allowed_methods")]
+    pub cors_allowed_methods: Vec<String>,
+    /// Request headers allowed in cross-origin requests.
+    #[serde(default = "default_cors_allowed_headers")]
+    pub cors_allowed_headers: Vec<String>,
+    /// Esplora HTTP API used for on-chain data.
+    #[schemars(example = "example_esplora_url")]
+    pub esplora_url: String,
+    /// Additional Esplora backends, tried in order when `esplora_url` is unavailable.
+    #[serde(default)]
+    pub esplora_urls: Vec<String>,
+    /// Maximum number of concurrent Esplora requests made while looking up an address's outputs.
+    #[serde(default = "default_esplora_max_concurrent_requests")]
+    pub esplora_max_concurrent_requests: usize,
+    /// Networks the Ark server is allowed to be on, e.g. `["bitcoin"]`. Empty allows any.
+    #[serde(default)]
+    pub allowed_networks: Vec<String>,
+    /// Bearer token required by the `/admin` endpoints. They are disabled when unset.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// `nLockTime` policy for redeem transactions built by `send_to_ark_address`.
+    #[serde(default)]
+    pub redeem_locktime: LocktimePolicy,
+    /// Largest amount a wallet may send in one Ark or on-chain send, in sats. Unlimited when unset.
+    #[serde(default)]
+    pub max_send_per_tx: Option<u64>,
+    /// Largest amount a wallet may send to Ark and on-chain addresses over any 24 hours, in sats.
+    /// Unlimited when unset.
+    #[serde(default)]
+    pub max_send_per_day: Option<u64>,
+    /// Ark and on-chain addresses that sends and settlements may pay to. Empty allows any address.
+    /// Settling into the wallet's own address is always allowed.
+    #[serde(default)]
+    pub send_address_whitelist: Vec<String>,
+    /// How long the response to a spend sent with an `Idempotency-Key` is returned again for a
+    /// request with the same key, in seconds.
+    #[serde(default = "default_idempotency_key_ttl_secs")]
+    pub idempotency_key_ttl_secs: u64,
+    /// How often wallets streamed by `GET /events/{wallet_id}` are checked for incoming payments,
+    /// in seconds.
+    #[serde(default = "default_incoming_poll_interval_secs")]
+    pub incoming_poll_interval_secs: u64,
+    /// Requests each client IP may make. Off unless `enabled`.
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    /// Bearer token `GET /metrics` requires. Unset leaves the endpoint open, since many
+    /// Prometheus scrapers cannot send credentials.
+    #[serde(default)]
+    pub metrics_token: Option<String>,
+    /// How long the outputs Esplora reports for an address are reused before fetching them
+    /// again, in seconds. A settlement forgets those of the wallet's addresses right away.
+    #[serde(default = "default_outpoint_cache_ttl_secs")]
+    pub outpoint_cache_ttl_secs: u64,
+    /// How long Esplora fee rate estimates are reused before fetching them again, in seconds.
+    #[serde(default = "default_fee_rates_cache_ttl_secs")]
+    pub fee_rates_cache_ttl_secs: u64,
+    /// Which log messages are written, in `RUST_LOG` syntax, e.g. `info` or `info,backend=debug`.
+    /// The `RUST_LOG` environment variable takes precedence. Defaults to debug logging with
+    /// noisy dependencies turned down.
+    #[serde(default)]
+    pub log_filter: Option<String>,
+    /// `json` writes one JSON object per log line, for log aggregators.
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Maximum number of activity events kept per wallet.
+    #[serde(default = "default_event_log_capacity")]
+    pub event_log_capacity: usize,
+    /// Check at startup that every loaded wallet has a usable seed and can derive its addresses.
+    /// Wallets that fail are quarantined instead of being served.
+    #[serde(default)]
+    pub validate_wallets_on_start: bool,
+    /// Extra headers added to every response, e.g. `Strict-Transport-Security`. They override
+    /// the built-in `Cache-Control: no-store` and `X-Content-Type-Options: nosniff` defaults.
+    #[serde(default)]
+    pub response_headers: BTreeMap<String, String>,
+    /// Confirmations a boarding deposit should have before users are told it is usable.
+    #[serde(default = "default_min_confirmations")]
+    pub min_confirmations: u32,
+    /// Derive the settlement cosigner key and nonces from a fixed seed, the wallet's key and the
+    /// outputs being settled, so that rounds can be reproduced in tests. Only allowed on regtest.
+    #[serde(default)]
+    pub deterministic_nonces: bool,
+    /// Register unconfirmed boarding outputs in settlements too. If such a deposit is
+    /// double-spent or dropped from the mempool the round fails, so only enable this where that
+    /// cannot happen, e.g. on regtest.
+    #[serde(default)]
+    pub allow_pending_boarding_in_settle: bool,
+    /// Passphrase used to encrypt wallet seeds at rest. Without it seeds are stored in plain text.
+    #[serde(default)]
+    pub encryption_passphrase: Option<String>,
+    /// Attempts made for each read-only Ark server request (and for connecting) before a
+    /// transport error is returned. `1` disables retrying.
+    #[serde(default = "default_ark_retry_max_attempts")]
+    pub ark_retry_max_attempts: u32,
+    /// Upper bound for the delay between two attempts, in milliseconds. The delay starts at
+    /// 200ms and doubles after every failed attempt.
+    #[serde(default = "default_ark_retry_max_delay_ms")]
+    pub ark_retry_max_delay_ms: u64,
+    /// Seconds to wait for a connection to the Ark server.
+    #[serde(default = "default_ark_connect_timeout_secs")]
+    pub ark_connect_timeout_secs: u64,
+    /// Seconds to wait for the answer to each Ark server request.
+    #[serde(default = "default_ark_request_timeout_secs")]
+    pub ark_request_timeout_secs: u64,
+    /// Seconds to wait for each event of a round during a settlement. The next round may only
+    /// start after the server's round interval, so this is much longer than the request timeout.
+    #[serde(default = "default_ark_event_stream_timeout_secs")]
+    pub ark_event_stream_timeout_secs: u64,
+    /// How often the Ark server info (dust, expiry, forfeit address) is re-fetched, in seconds.
+    /// Defaults to the server's round interval.
+    #[serde(default)]
+    pub server_info_refresh_secs: Option<u64>,
+    /// Serve `POST /faucet`, which runs `faucet_command` to fund an address. Only meant for
+    /// local regtest setups and refused on mainnet.
+    #[serde(default)]
+    pub faucet_enabled: bool,
+    /// Program run by `POST /faucet`.
+    #[serde(default = "default_faucet_command")]
+    pub faucet_command: String,
+    /// Arguments passed to `faucet_command`. `{address}` and `{amount}` (in BTC) are replaced
+    /// with the values from the request.
+    #[serde(default = "default_faucet_args_template")]
+    pub faucet_args_template: Vec<String>,
+    /// On SIGINT/SIGTERM, how long to wait for requests, settlements and other background tasks
+    /// in flight to finish before stopping anyway, in seconds.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Attempts made to reach the Ark server and set up the Esplora client at startup. The delay
+    /// between attempts starts at 1s and doubles up to 30s.
+    #[serde(default = "default_startup_connect_attempts")]
+    pub startup_connect_attempts: u32,
+    /// Keep trying to reach a dependency that was still down after startup, so the server
+    /// recovers on its own once it comes back.
+    #[serde(default = "default_reconnect_in_background")]
+    pub reconnect_in_background: bool,
+    /// Settle every wallet automatically before its VTXOs expire.
+    #[serde(default)]
+    pub auto_settle: bool,
+    /// Wallets to settle automatically even when `auto_settle` is off.
+    #[serde(default)]
+    pub auto_settle_wallets: Vec<String>,
+    /// Settle a wallet once one of its VTXOs expires within this many seconds.
+    #[serde(default = "default_auto_settle_threshold_secs")]
+    pub auto_settle_threshold_secs: u64,
+    /// How often wallets are checked for expiring VTXOs, in seconds.
+    #[serde(default = "default_auto_settle_interval_secs")]
+    pub auto_settle_interval_secs: u64,
+    /// Spread the settlements of the wallets due at one check over this many seconds instead of
+    /// starting them all at once. They run concurrently either way.
+    #[serde(default)]
+    pub auto_settle_batch_window_secs: u64,
+}
+
+impl Config {
+    pub fn ark_retry_policy(&self) -> ark_grpc::RetryPolicy {
+        ark_grpc::RetryPolicy {
+            max_attempts: self.ark_retry_max_attempts.max(1),
+            max_delay: std::time::Duration::from_millis(self.ark_retry_max_delay_ms),
+            ..Default::default()
+        }
+    }
+
+    pub fn ark_timeouts(&self) -> ark_grpc::Timeouts {
+        ark_grpc::Timeouts {
+            connect: std::time::Duration::from_secs(self.ark_connect_timeout_secs),
+            request: std::time::Duration::from_secs(self.ark_request_timeout_secs),
+            event_stream: std::time::Duration::from_secs(self.ark_event_stream_timeout_secs),
+        }
+    }
+
+    /// The TLS settings for the Ark server connection, with the CA certificate loaded, or `None`
+    /// for plaintext.
+    pub fn ark_tls(&self) -> Result<Option<ark_grpc::TlsConfig>, String> {
+        if !self.tls.enabled && !self.ark_server_url.starts_with("https://") {
+            return Ok(None);
+        }
 
-impl std::str::FromStr for ArkAddressCli {
-    type Err = anyhow::Error;
+        let ca_certificate = match &self.tls.ca_cert_path {
+            Some(path) => Some(std::fs::read(path).map_err(|e| {
+                format!("Failed to read the Ark server CA certificate {}: {}", path, e)
+            })?),
+            None => None,
+        };
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let address = ArkAddress::decode(s)?;
-        Ok(Self(address))
+        Ok(Some(ark_grpc::TlsConfig {
+            ca_certificate,
+            domain_name: self.tls.server_name.clone(),
+        }))
+    }
+
+    /// Whether the auto-settle worker should look after `wallet_id`.
+    pub fn auto_settles(&self, wallet_id: &str) -> bool {
+        self.auto_settle || self.auto_settle_wallets.iter().any(|id| id == wallet_id)
     }
 }
 
-#[derive(Deserialize, Clone)]
-pub struct Config {
-    pub ark_server_url: String,
-    pub esplora_url: String,
+fn default_ark_retry_max_attempts() -> u32 {
+    3
+}
+
+fn default_ark_retry_max_delay_ms() -> u64 {
+    5_000
+}
+
+fn default_ark_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_ark_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_ark_event_stream_timeout_secs() -> u64 {
+    600
+}
+
+fn default_idempotency_key_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_incoming_poll_interval_secs() -> u64 {
+    5
+}
+
+fn default_outpoint_cache_ttl_secs() -> u64 {
+    10
+}
+
+fn default_fee_rates_cache_ttl_secs() -> u64 {
+    60
+}
+
+fn default_bind_address() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    vec!["GET".to_string(), "POST".to_string()]
+}
+
+fn default_cors_allowed_headers() -> Vec<String> {
+    vec![
+        "Content-Type".to_string(),
+        "Authorization".to_string(),
+        "X-Request-Id".to_string(),
+        "Idempotency-Key".to_string(),
+    ]
+}
+
+fn default_faucet_command() -> String {
+    "nigiri".to_string()
+}
+
+fn default_faucet_args_template() -> Vec<String> {
+    vec![
+        "faucet".to_string(),
+        "{address}".to_string(),
+        "{amount}".to_string(),
+    ]
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    60
+}
+
+fn default_startup_connect_attempts() -> u32 {
+    5
+}
+
+fn default_reconnect_in_background() -> bool {
+    true
+}
+
+fn default_auto_settle_threshold_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_auto_settle_interval_secs() -> u64 {
+    10 * 60
+}
+
+fn default_esplora_max_concurrent_requests() -> usize {
+    crate::esplora::DEFAULT_MAX_CONCURRENT_REQUESTS
+}
+
+fn default_min_confirmations() -> u32 {
+    1
+}
+
+fn default_event_log_capacity() -> usize {
+    200
+}
+
+fn example_ark_server_url() -> &'static str {
+    "http://localhost:7070"
+}
+
+fn example_esplora_url() -> &'static str {
+    "http://localhost:30000"
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct TlsSettings {
+    /// Connect to the Ark server over TLS.
+    #[serde(default)]
+    pub enabled: bool,
+    /// PEM file with a CA certificate to trust in addition to the system roots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert_path: Option<String>,
+    /// Name to verify the server certificate against (and send as SNI) instead of the URL host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    /// Answer clients over their limit with `429 Too Many Requests`.
+    pub enabled: bool,
+    /// Limit of every route without an entry in `routes`, shared between those routes.
+    pub default: RateLimit,
+    /// Limits of single routes, by pattern, e.g. `/get_balance/{wallet_id}`. Each has its own
+    /// bucket, so requests to it do not count against `default`.
+    pub routes: BTreeMap<String, RateLimit>,
+    /// Take the client IP from the `Forwarded` or `X-Forwarded-For` header. Only enable this
+    /// behind a reverse proxy that sets it, as clients can otherwise pick any IP.
+    pub trust_forwarded_headers: bool,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default: RateLimit {
+                requests_per_second: 10.0,
+                burst: 20,
+            },
+            routes: BTreeMap::from([(
+                "/create_wallet".to_string(),
+                RateLimit {
+                    requests_per_second: 0.1,
+                    burst: 5,
+                },
+            )]),
+            trust_forwarded_headers: false,
+        }
+    }
+}
+
+/// A token bucket: up to `burst` requests at once, refilled at `requests_per_second`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub struct RateLimit {
+    pub requests_per_second: f64,
+    pub burst: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LocktimePolicy {
+    /// Always use a zero locktime.
+    #[default]
+    Zero,
+    /// Lock to the current chain height, as anti-fee-sniping wallets do.
+    CurrentHeight,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    Json,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct WalletInfo {
     pub id: String,
-    pub seed: String,
+    #[serde(flatten)]
+    pub keys: WalletKeys,
+    /// The owner's x-only public key, in plain text even when the seed is encrypted, so that
+    /// wallets can be told apart without decrypting every seed. Missing from wallet files written
+    /// before it was added until [`WalletInfo::fill_in_owner`] runs on them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Delay of the owner's exit path, if the wallet asked for a longer one than the Ark server's.
+    /// Fixed when the wallet is created, since its addresses depend on it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unilateral_exit_delay: Option<Sequence>,
+}
+
+/// The key material the server holds for a wallet.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum WalletKeys {
+    /// The wallet's secret, so the server can sign for it.
+    Full { seed: WalletSeed },
+    /// Only the owner's x-only public key, in hex. Addresses and balances work; spending does not.
+    WatchOnly { pubkey: String },
+}
+
+impl WalletInfo {
+    /// The key that owns the wallet's boarding output and VTXOs.
+    pub fn owner_pk(&self, passphrase: Option<&str>) -> Result<XOnlyPublicKey, anyhow::Error> {
+        match &self.keys {
+            WalletKeys::Full { seed } => {
+                let sk = seed.secret_key(passphrase)?;
+                Ok(sk.x_only_public_key(&Secp256k1::new()).0)
+            }
+            WalletKeys::WatchOnly { pubkey } => Ok(XOnlyPublicKey::from_str(pubkey)?),
+        }
+    }
+
+    /// Record the owner key of a wallet loaded without one. Decrypts the seed, so it is meant to
+    /// run once at startup rather than in handlers.
+    pub fn fill_in_owner(&mut self, passphrase: Option<&str>) {
+        if self.owner.is_none()
+            && let Ok(owner) = self.owner_pk(passphrase)
+        {
+            self.owner = Some(owner.to_string());
+        }
+    }
 }
 
+/// Shared state of all request handlers.
+///
+/// The mutexes are std ones and must only be held to copy data in or out: handlers drop the guard
+/// before their first `.await` or disk write, so a slow Ark server, Esplora backend or disk never
+/// blocks a worker.
 pub struct AppState {
     pub wallets: Mutex<HashMap<String, WalletInfo>>,
     pub config: Config,
-    pub server_info: Option<Mutex<ark_core::server::Info>>,
-    pub esplora_client: Option<Mutex<EsploraClient>>,
+    pub ark_client: ArkClient,
+    /// `None` until the Ark server has answered; filled in by a background task if it was down
+    /// at startup.
+    pub server_info: Mutex<Option<ark_core::server::Info>>,
+    /// `None` until the Esplora client could be set up.
+    pub esplora_client: Mutex<Option<EsploraClient>>,
+    pub chain_tip: TtlCache<(), ChainTip>,
+    pub fee_rates: TtlCache<(), BTreeMap<u16, f64>>,
+    /// Outputs Esplora reported, by address.
+    pub outpoints: OutpointCache,
+    pub events: EventLog,
+    pub spend_locks: SpendLocks,
+    pub settle_jobs: SettleJobs,
+    pub unsigned_sends: UnsignedSends,
+    pub spend_limits: SpendLimits,
+    pub idempotency_keys: IdempotencyKeys,
+    pub incoming_watchers: IncomingWatchers,
+    pub metrics: Metrics,
+    pub rate_limiter: RateLimiter,
+    pub background: BackgroundTasks,
+}
+
+impl AppState {
+    pub fn server_info(&self) -> Option<ark_core::server::Info> {
+        self.server_info.lock().unwrap().clone()
+    }
+
+    pub fn esplora_client(&self) -> Option<EsploraClient> {
+        self.esplora_client.lock().unwrap().clone()
+    }
+
+    /// Every cache the admin endpoints can inspect and flush.
+    pub fn caches(&self) -> Vec<&dyn CacheAdmin> {
+        vec![&self.chain_tip, &self.fee_rates, &self.outpoints]
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImportKeyRequest {
+    pub secret_key: String,
+    /// See [`CreateWalletRequest::unilateral_exit_delay_secs`].
+    #[serde(default)]
+    pub unilateral_exit_delay_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportWatchOnlyRequest {
+    /// Hex-encoded x-only public key of the wallet owner.
+    pub pubkey: String,
+    /// See [`CreateWalletRequest::unilateral_exit_delay_secs`].
+    #[serde(default)]
+    pub unilateral_exit_delay_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportWalletRequest {
+    /// Hex-encoded secret key.
+    pub seed: String,
+    /// See [`CreateWalletRequest::unilateral_exit_delay_secs`].
+    #[serde(default)]
+    pub unilateral_exit_delay_secs: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -47,29 +558,284 @@ pub struct AddressResponse {
     pub offchain_address: String,
 }
 
+/// A receive address pair, with the index of the key it pays to.
+#[derive(Serialize)]
+pub struct AddressEntry {
+    pub index: u32,
+    pub onchain_address: String,
+    pub offchain_address: String,
+}
+
+#[derive(Serialize)]
+pub struct AddressesResponse {
+    pub wallet_id: String,
+    pub addresses: Vec<AddressEntry>,
+}
+
 #[derive(Serialize)]
 pub struct WalletResponse {
     pub wallet_id: String,
 }
 
+#[derive(Deserialize)]
+pub struct CreateWalletRequest {
+    /// Length of the generated mnemonic, 12 or 24 words.
+    #[serde(default = "default_word_count")]
+    pub word_count: usize,
+    /// How long the owner has to wait before spending a boarding output or VTXO without the Ark
+    /// server, if longer than the server's `unilateral_exit_delay_secs`. Rounded up to a multiple
+    /// of 512 seconds.
+    #[serde(default)]
+    pub unilateral_exit_delay_secs: Option<u64>,
+}
+
+/// A refused `unilateral_exit_delay_secs`, with a `code` of `EXIT_DELAY_TOO_SHORT` or
+/// `INVALID_EXIT_DELAY`.
+#[derive(Serialize)]
+pub struct ExitDelayErrorResponse {
+    pub code: &'static str,
+    pub error: String,
+    /// The shortest delay the Ark server accepts, if it is in seconds.
+    pub minimum_secs: Option<u64>,
+}
+
+fn default_word_count() -> usize {
+    12
+}
+
+#[derive(Serialize)]
+pub struct CreateWalletResponse {
+    pub wallet_id: String,
+    /// Shown only here; write it down, it is the only backup of the wallet.
+    pub mnemonic: String,
+}
+
+#[derive(Deserialize)]
+pub struct ListWalletsQuery {
+    #[serde(default)]
+    pub include_addresses: bool,
+}
+
+#[derive(Serialize)]
+pub struct ListWalletsResponse {
+    pub wallets: Vec<WalletSummary>,
+}
+
+#[derive(Serialize)]
+pub struct WalletSummary {
+    pub id: String,
+    /// Only set for wallets with their own exit delay.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unilateral_exit_delay_secs: Option<u64>,
+    pub onchain_address: Option<String>,
+    pub offchain_address: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct BalanceResponse {
     pub wallet_id: String,
     pub offchain_balance: OffchainBalance,
     pub boarding_balance: BoardingBalance,
+    /// Set when nothing is spendable offchain but settling the boarding outputs would change
+    /// that.
+    pub needs_settlement: bool,
+    /// How expired VTXOs can be recovered when nothing is spendable offchain; only
+    /// `"unilateral_exit"` for now, since they cannot join a round.
+    pub recovery: Option<&'static str>,
+    pub hint: Option<String>,
+}
+
+/// `sats` in BTC with all 8 decimal places, e.g. `0.00010000`.
+///
+/// Responses carry this next to every amount in sats, as a `_btc` sibling field, so that clients
+/// do not have to convert (and round) themselves.
+pub fn btc(sats: u64) -> String {
+    format!("{:.8}", Amount::from_sat(sats).display_in(Denomination::Bitcoin))
+}
+
+/// Balances summed over every wallet, in sats.
+#[derive(Serialize)]
+pub struct TotalBalanceResponse {
+    /// Spendable offchain and boarding funds.
+    pub total_spendable: u64,
+    pub total_spendable_btc: String,
+    /// Funds committed to sends or settlements in flight.
+    pub total_reserved: u64,
+    pub total_reserved_btc: String,
+    /// Expired offchain and boarding funds.
+    pub total_expired: u64,
+    pub total_expired_btc: String,
+    /// Unconfirmed boarding deposits.
+    pub total_pending: u64,
+    pub total_pending_btc: String,
+    pub per_wallet: Vec<WalletTotal>,
+}
+
+/// One wallet's share of [`TotalBalanceResponse`].
+#[derive(Serialize)]
+pub struct WalletTotal {
+    pub wallet_id: String,
+    pub spendable: u64,
+    pub spendable_btc: String,
+    pub reserved: u64,
+    pub reserved_btc: String,
+    pub expired: u64,
+    pub expired_btc: String,
+    pub pending: u64,
+    pub pending_btc: String,
+    /// Why the balance could not be fetched; the amounts are 0 then.
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FundingInstructionsResponse {
+    pub wallet_id: String,
+    pub boarding_address: String,
+    /// Deposits must be worth more than this to be turned into a VTXO.
+    pub min_deposit: u64,
+    pub min_deposit_btc: String,
+    pub min_confirmations: u32,
+    pub round_interval_secs: i64,
+    pub instructions: String,
+}
+
+#[derive(Serialize)]
+pub struct BoardingEtaResponse {
+    pub wallet_id: String,
+    pub deposits: Vec<DepositEta>,
+    /// Unix time by which every pending deposit should be usable offchain.
+    pub fully_usable_by: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct DepositEta {
+    pub outpoint: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    pub confirmations: u32,
+    pub remaining_confirmations: u32,
+    /// Estimated seconds until the deposit is confirmed deeply enough and the next round ran.
+    pub eta_secs: u64,
+    pub usable_by: u64,
+}
+
+#[derive(Serialize)]
+pub struct VtxoHistoryResponse {
+    pub wallet_id: String,
+    pub vtxos: Vec<VtxoHistoryEntry>,
+}
+
+#[derive(Serialize)]
+pub struct VtxoHistoryEntry {
+    pub outpoint: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    pub spent: bool,
+    /// The transaction that spent this VTXO, if the server knows it.
+    pub spent_by: Option<String>,
+    pub swept: bool,
+    pub is_pending: bool,
+    pub round_txid: String,
+    pub created_at: i64,
+    pub expire_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct VtxoListResponse {
+    pub wallet_id: String,
+    pub vtxos: Vec<VtxoEntry>,
+}
+
+#[derive(Serialize)]
+pub struct VtxoEntry {
+    /// `txid:vout`
+    pub outpoint: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    pub expire_at: i64,
+    /// Created by a redeem transaction that is not yet part of a settled round.
+    pub is_pending: bool,
+}
+
+#[derive(Serialize)]
+pub struct BoardingOutputsResponse {
+    pub wallet_id: String,
+    pub boarding_outputs: Vec<BoardingOutputEntry>,
+}
+
+#[derive(Serialize)]
+pub struct BoardingOutputEntry {
+    /// `txid:vout`
+    pub outpoint: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    pub status: BoardingOutputStatus,
+    /// Block time of the confirming block, if confirmed.
+    pub confirmed_at: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BoardingOutputStatus {
+    /// Confirmed and can be settled into a VTXO.
+    Spendable,
+    /// Not confirmed yet.
+    Pending,
+    /// Past the exit delay; only the owner can spend it, on-chain.
+    Expired,
+}
+
+#[derive(Serialize)]
+pub struct BalanceDetailResponse {
+    pub wallet_id: String,
+    pub offchain: OffchainDetail,
+    pub boarding: BoardingDetail,
+}
+
+#[derive(Serialize)]
+pub struct OffchainDetail {
+    pub spendable: Vec<OutpointDetail>,
+    pub expired: Vec<OutpointDetail>,
+}
+
+#[derive(Serialize)]
+pub struct BoardingDetail {
+    pub spendable: Vec<OutpointDetail>,
+    pub expired: Vec<OutpointDetail>,
+    pub pending: Vec<OutpointDetail>,
+}
+
+/// A single outpoint and the timestamps that matter for its category, in Unix seconds.
+#[derive(Serialize)]
+pub struct OutpointDetail {
+    pub outpoint: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    pub created_at: Option<i64>,
+    pub confirmed_at: Option<u64>,
+    pub expires_at: Option<i64>,
 }
 
 #[derive(Serialize)]
 pub struct OffchainBalance {
+    /// Spendable VTXOs not already committed to a send or settlement in flight.
     pub spendable: u64,
+    pub spendable_btc: String,
+    /// Spendable VTXOs selected by a send or settlement that has not completed yet.
+    pub reserved: u64,
+    pub reserved_btc: String,
     pub expired: u64,
+    pub expired_btc: String,
 }
 
 #[derive(Serialize)]
 pub struct BoardingBalance {
     pub spendable: u64,
+    pub spendable_btc: String,
     pub expired: u64,
+    pub expired_btc: String,
     pub pending: u64,
+    pub pending_btc: String,
 }
 
 #[derive(Deserialize)]
@@ -77,6 +843,33 @@ pub struct SendToArkAddressRequest {
     pub wallet_id: String,
     pub address: String,
     pub amount: u64,
+    #[serde(default)]
+    pub coin_selection: CoinSelection,
+}
+
+#[derive(Deserialize)]
+pub struct SendQuery {
+    /// Select inputs and build the transaction, but do not sign or submit it.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// How the VTXOs funding a send are picked.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinSelection {
+    /// Spend the VTXOs that expire soonest first.
+    #[default]
+    Default,
+    /// Pick VTXOs in random order so that input choice does not reveal the wallet's structure.
+    /// This may select more or larger inputs than needed, at the cost of a higher fee.
+    Random,
+    /// Spend the largest VTXOs first, for as few inputs (and as low a fee) as possible.
+    LargestFirst,
+    /// Spend the smallest VTXOs first, cleaning up small VTXOs at the cost of a higher fee.
+    SmallestFirst,
+    /// Spend the VTXOs created longest ago first.
+    OldestFirst,
 }
 
 #[derive(Serialize)]
@@ -84,7 +877,245 @@ pub struct SendToArkAddressResponse {
     pub wallet_id: String,
     pub to_address: String,
     pub amount: u64,
+    pub amount_btc: String,
+    pub txid: String,
+    /// Total input value minus total output value of the redeem transaction.
+    pub fee_paid: u64,
+    pub fee_paid_btc: String,
+    /// The VTXOs spent, as `txid:vout`.
+    pub selected_outpoints: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SendMaxRequest {
+    pub wallet_id: String,
+    pub address: String,
+}
+
+#[derive(Deserialize)]
+pub struct SendBatchRequest {
+    pub wallet_id: String,
+    pub outputs: Vec<SendOutput>,
+    #[serde(default)]
+    pub coin_selection: CoinSelection,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SendOutput {
+    pub address: String,
+    pub amount: u64,
+}
+
+/// An output of a send, as reported back to the client.
+#[derive(Serialize)]
+pub struct SendOutputEntry {
+    pub address: String,
+    pub amount: u64,
+    pub amount_btc: String,
+}
+
+impl From<&SendOutput> for SendOutputEntry {
+    fn from(output: &SendOutput) -> Self {
+        Self {
+            address: output.address.clone(),
+            amount: output.amount,
+            amount_btc: btc(output.amount),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SendBatchResponse {
+    pub wallet_id: String,
+    pub outputs: Vec<SendOutputEntry>,
+    /// Sum of all output amounts, excluding change and fees.
+    pub amount: u64,
+    pub amount_btc: String,
+    pub txid: String,
+    /// Total input value minus total output value of the redeem transaction.
+    pub fee_paid: u64,
+    pub fee_paid_btc: String,
+    /// The VTXOs spent, as `txid:vout`.
+    pub selected_outpoints: Vec<String>,
+}
+
+/// What a send would do, returned instead of sending when `dry_run` is set.
+#[derive(Serialize)]
+pub struct SendPreviewResponse {
+    pub wallet_id: String,
+    pub outputs: Vec<SendOutputEntry>,
+    pub selected_outpoints: Vec<String>,
+    pub total_input: u64,
+    pub total_input_btc: String,
+    pub change: u64,
+    pub change_btc: String,
+    /// Whether the change would have been below the dust limit, so it was left to the fee
+    /// instead of returned to the wallet.
+    pub change_omitted: bool,
+    pub fee: u64,
+    pub fee_btc: String,
+    /// The unsigned redeem transaction, base64-encoded.
+    pub psbt: String,
+}
+
+/// A redeem transaction for an external signer, returned by `/build_unsigned_send`.
+#[derive(Serialize)]
+pub struct UnsignedSendResponse {
+    pub wallet_id: String,
+    /// Passed to `/cancel_redeem/{reservation_id}` to release the inputs without submitting.
+    pub reservation_id: String,
+    pub txid: String,
+    pub selected_outpoints: Vec<String>,
+    pub total_input: u64,
+    pub total_input_btc: String,
+    pub fee: u64,
+    pub fee_btc: String,
+    /// How long the signed PSBT is accepted by `/submit_signed_psbt`.
+    pub expires_in_secs: u64,
+    /// The unsigned redeem transaction, base64-encoded.
+    pub psbt: String,
+}
+
+#[derive(Deserialize)]
+pub struct SubmitSignedPsbtRequest {
+    pub wallet_id: String,
+    /// The PSBT returned by `/build_unsigned_send` with every input signed, base64-encoded.
+    pub psbt: String,
+}
+
+#[derive(Serialize)]
+pub struct CancelRedeemResponse {
+    pub reservation_id: String,
+    pub wallet_id: String,
     pub txid: String,
+    /// The VTXOs the cancelled transaction spent, spendable again.
+    pub released_outpoints: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EstimateFeeQuery {
+    pub amount: u64,
+}
+
+#[derive(Serialize)]
+pub struct EstimateFeeResponse {
+    pub wallet_id: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    /// Number of VTXOs coin selection picked.
+    pub inputs: usize,
+    pub fee: u64,
+    pub fee_btc: String,
+    /// Change below the dust limit, which goes to the fee on top of `fee` instead of coming back
+    /// to the wallet.
+    pub dust_change: u64,
+    pub dust_change_btc: String,
+    /// Estimated virtual size of the signed redeem transaction.
+    pub vsize: u64,
+    pub fee_rate_sat_per_vb: f64,
+    /// What settling the amount would cost; always `null` for now, see `settle_fee_reason`.
+    pub settle_fee: Option<u64>,
+    pub settle_fee_reason: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct SendErrorResponse {
+    pub wallet_id: String,
+    /// Whether the Ark server accepted the transaction before the error happened.
+    pub submitted: bool,
+    pub txid: Option<String>,
+    pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct InsufficientFundsResponse {
+    pub code: &'static str,
+    pub error: String,
+    pub selected: u64,
+    pub selected_btc: String,
+    pub required: u64,
+    pub required_btc: String,
+    pub shortfall: u64,
+    pub shortfall_btc: String,
+}
+
+/// A send refused with `code` `SPEND_LIMIT_EXCEEDED`.
+#[derive(Serialize)]
+pub struct SpendLimitResponse {
+    pub code: &'static str,
+    pub error: String,
+    /// `per_transaction` or `per_day`.
+    pub limit: &'static str,
+    /// What the wallet may still send right now, in sats.
+    pub remaining: u64,
+    pub remaining_btc: String,
+}
+
+/// A rejected address, with a `code` of `INVALID_ADDRESS` or `WRONG_NETWORK` for Bitcoin
+/// addresses and `ADDRESS_NETWORK_MISMATCH` for Ark addresses.
+#[derive(Serialize)]
+pub struct AddressErrorResponse {
+    pub code: &'static str,
+    pub error: String,
+}
+
+#[derive(Deserialize)]
+pub struct ValidateAddressQuery {
+    pub address: String,
+}
+
+#[derive(Serialize)]
+pub struct ValidateAddressResponse {
+    /// Whether the address parses as an Ark or on-chain address, on whatever network.
+    pub valid: bool,
+    /// `ark` or `onchain`.
+    pub kind: Option<&'static str>,
+    pub network: Option<String>,
+    /// Whether the address can be used with the Ark server's network. `None` while the server
+    /// info is unavailable.
+    pub matches_server_network: Option<bool>,
+    /// Why the address is invalid or cannot be used.
+    pub error: Option<String>,
+}
+
+/// An output amount refused with `code` `AMOUNT_BELOW_DUST`, including a zero amount.
+#[derive(Serialize)]
+pub struct AmountBelowDustResponse {
+    pub code: &'static str,
+    pub error: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    /// The smallest amount the Ark server accepts for an output, in sats.
+    pub dust: u64,
+    pub dust_btc: String,
+}
+
+/// A destination refused with `code` `ADDRESS_NOT_WHITELISTED`.
+#[derive(Serialize)]
+pub struct AddressNotAllowedResponse {
+    pub code: &'static str,
+    pub error: String,
+    pub address: String,
+}
+
+#[derive(Deserialize)]
+pub struct SendOnchainRequest {
+    pub wallet_id: String,
+    pub onchain_address: String,
+    pub amount: u64,
+}
+
+#[derive(Serialize)]
+pub struct SendOnchainResponse {
+    pub wallet_id: String,
+    pub onchain_address: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    /// The round transaction paying the on-chain output.
+    pub txid: String,
+    /// Value of the wallet's inputs not returned to it or paid to the on-chain address.
+    pub fee_paid: u64,
+    pub fee_paid_btc: String,
 }
 
 #[derive(Deserialize)]
@@ -109,78 +1140,216 @@ pub struct SettleRequest {
     pub to_address: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    /// Refresh only this many of the VTXOs closest to expiry.
+    pub max_vtxos: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct RefreshResponse {
+    pub wallet_id: String,
+    pub txid: String,
+    /// How many VTXOs were settled back to the wallet.
+    pub refreshed: usize,
+    /// When the refreshed VTXOs would have expired, as Unix timestamps.
+    pub previous_expiries: Vec<i64>,
+    /// When the VTXOs created by the round expire, as Unix timestamps.
+    pub new_expiries: Vec<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct ConsolidateRequest {
+    /// Do nothing unless the wallet has at least this many spendable VTXOs. Defaults to 2.
+    pub min_inputs: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct ConsolidateResponse {
+    pub wallet_id: String,
+    /// How many VTXOs were merged; 0 if there were fewer than `min_inputs`.
+    pub consolidated: usize,
+    pub txid: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SettleQuery {
+    /// Respond right away with a job ID to poll at `/settle_status/{job_id}` instead of waiting
+    /// for the round to finish.
+    #[serde(default)]
+    pub background: bool,
+}
+
+#[derive(Serialize)]
+pub struct SettleJobResponse {
+    pub job_id: String,
+    pub wallet_id: String,
+}
+
 #[derive(Serialize)]
 pub struct SettleResponse {
     pub wallet_id: String,
     pub success: bool,
     pub txid: Option<String>,
+    /// Value of the wallet's inputs not returned to it in the round.
+    pub fee_paid: Option<u64>,
+    pub fee_paid_btc: Option<String>,
+    /// Fee of the round transaction itself, when the server provides enough data to compute it.
+    pub round_fee: Option<u64>,
+    pub round_fee_btc: Option<String>,
+    /// The round the wallet took part in.
+    pub round_id: Option<String>,
+    /// How many VTXOs the wallet registered as inputs of the round.
+    pub vtxo_inputs: Option<usize>,
+    /// How many boarding outputs the wallet registered as inputs of the round.
+    pub boarding_inputs: Option<usize>,
+    /// Total value of the wallet's inputs.
+    pub amount: Option<u64>,
+    pub amount_btc: Option<String>,
+    /// The Ark address the settled funds went to, besides any on-chain output.
+    pub to_address: Option<String>,
+    /// The steps of the round the wallet went through, with the round ID the server gave each.
+    pub timeline: Vec<SettleTimelineEntry>,
+    /// Machine-readable reason for failures the client may want to handle specially.
+    pub code: Option<&'static str>,
     pub error: Option<String>,
 }
 
-#[derive(Clone)]
-pub struct EsploraClient {
-    pub esplora_client: std::sync::Arc<esplora_client::AsyncClient>,
+#[derive(Deserialize)]
+pub struct BroadcastRequest {
+    /// The fully signed transaction, hex-encoded.
+    pub tx_hex: String,
 }
 
-impl EsploraClient {
-    pub fn new(url: &str) -> Result<Self, anyhow::Error> {
-        let builder = esplora_client::Builder::new(url);
-        let esplora_client = std::sync::Arc::new(builder.build_async()?);
-        Ok(Self { esplora_client })
-    }
+#[derive(Serialize)]
+pub struct BroadcastResponse {
+    pub txid: String,
+}
 
-    pub async fn find_outpoints(
-        &self,
-        address: &bitcoin::Address,
-    ) -> Result<Vec<ExplorerUtxo>, anyhow::Error> {
-        let script_pubkey = address.script_pubkey();
-        let txs = self
-            .esplora_client
-            .scripthash_txs(&script_pubkey, None)
-            .await?;
-
-        let outputs = txs
-            .into_iter()
-            .flat_map(|tx| {
-                let txid = tx.txid;
-                tx.vout
-                    .iter()
-                    .enumerate()
-                    .filter(|(_, v)| v.scriptpubkey == script_pubkey)
-                    .map(|(i, v)| ExplorerUtxo {
-                        outpoint: bitcoin::OutPoint {
-                            txid,
-                            vout: i as u32,
-                        },
-                        amount: Amount::from_sat(v.value),
-                        confirmation_blocktime: tx.status.block_time,
-                        is_spent: false,
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .collect::<Vec<_>>();
-
-        let mut utxos = Vec::new();
-        for output in outputs.iter() {
-            let outpoint = output.outpoint;
-            let status = self
-                .esplora_client
-                .get_output_status(&outpoint.txid, outpoint.vout as u64)
-                .await?;
-
-            match status {
-                Some(esplora_client::OutputStatus { spent: false, .. }) | None => {
-                    utxos.push(*output);
-                }
-                Some(esplora_client::OutputStatus { spent: true, .. }) => {
-                    utxos.push(ExplorerUtxo {
-                        is_spent: true,
-                        ..*output
-                    });
-                }
-            }
-        }
+#[derive(Serialize)]
+pub struct UnilateralExitResponse {
+    pub wallet_id: String,
+    /// Exit transactions broadcast by this request, parents first.
+    pub broadcast_txids: Vec<String>,
+    /// Exit transactions that were already on chain or in the mempool.
+    pub published_txids: Vec<String>,
+    pub vtxos: Vec<ExitingVtxo>,
+    /// The first height at which one of `vtxos` can be spent on-chain.
+    pub earliest_spendable_height: Option<u32>,
+    /// VTXOs created out of round, which cannot be exited yet.
+    pub skipped_vtxos: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ExitingVtxo {
+    /// `txid:vout`
+    pub outpoint: String,
+    pub amount: u64,
+    pub amount_btc: String,
+    /// When the exit delay will have passed, estimated if the delay is in seconds or the VTXO is
+    /// not confirmed yet.
+    pub spendable_at_height: u32,
+}
+
+#[derive(Serialize)]
+pub struct FeeRatesResponse {
+    /// Estimated fee rate in sat/vB, by confirmation target in blocks.
+    pub fee_rates: BTreeMap<u16, f64>,
+}
+
+#[derive(Serialize)]
+pub struct TxStatusResponse {
+    pub confirmed: bool,
+    /// Height of the block that confirmed the transaction.
+    pub block_height: Option<u32>,
+    /// Timestamp of that block, in Unix seconds.
+    pub block_time: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ChainStatusResponse {
+    pub height: u32,
+    pub tip_hash: String,
+    pub tip_time: u64,
+    pub tip_age_secs: u64,
+    pub synced: bool,
+}
+
+/// The Ark server parameters clients need to build transactions and explain delays.
+#[derive(Serialize)]
+pub struct ServerInfoResponse {
+    pub pubkey: String,
+    pub network: String,
+    /// Smallest output amount the server accepts, in sats.
+    pub dust: u64,
+    pub dust_btc: String,
+    pub round_interval_secs: i64,
+    /// `null` if the server uses a block-based delay.
+    pub unilateral_exit_delay_secs: Option<u64>,
+    /// `null` if the server uses a block-based expiry.
+    pub vtxo_tree_expiry_secs: Option<u64>,
+    pub forfeit_address: String,
+    /// Output descriptor of boarding outputs, with `USER` standing for the wallet owner's key.
+    pub boarding_descriptor_template: String,
+    /// Output descriptors of VTXOs, with `USER` standing for the wallet owner's key.
+    pub vtxo_descriptor_templates: Vec<String>,
+}
 
-        Ok(utxos)
+/// The Ark server's descriptor templates instantiated for one wallet.
+#[derive(Serialize)]
+pub struct WalletDescriptorsResponse {
+    pub wallet_id: String,
+    /// The x-only key standing in for `USER` in the templates.
+    pub owner_pubkey: String,
+    pub boarding_descriptor: String,
+    pub vtxo_descriptors: Vec<String>,
+    /// The boarding address this server derives for the wallet, to compare with
+    /// `boarding_descriptor`.
+    pub onchain_address: String,
+    /// The Ark address this server derives for the wallet.
+    pub offchain_address: String,
+}
+
+/// Which build of this server is running, and against what.
+#[derive(Serialize)]
+pub struct VersionResponse {
+    pub version: &'static str,
+    /// The Ark server's network, or `unknown` while it has not answered yet.
+    pub network: String,
+    /// Without any credentials the configured URL carries.
+    pub ark_server_url: String,
+}
+
+#[derive(Serialize)]
+pub struct CacheStatsResponse {
+    pub caches: Vec<CacheStats>,
+}
+
+#[derive(Deserialize)]
+pub struct CacheFlushRequest {
+    /// Only flush the cache with this name; all caches are flushed when omitted.
+    pub cache: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CacheFlushResponse {
+    pub flushed: Vec<FlushedCache>,
+}
+
+#[derive(Serialize)]
+pub struct FlushedCache {
+    pub name: &'static str,
+    pub removed: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn btc_amounts_have_all_eight_decimals() {
+        assert_eq!(btc(0), "0.00000000");
+        assert_eq!(btc(10_000), "0.00010000");
+        assert_eq!(btc(2_100_000_000_000_000), "21000000.00000000");
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,173 @@
+use bitcoin::{Amount, OutPoint, Transaction, Txid};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::transactions::Recipient;
+
+/// How long a transaction built for an external signer can be submitted back.
+pub const UNSIGNED_SEND_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Redeem transactions built for an external signer, by TXID, until the signed PSBT comes back.
+///
+/// Only a PSBT whose unsigned transaction is one of these is accepted for submission. The TXID
+/// commits to every input and output, so a signer cannot substitute inputs or outputs for the
+/// ones the server selected.
+///
+/// The VTXOs a send spends stay reserved for it until it is submitted, cancelled by its
+/// reservation ID or expires, so that no other spend selects them and balances do not count them
+/// as spendable.
+#[derive(Default)]
+pub struct UnsignedSends {
+    sends: Mutex<HashMap<Txid, UnsignedSend>>,
+}
+
+#[derive(Clone)]
+pub struct UnsignedSend {
+    /// Identifies the send, and the inputs it reserves, to cancel it.
+    pub reservation_id: String,
+    pub wallet_id: String,
+    pub recipients: Vec<Recipient>,
+    /// Total value of the selected VTXOs.
+    pub selected: Amount,
+    /// The VTXOs the transaction spends.
+    pub outpoints: Vec<OutPoint>,
+    built_at: Instant,
+}
+
+impl UnsignedSends {
+    /// Remember `tx`, built for `wallet_id`, until it is submitted, cancelled or expires. Returns
+    /// the reservation ID to cancel it with.
+    pub fn insert(
+        &self,
+        wallet_id: &str,
+        tx: &Transaction,
+        recipients: Vec<Recipient>,
+        selected: Amount,
+    ) -> String {
+        let reservation_id = uuid::Uuid::new_v4().to_string();
+        let mut sends = self.sends.lock().unwrap();
+        sends.retain(|_, send| send.built_at.elapsed() < UNSIGNED_SEND_TTL);
+        sends.insert(
+            tx.compute_txid(),
+            UnsignedSend {
+                reservation_id: reservation_id.clone(),
+                wallet_id: wallet_id.to_string(),
+                recipients,
+                selected,
+                outpoints: tx.input.iter().map(|input| input.previous_output).collect(),
+                built_at: Instant::now(),
+            },
+        );
+
+        reservation_id
+    }
+
+    /// The send built for `wallet_id` whose unsigned transaction is exactly `tx`, if it has not
+    /// expired.
+    pub fn get(&self, wallet_id: &str, tx: &Transaction) -> Option<UnsignedSend> {
+        self.sends
+            .lock()
+            .unwrap()
+            .get(&tx.compute_txid())
+            .filter(|send| send.wallet_id == wallet_id)
+            .filter(|send| send.built_at.elapsed() < UNSIGNED_SEND_TTL)
+            .cloned()
+    }
+
+    /// Forget `tx` once it was submitted.
+    pub fn remove(&self, tx: &Transaction) {
+        self.sends.lock().unwrap().remove(&tx.compute_txid());
+    }
+
+    /// The wallet that the send reserved as `reservation_id` was built for, if it has not expired.
+    pub fn wallet_of(&self, reservation_id: &str) -> Option<String> {
+        self.sends
+            .lock()
+            .unwrap()
+            .values()
+            .find(|send| send.reservation_id == reservation_id)
+            .filter(|send| send.built_at.elapsed() < UNSIGNED_SEND_TTL)
+            .map(|send| send.wallet_id.clone())
+    }
+
+    /// Forget the send reserved as `reservation_id`, releasing its VTXOs. Returns its TXID and
+    /// the VTXOs, or `None` if there is no such send or it already expired.
+    pub fn cancel(&self, reservation_id: &str) -> Option<(Txid, Vec<OutPoint>)> {
+        let mut sends = self.sends.lock().unwrap();
+        let (&txid, send) = sends.iter().find(|(_, send)| send.reservation_id == reservation_id)?;
+        let outpoints = send.outpoints.clone();
+        let expired = send.built_at.elapsed() >= UNSIGNED_SEND_TTL;
+        sends.remove(&txid);
+
+        (!expired).then_some((txid, outpoints))
+    }
+
+    /// The VTXOs of `wallet_id` reserved by sends that were neither submitted nor expired.
+    pub fn reserved(&self, wallet_id: &str) -> Vec<OutPoint> {
+        self.sends
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|send| send.wallet_id == wallet_id)
+            .filter(|send| send.built_at.elapsed() < UNSIGNED_SEND_TTL)
+            .flat_map(|send| send.outpoints.iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::TxIn;
+
+    fn spending(vout: u32) -> Transaction {
+        Transaction {
+            version: Version::non_standard(3),
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::all_zeros(),
+                    vout,
+                },
+                ..Default::default()
+            }],
+            output: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn only_the_transaction_built_for_the_wallet_is_accepted() {
+        let sends = UnsignedSends::default();
+        sends.insert("a", &spending(0), Vec::new(), Amount::from_sat(1_000));
+
+        assert!(sends.get("a", &spending(0)).is_some());
+        assert!(sends.get("a", &spending(1)).is_none(), "substituted input");
+        assert!(sends.get("b", &spending(0)).is_none(), "other wallet");
+
+        sends.remove(&spending(0));
+        assert!(sends.get("a", &spending(0)).is_none());
+    }
+
+    #[test]
+    fn inputs_stay_reserved_until_the_send_is_cancelled() {
+        let sends = UnsignedSends::default();
+        let reservation_id = sends.insert("a", &spending(0), Vec::new(), Amount::from_sat(1_000));
+        let outpoint = spending(0).input[0].previous_output;
+
+        assert_eq!(sends.reserved("a"), vec![outpoint]);
+        assert!(sends.reserved("b").is_empty(), "other wallet");
+        assert_eq!(sends.wallet_of(&reservation_id).as_deref(), Some("a"));
+
+        let txid = spending(0).compute_txid();
+        assert_eq!(sends.cancel("unknown"), None);
+        assert_eq!(sends.cancel(&reservation_id), Some((txid, vec![outpoint])));
+        assert!(sends.reserved("a").is_empty());
+        assert!(sends.get("a", &spending(0)).is_none());
+        assert_eq!(sends.wallet_of(&reservation_id), None);
+        assert_eq!(sends.cancel(&reservation_id), None, "already cancelled");
+    }
+}
@@ -1,171 +1,1051 @@
 use actix_web::{get, post, web, HttpResponse, Responder};
-use bitcoin::key::Keypair;
+use bip39::Mnemonic;
 use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
-use rand::thread_rng;
+use bitcoin::relative::LockTime;
+use bitcoin::{Sequence, XOnlyPublicKey};
+use futures::StreamExt;
+use rand::{thread_rng, RngCore};
 use std::collections::HashMap;
+use std::path::Path;
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+use crate::chain::current_tip;
+use crate::seed::mnemonic_secret_key;
+use crate::storage::{persist_wallet, WALLETS_DIR};
 use crate::types::*;
+use ark_core::server::VtxoOutPoint;
 use ark_core::{BoardingOutput, Vtxo};
 use ark_core::vtxo::list_virtual_tx_outpoints;
 use ark_core::boarding_output::list_boarding_outpoints;
 pub use ark_core::ExplorerUtxo;
 
+/// Target time between blocks. Every network we support aims for ten minutes, though regtest
+/// only mines on demand.
+const EXPECTED_BLOCK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How many wallets `/total_balance` looks up at the same time.
+const TOTAL_BALANCE_CONCURRENCY: usize = 8;
+
+pub(crate) const EXPIRED_FUNDS_HINT: &str = "The remaining funds have expired and can no longer be \
+     sent offchain. Expired outputs cannot join a round; they must be recovered through their \
+     unilateral exit path.";
+
+const SETTLE_BOARDING_HINT: &str = "Nothing is spendable offchain yet. Settle to turn the \
+     wallet's boarding outputs into VTXOs.";
+
 #[post("/create_wallet")]
-pub async fn create_wallet(data: web::Data<AppState>) -> impl Responder {
-    let mut rng = thread_rng();
-    let secp = Secp256k1::new();
-    let keypair = Keypair::new(&secp, &mut rng);
-    let secret_key = keypair.secret_key();
+pub async fn create_wallet(
+    data: web::Data<AppState>,
+    req: Option<web::Json<CreateWalletRequest>>,
+) -> impl Responder {
+    let (word_count, exit_delay_secs) = req
+        .map(|req| (req.word_count, req.unilateral_exit_delay_secs))
+        .unwrap_or((12, None));
+
+    if word_count != 12 && word_count != 24 {
+        return HttpResponse::BadRequest().body("word_count must be 12 or 24");
+    }
+
+    let unilateral_exit_delay = match requested_exit_delay(&data, exit_delay_secs) {
+        Ok(delay) => delay,
+        Err(response) => return response,
+    };
+
+    // 128 bits of entropy give 12 words, 256 bits give 24.
+    let mut entropy = vec![0u8; word_count / 3 * 4];
+    thread_rng().fill_bytes(&mut entropy);
+
+    let mnemonic = match Mnemonic::from_entropy(&entropy) {
+        Ok(mnemonic) => mnemonic,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to generate mnemonic: {}", e));
+        }
+    };
+
+    let passphrase = data.config.encryption_passphrase.as_deref();
+    let seed = match WalletSeed::from_mnemonic(&mnemonic, passphrase) {
+        Ok(seed) => seed,
+        Err(e) => {
+            return HttpResponse::InternalServerError().body(format!("Failed to store seed: {}", e));
+        }
+    };
+
+    let owner = match mnemonic_secret_key(&mnemonic) {
+        Ok(sk) => sk.x_only_public_key(&Secp256k1::new()).0,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to derive wallet key: {}", e));
+        }
+    };
 
     let wallet_id = Uuid::new_v4().to_string();
 
     let wallet_info = WalletInfo {
         id: wallet_id.clone(),
-        seed: secret_key.display_secret().to_string(),
+        keys: WalletKeys::Full { seed },
+        owner: Some(owner.to_string()),
+        unilateral_exit_delay,
     };
 
-    let mut wallets = data.wallets.lock().unwrap();
-    wallets.insert(wallet_id.clone(), wallet_info);
+    if let Err(response) = store_wallet(&data, wallet_info) {
+        return response;
+    }
+    data.events.record(&wallet_id, WalletEventKind::WalletCreated);
 
-    HttpResponse::Ok().json(WalletResponse { wallet_id })
+    HttpResponse::Ok().json(CreateWalletResponse {
+        wallet_id,
+        mnemonic: mnemonic.to_string(),
+    })
 }
 
-#[get("/get_address/{wallet_id}")]
-pub async fn get_address(wallet_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
-    let wallets = data.wallets.lock().unwrap();
-    let wallet_info = match wallets.get(&wallet_id.into_inner()) {
-        Some(info) => info.clone(),
-        None => return HttpResponse::NotFound().body("Wallet not found"),
+#[post("/import_key")]
+pub async fn import_key(data: web::Data<AppState>, req: web::Json<ImportKeyRequest>) -> impl Responder {
+    let sk = match SecretKey::from_str(req.secret_key.trim()) {
+        Ok(sk) => sk,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid secret key"),
     };
 
-    let server_info = match data.server_info.as_ref() {
-        Some(info) => info.lock().unwrap().clone(),
+    let server_info = match data.server_info() {
+        Some(info) => info,
         None => return HttpResponse::InternalServerError().body("Server not connected"),
     };
 
-    let sk = match SecretKey::from_str(&wallet_info.seed) {
-        Ok(sk) => sk,
-        Err(_) => return HttpResponse::InternalServerError().body("Invalid wallet seed"),
+    let unilateral_exit_delay = match requested_exit_delay(&data, req.unilateral_exit_delay_secs) {
+        Ok(delay) => delay,
+        Err(response) => return response,
     };
 
     let secp = Secp256k1::new();
     let pk = PublicKey::from_secret_key(&secp, &sk);
 
-    let boarding_output = match BoardingOutput::new(
-        &secp,
-        server_info.pk.x_only_public_key().0,
-        pk.x_only_public_key().0,
-        server_info.unilateral_exit_delay,
-        server_info.network,
-    ) {
-        Ok(bo) => bo,
+    let (boarding_output, vtxo) =
+        match wallet_outputs(&server_info, pk.x_only_public_key().0, unilateral_exit_delay) {
+            Ok(outputs) => outputs,
+            Err(response) => return response,
+        };
+
+    let wallet_id = match import_secret_key(&data, &sk, unilateral_exit_delay) {
+        Ok(wallet_id) => wallet_id,
+        Err(response) => return response,
+    };
+
+    HttpResponse::Ok().json(AddressResponse {
+        wallet_id,
+        onchain_address: boarding_output.address().to_string(),
+        offchain_address: vtxo.to_ark_address().to_string(),
+    })
+}
+
+#[post("/import_wallet")]
+pub async fn import_wallet(
+    data: web::Data<AppState>,
+    req: web::Json<ImportWalletRequest>,
+) -> impl Responder {
+    let sk = match SecretKey::from_str(req.seed.trim()) {
+        Ok(sk) => sk,
         Err(_) => {
-            return HttpResponse::InternalServerError().body("Failed to create boarding output");
+            return HttpResponse::BadRequest()
+                .body("Invalid seed: expected a 32-byte secret key in hex");
         }
     };
 
-    let vtxo = match Vtxo::new(
-        &secp,
-        server_info.pk.x_only_public_key().0,
-        pk.x_only_public_key().0,
-        vec![],
-        server_info.unilateral_exit_delay,
-        server_info.network,
+    let unilateral_exit_delay = match requested_exit_delay(&data, req.unilateral_exit_delay_secs) {
+        Ok(delay) => delay,
+        Err(response) => return response,
+    };
+
+    match import_secret_key(&data, &sk, unilateral_exit_delay) {
+        Ok(wallet_id) => HttpResponse::Ok().json(WalletResponse { wallet_id }),
+        Err(response) => response,
+    }
+}
+
+#[post("/import_watch_only")]
+pub async fn import_watch_only(
+    data: web::Data<AppState>,
+    req: web::Json<ImportWatchOnlyRequest>,
+) -> impl Responder {
+    let pubkey = match XOnlyPublicKey::from_str(req.pubkey.trim()) {
+        Ok(pubkey) => pubkey,
+        Err(_) => {
+            return HttpResponse::BadRequest()
+                .body("Invalid pubkey: expected a 32-byte x-only public key in hex");
+        }
+    };
+
+    let unilateral_exit_delay = match requested_exit_delay(&data, req.unilateral_exit_delay_secs) {
+        Ok(delay) => delay,
+        Err(response) => return response,
+    };
+
+    match import_keys(
+        &data,
+        pubkey,
+        WalletKeys::WatchOnly {
+            pubkey: pubkey.to_string(),
+        },
+        unilateral_exit_delay,
     ) {
-        Ok(vtxo) => vtxo,
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to create VTXO"),
+        Ok(wallet_id) => HttpResponse::Ok().json(WalletResponse { wallet_id }),
+        Err(response) => response,
+    }
+}
+
+/// Store `sk` as a new wallet unless some existing wallet already has the same owner key.
+fn import_secret_key(
+    data: &AppState,
+    sk: &SecretKey,
+    unilateral_exit_delay: Option<Sequence>,
+) -> Result<String, HttpResponse> {
+    let seed = WalletSeed::new(sk, data.config.encryption_passphrase.as_deref()).map_err(|e| {
+        HttpResponse::InternalServerError().body(format!("Failed to store seed: {}", e))
+    })?;
+
+    let owner = sk.x_only_public_key(&Secp256k1::new()).0;
+    import_keys(data, owner, WalletKeys::Full { seed }, unilateral_exit_delay)
+}
+
+fn import_keys(
+    data: &AppState,
+    owner: XOnlyPublicKey,
+    keys: WalletKeys,
+    unilateral_exit_delay: Option<Sequence>,
+) -> Result<String, HttpResponse> {
+    let owner = owner.to_string();
+    let wallet_id = Uuid::new_v4().to_string();
+    let wallet_info = WalletInfo {
+        id: wallet_id.clone(),
+        keys,
+        owner: Some(owner.clone()),
+        unilateral_exit_delay,
+    };
+
+    // Look for the key and insert the wallet under one lock, so that concurrent imports of the
+    // same key cannot both get through.
+    let mut wallets = data.wallets.lock().unwrap();
+    let existing = wallets
+        .values()
+        .find(|wallet_info| wallet_info.owner.as_ref() == Some(&owner));
+    if let Some(existing) = existing {
+        return Err(HttpResponse::Conflict()
+            .body(format!("This key is already imported as wallet {}", existing.id)));
+    }
+    wallets.insert(wallet_id.clone(), wallet_info.clone());
+    drop(wallets);
+    persist_stored_wallet(data, &wallet_info)?;
+    data.events.record(&wallet_id, WalletEventKind::WalletImported);
+
+    Ok(wallet_id)
+}
+
+/// The wallet's owner key, for handlers that only need to derive addresses.
+pub(crate) fn owner_pk(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+) -> Result<XOnlyPublicKey, HttpResponse> {
+    wallet_info
+        .owner_pk(data.config.encryption_passphrase.as_deref())
+        .map_err(|_| HttpResponse::InternalServerError().body("Invalid wallet key"))
+}
+
+/// The wallet's secret key, for handlers that need to sign. Watch-only wallets get a 400.
+pub(crate) fn signing_key(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+) -> Result<SecretKey, HttpResponse> {
+    match &wallet_info.keys {
+        WalletKeys::Full { seed } => seed
+            .secret_key(data.config.encryption_passphrase.as_deref())
+            .map_err(|_| HttpResponse::InternalServerError().body("Invalid wallet seed")),
+        WalletKeys::WatchOnly { .. } => Err(HttpResponse::BadRequest()
+            .body("Wallet is watch-only: the server holds no key to sign with")),
+    }
+}
+
+/// The exit delay asked for by a new wallet, which the Ark server must accept: its own delay is the
+/// shortest one. `None` leaves the wallet on the server's delay.
+fn requested_exit_delay(
+    data: &AppState,
+    secs: Option<u64>,
+) -> Result<Option<Sequence>, HttpResponse> {
+    let Some(secs) = secs else {
+        return Ok(None);
+    };
+
+    let server_info = match data.server_info() {
+        Some(info) => info,
+        None => return Err(HttpResponse::InternalServerError().body("Server not connected")),
+    };
+    let minimum_secs = match server_info.unilateral_exit_delay.to_relative_lock_time() {
+        Some(LockTime::Time(time)) => u64::from(time.value()) * 512,
+        _ => {
+            return Err(HttpResponse::BadRequest().json(ExitDelayErrorResponse {
+                code: "INVALID_EXIT_DELAY",
+                error: "The Ark server's exit delay is not in seconds, so it cannot be changed"
+                    .to_string(),
+                minimum_secs: None,
+            }));
+        }
+    };
+
+    if secs < minimum_secs {
+        return Err(HttpResponse::BadRequest().json(ExitDelayErrorResponse {
+            code: "EXIT_DELAY_TOO_SHORT",
+            error: format!(
+                "unilateral_exit_delay_secs must be at least the Ark server's {} seconds",
+                minimum_secs
+            ),
+            minimum_secs: Some(minimum_secs),
+        }));
+    }
+
+    u32::try_from(secs)
+        .ok()
+        .and_then(|secs| Sequence::from_seconds_ceil(secs).ok())
+        .map(Some)
+        .ok_or_else(|| {
+            HttpResponse::BadRequest().json(ExitDelayErrorResponse {
+                code: "INVALID_EXIT_DELAY",
+                error: format!(
+                    "unilateral_exit_delay_secs must be at most {} seconds",
+                    u64::from(u16::MAX) * 512
+                ),
+                minimum_secs: Some(minimum_secs),
+            })
+        })
+}
+
+#[get("/list_wallets")]
+pub async fn list_wallets(
+    data: web::Data<AppState>,
+    query: web::Query<ListWalletsQuery>,
+) -> impl Responder {
+    let mut wallets = data
+        .wallets
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    wallets.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let server_info = match (query.include_addresses, data.server_info()) {
+        (false, _) => None,
+        (true, Some(info)) => Some(info),
+        (true, None) => {
+            return HttpResponse::InternalServerError().body("Server not connected");
+        }
+    };
+
+    let passphrase = data.config.encryption_passphrase.as_deref();
+
+    let wallets = wallets
+        .into_iter()
+        .map(|wallet_info| {
+            let addresses = server_info.as_ref().and_then(|server_info| {
+                let owner = wallet_info.owner_pk(passphrase).ok()?;
+                wallet_outputs(server_info, owner, wallet_info.unilateral_exit_delay).ok()
+            });
+
+            WalletSummary {
+                id: wallet_info.id,
+                unilateral_exit_delay_secs: wallet_info
+                    .unilateral_exit_delay
+                    .and_then(|delay| delay.to_relative_lock_time())
+                    .map(|lock_time| match lock_time {
+                        LockTime::Time(time) => u64::from(time.value()) * 512,
+                        LockTime::Blocks(blocks) => u64::from(blocks.value()),
+                    }),
+                onchain_address: addresses
+                    .as_ref()
+                    .map(|(boarding_output, _)| boarding_output.address().to_string()),
+                offchain_address: addresses
+                    .as_ref()
+                    .map(|(_, vtxo)| vtxo.to_ark_address().to_string()),
+            }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(ListWalletsResponse { wallets })
+}
+
+#[get("/get_address/{wallet_id}")]
+pub async fn get_address(wallet_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
     };
 
-    let onchain_address = boarding_output.address().to_string();
-    let offchain_address = vtxo.to_ark_address().to_string();
+    let address = match primary_address(&data, &wallet_info) {
+        Ok(address) => address,
+        Err(response) => return response,
+    };
 
     HttpResponse::Ok().json(AddressResponse {
         wallet_id: wallet_info.id,
-        onchain_address,
-        offchain_address,
+        onchain_address: address.onchain_address,
+        offchain_address: address.offchain_address,
     })
 }
 
-#[get("/get_balance/{wallet_id}")]
-pub async fn get_balance(wallet_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
-    let wallets = data.wallets.lock().unwrap();
-    let wallet_info = match wallets.get(&wallet_id.into_inner()) {
-        Some(info) => info.clone(),
-        None => return HttpResponse::NotFound().body("Wallet not found"),
+/// The wallet's receive addresses, each with the index of the key it pays to.
+///
+/// Only index 0, the wallet's own key, is listed for now. Keys derived from the wallet's would
+/// need balances, sends and settlements to cover them too, or funds paid to their addresses would
+/// neither show up nor be spendable.
+#[get("/address/{wallet_id}")]
+pub async fn list_addresses(
+    wallet_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    match primary_address(&data, &wallet_info) {
+        Ok(address) => HttpResponse::Ok().json(AddressesResponse {
+            wallet_id: wallet_info.id,
+            addresses: vec![address],
+        }),
+        Err(response) => response,
+    }
+}
+
+/// The boarding and offchain addresses of the wallet's own key, index 0.
+fn primary_address(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+) -> Result<AddressEntry, HttpResponse> {
+    let server_info = match data.server_info() {
+        Some(info) => info,
+        None => return Err(HttpResponse::InternalServerError().body("Server not connected")),
+    };
+
+    let owner = owner_pk(data, wallet_info)?;
+    let (boarding_output, vtxo) =
+        wallet_outputs(&server_info, owner, wallet_info.unilateral_exit_delay)?;
+
+    Ok(AddressEntry {
+        index: 0,
+        onchain_address: boarding_output.address().to_string(),
+        offchain_address: vtxo.to_ark_address().to_string(),
+    })
+}
+
+#[get("/funding_instructions/{wallet_id}")]
+pub async fn funding_instructions(
+    wallet_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let server_info = match data.server_info() {
+        Some(info) => info,
+        None => return HttpResponse::InternalServerError().body("Server not connected"),
     };
 
-    let server_info = match data.server_info.as_ref() {
-        Some(info) => info.lock().unwrap().clone(),
+    let owner = match owner_pk(&data, &wallet_info) {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    let (boarding_output, _) =
+        match wallet_outputs(&server_info, owner, wallet_info.unilateral_exit_delay) {
+            Ok(outputs) => outputs,
+            Err(response) => return response,
+        };
+
+    let boarding_address = boarding_output.address().to_string();
+    let min_deposit = server_info.dust.to_sat();
+    let min_confirmations = data.config.min_confirmations;
+    let round_interval_secs = server_info.round_interval;
+
+    let instructions = format!(
+        "Send more than {} sats on {} to {}. Once the deposit has {} confirmation(s), call \
+         /settle to move it offchain; rounds run every {} seconds.",
+        min_deposit, server_info.network, boarding_address, min_confirmations, round_interval_secs
+    );
+
+    HttpResponse::Ok().json(FundingInstructionsResponse {
+        wallet_id: wallet_info.id,
+        boarding_address,
+        min_deposit,
+        min_deposit_btc: btc(min_deposit),
+        min_confirmations,
+        round_interval_secs,
+        instructions,
+    })
+}
+
+#[get("/boarding_eta/{wallet_id}")]
+pub async fn boarding_eta(wallet_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let server_info = match data.server_info() {
+        Some(info) => info,
         None => return HttpResponse::InternalServerError().body("Server not connected"),
     };
 
-    let esplora_client = match data.esplora_client.as_ref() {
-        Some(client) => client.lock().unwrap().clone(),
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
         None => return HttpResponse::InternalServerError().body("Esplora client not available"),
     };
 
-    let sk = match SecretKey::from_str(&wallet_info.seed) {
-        Ok(sk) => sk,
-        Err(_) => return HttpResponse::InternalServerError().body("Invalid wallet seed"),
+    let owner = match owner_pk(&data, &wallet_info) {
+        Ok(owner) => owner,
+        Err(response) => return response,
     };
 
-    let secp = Secp256k1::new();
-    let pk = PublicKey::from_secret_key(&secp, &sk);
+    let (boarding_output, _) =
+        match wallet_outputs(&server_info, owner, wallet_info.unilateral_exit_delay) {
+            Ok(outputs) => outputs,
+            Err(response) => return response,
+        };
 
-    let boarding_output = match BoardingOutput::new(
-        &secp,
-        server_info.pk.x_only_public_key().0,
-        pk.x_only_public_key().0,
-        server_info.unilateral_exit_delay,
-        server_info.network,
-    ) {
-        Ok(bo) => bo,
-        Err(_) => {
-            return HttpResponse::InternalServerError().body("Failed to create boarding output");
+    let tip = match current_tip(&data).await {
+        Ok(tip) => tip,
+        Err(response) => return response,
+    };
+
+    let deposits = match esplora_client.find_deposits(boarding_output.address()).await {
+        Ok(deposits) => deposits,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to fetch boarding outpoints: {}", e));
         }
     };
 
-    let vtxo = match Vtxo::new(
-        &secp,
-        server_info.pk.x_only_public_key().0,
-        pk.x_only_public_key().0,
-        vec![],
-        server_info.unilateral_exit_delay,
-        server_info.network,
-    ) {
-        Ok(vtxo) => vtxo,
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to create VTXO"),
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let min_confirmations = data.config.min_confirmations;
+    let round_interval_secs = server_info.round_interval.max(0) as u64;
+
+    let deposits = deposits
+        .into_iter()
+        .filter(|(utxo, _)| !utxo.is_spent)
+        .filter_map(|(utxo, block_height)| {
+            let confirmations = block_height
+                .map(|height| tip.height.saturating_sub(height) + 1)
+                .unwrap_or(0);
+            if confirmations >= min_confirmations {
+                return None;
+            }
+
+            let remaining_confirmations = min_confirmations - confirmations;
+            let eta_secs = remaining_confirmations as u64 * EXPECTED_BLOCK_INTERVAL.as_secs()
+                + round_interval_secs;
+
+            Some(DepositEta {
+                outpoint: utxo.outpoint.to_string(),
+                amount: utxo.amount.to_sat(),
+                amount_btc: btc(utxo.amount.to_sat()),
+                confirmations,
+                remaining_confirmations,
+                eta_secs,
+                usable_by: now + eta_secs,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let fully_usable_by = deposits.iter().map(|deposit| deposit.usable_by).max();
+
+    HttpResponse::Ok().json(BoardingEtaResponse {
+        wallet_id: wallet_info.id,
+        deposits,
+        fully_usable_by,
+    })
+}
+
+#[get("/vtxo_history/{wallet_id}")]
+pub async fn vtxo_history(wallet_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
     };
 
-    let mut grpc_client = ark_grpc::Client::new(data.config.ark_server_url.clone());
-    if let Err(_) = grpc_client.connect().await {
-        return HttpResponse::InternalServerError().body("Failed to connect to Ark server");
-    }
+    let server_info = match data.server_info() {
+        Some(info) => info,
+        None => return HttpResponse::InternalServerError().body("Server not connected"),
+    };
+
+    let owner = match owner_pk(&data, &wallet_info) {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    let (_, vtxo) = match wallet_outputs(&server_info, owner, wallet_info.unilateral_exit_delay) {
+        Ok(outputs) => outputs,
+        Err(response) => return response,
+    };
+
+    let grpc_client = match data.ark_client.get().await {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().body("Failed to connect to Ark server"),
+    };
 
     let vtxos = match grpc_client.list_vtxos(&vtxo.to_ark_address()).await {
         Ok(vtxos) => vtxos,
         Err(e) => {
+            data.ark_client.check(&e).await;
             return HttpResponse::InternalServerError()
                 .body(format!("Failed to list VTXOs: {}", e));
         }
     };
 
-    let mut spendable_vtxos = HashMap::new();
-    spendable_vtxos.insert(vtxo.clone(), vtxos.spendable);
+    let mut vtxos = vtxos
+        .spendable
+        .into_iter()
+        .map(|vtxo| (vtxo, false))
+        // VTXOs spent by a pending redeem transaction are listed as spent without being flagged.
+        .chain(vtxos.spent.into_iter().map(|vtxo| (vtxo, true)))
+        .map(|(vtxo, listed_as_spent)| VtxoHistoryEntry {
+            outpoint: vtxo.outpoint.to_string(),
+            amount: vtxo.amount.to_sat(),
+            amount_btc: btc(vtxo.amount.to_sat()),
+            spent: vtxo.spent || listed_as_spent,
+            spent_by: vtxo.spent_by.map(|txid| txid.to_string()),
+            swept: vtxo.swept,
+            is_pending: vtxo.is_pending,
+            round_txid: vtxo.round_txid.to_string(),
+            created_at: vtxo.created_at,
+            expire_at: vtxo.expire_at,
+        })
+        .collect::<Vec<_>>();
+    vtxos.sort_by_key(|vtxo| vtxo.created_at);
 
-    let boarding_address = boarding_output.address();
-    let boarding_outpoints = match esplora_client.find_outpoints(&boarding_address).await {
+    HttpResponse::Ok().json(VtxoHistoryResponse {
+        wallet_id: wallet_info.id,
+        vtxos,
+    })
+}
+
+#[get("/get_balance/{wallet_id}")]
+pub async fn get_balance(wallet_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    match wallet_balance(&data, &wallet_info).await {
+        Ok(balance) => HttpResponse::Ok().json(balance),
+        Err(response) => response,
+    }
+}
+
+/// Sum the balances of every wallet, e.g. to report the funds under management.
+///
+/// Wallets whose balance cannot be fetched are listed with the error and left out of the totals.
+#[get("/total_balance")]
+pub async fn total_balance(data: web::Data<AppState>) -> impl Responder {
+    let per_wallet = wallet_totals(&data).await;
+
+    let sum = |amount: fn(&WalletTotal) -> u64| per_wallet.iter().map(amount).sum::<u64>();
+    let total_spendable = sum(|wallet| wallet.spendable);
+    let total_reserved = sum(|wallet| wallet.reserved);
+    let total_expired = sum(|wallet| wallet.expired);
+    let total_pending = sum(|wallet| wallet.pending);
+
+    HttpResponse::Ok().json(TotalBalanceResponse {
+        total_spendable,
+        total_spendable_btc: btc(total_spendable),
+        total_reserved,
+        total_reserved_btc: btc(total_reserved),
+        total_expired,
+        total_expired_btc: btc(total_expired),
+        total_pending,
+        total_pending_btc: btc(total_pending),
+        per_wallet,
+    })
+}
+
+/// The balance of every wallet, by wallet ID.
+pub(crate) async fn wallet_totals(data: &AppState) -> Vec<WalletTotal> {
+    let wallets = data
+        .wallets
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut per_wallet = futures::stream::iter(wallets)
+        .map(|wallet_info| async move {
+            match wallet_balance(data, &wallet_info).await {
+                Ok(balance) => WalletTotal::from_balance(balance),
+                Err(response) => WalletTotal::failed(wallet_info.id, response).await,
+            }
+        })
+        .buffer_unordered(TOTAL_BALANCE_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+    per_wallet.sort_by(|a, b| a.wallet_id.cmp(&b.wallet_id));
+
+    per_wallet
+}
+
+impl WalletTotal {
+    fn from_balance(balance: BalanceResponse) -> Self {
+        let offchain = balance.offchain_balance;
+        let boarding = balance.boarding_balance;
+        let spendable = offchain.spendable + boarding.spendable;
+        let expired = offchain.expired + boarding.expired;
+
+        Self {
+            wallet_id: balance.wallet_id,
+            spendable,
+            spendable_btc: btc(spendable),
+            reserved: offchain.reserved,
+            reserved_btc: offchain.reserved_btc,
+            expired,
+            expired_btc: btc(expired),
+            pending: boarding.pending,
+            pending_btc: boarding.pending_btc,
+            error: None,
+        }
+    }
+
+    async fn failed(wallet_id: String, response: HttpResponse) -> Self {
+        let status = response.status();
+        let error = match actix_web::body::to_bytes(response.into_body()).await {
+            Ok(body) if !body.is_empty() => String::from_utf8_lossy(&body).into_owned(),
+            _ => status.to_string(),
+        };
+
+        Self {
+            wallet_id,
+            spendable: 0,
+            spendable_btc: btc(0),
+            reserved: 0,
+            reserved_btc: btc(0),
+            expired: 0,
+            expired_btc: btc(0),
+            pending: 0,
+            pending_btc: btc(0),
+            error: Some(error),
+        }
+    }
+}
+
+/// The offchain and boarding balances of `wallet_info`. VTXOs reserved by a spend in flight or by
+/// a transaction built for an external signer are reported separately from the spendable ones.
+pub(crate) async fn wallet_balance(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+) -> Result<BalanceResponse, HttpResponse> {
+    let WalletOutpoints {
+        vtxos: virtual_tx_outpoints,
+        boarding: boarding_outpoints,
+        ..
+    } = wallet_outpoints(data, wallet_info).await?;
+
+    let guidance = balance_guidance(
+        !virtual_tx_outpoints.spendable.is_empty(),
+        !boarding_outpoints.spendable.is_empty(),
+        !virtual_tx_outpoints.expired.is_empty(),
+    );
+
+    let mut reserved_outpoints = data.spend_locks.reserved(&wallet_info.id);
+    reserved_outpoints.extend(data.unsigned_sends.reserved(&wallet_info.id));
+    let reserved = reserved_amount(
+        virtual_tx_outpoints
+            .spendable
+            .iter()
+            .map(|(outpoint, _)| (outpoint.outpoint, outpoint.amount.to_sat())),
+        &reserved_outpoints,
+    );
+    let spendable = virtual_tx_outpoints.spendable_balance().to_sat() - reserved;
+
+    Ok(BalanceResponse {
+        wallet_id: wallet_info.id.clone(),
+        offchain_balance: OffchainBalance {
+            spendable,
+            spendable_btc: btc(spendable),
+            reserved,
+            reserved_btc: btc(reserved),
+            expired: virtual_tx_outpoints.expired_balance().to_sat(),
+            expired_btc: btc(virtual_tx_outpoints.expired_balance().to_sat()),
+        },
+        boarding_balance: BoardingBalance {
+            spendable: boarding_outpoints.spendable_balance().to_sat(),
+            spendable_btc: btc(boarding_outpoints.spendable_balance().to_sat()),
+            expired: boarding_outpoints.expired_balance().to_sat(),
+            expired_btc: btc(boarding_outpoints.expired_balance().to_sat()),
+            pending: boarding_outpoints.pending_balance().to_sat(),
+            pending_btc: btc(boarding_outpoints.pending_balance().to_sat()),
+        },
+        needs_settlement: guidance.needs_settlement,
+        recovery: guidance.recovery,
+        hint: guidance.hint,
+    })
+}
+
+/// What a wallet with nothing to spend offchain can do about it.
+struct BalanceGuidance {
+    needs_settlement: bool,
+    recovery: Option<&'static str>,
+    hint: Option<String>,
+}
+
+/// A settlement only helps a wallet without spendable VTXOs if it has boarding outputs to turn
+/// into VTXOs; expired outputs cannot join a round and go through their unilateral exit path.
+fn balance_guidance(
+    has_spendable_vtxos: bool,
+    has_spendable_boarding: bool,
+    has_expired_vtxos: bool,
+) -> BalanceGuidance {
+    if has_spendable_vtxos {
+        return BalanceGuidance {
+            needs_settlement: false,
+            recovery: None,
+            hint: None,
+        };
+    }
+
+    let needs_settlement = has_spendable_boarding;
+    let recovery = has_expired_vtxos.then_some("unilateral_exit");
+    let hint = match (needs_settlement, recovery) {
+        (true, Some(_)) => Some(format!("{} {}", SETTLE_BOARDING_HINT, EXPIRED_FUNDS_HINT)),
+        (true, None) => Some(SETTLE_BOARDING_HINT.to_string()),
+        (false, Some(_)) => Some(EXPIRED_FUNDS_HINT.to_string()),
+        (false, None) => None,
+    };
+
+    BalanceGuidance {
+        needs_settlement,
+        recovery,
+        hint,
+    }
+}
+
+#[get("/balance_detail/{wallet_id}")]
+pub async fn balance_detail(
+    wallet_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let WalletOutpoints {
+        vtxos,
+        boarding,
+        boarding_utxos,
+    } = match wallet_outpoints(&data, &wallet_info).await {
+        Ok(outpoints) => outpoints,
+        Err(response) => return response,
+    };
+
+    let vtxo_detail = |(outpoint, _): &(VtxoOutPoint, Vtxo)| OutpointDetail {
+        outpoint: outpoint.outpoint.to_string(),
+        amount: outpoint.amount.to_sat(),
+        amount_btc: btc(outpoint.amount.to_sat()),
+        created_at: Some(outpoint.created_at),
+        confirmed_at: None,
+        expires_at: Some(outpoint.expire_at),
+    };
+
+    let boarding_detail = |(outpoint, amount, boarding_output): &(
+        bitcoin::OutPoint,
+        bitcoin::Amount,
+        BoardingOutput,
+    )| {
+        let confirmed_at = boarding_utxos
+            .iter()
+            .find(|utxo| utxo.outpoint == *outpoint && utxo.is_confirmed)
+            .and_then(|utxo| utxo.confirmation_blocktime);
+
+        OutpointDetail {
+            outpoint: outpoint.to_string(),
+            amount: amount.to_sat(),
+            amount_btc: btc(amount.to_sat()),
+            created_at: None,
+            confirmed_at,
+            // After this the owner can exit unilaterally, so the server no longer accepts it.
+            expires_at: confirmed_at
+                .map(|time| (time + boarding_output.exit_delay_duration().as_secs()) as i64),
+        }
+    };
+
+    HttpResponse::Ok().json(BalanceDetailResponse {
+        wallet_id: wallet_info.id,
+        offchain: OffchainDetail {
+            spendable: vtxos.spendable.iter().map(vtxo_detail).collect(),
+            expired: vtxos.expired.iter().map(vtxo_detail).collect(),
+        },
+        boarding: BoardingDetail {
+            spendable: boarding.spendable.iter().map(boarding_detail).collect(),
+            expired: boarding.expired.iter().map(boarding_detail).collect(),
+            pending: boarding.pending.iter().map(boarding_detail).collect(),
+        },
+    })
+}
+
+/// The wallet's spendable VTXOs, one entry per outpoint, for coin control.
+#[get("/vtxos/{wallet_id}")]
+pub async fn list_vtxos(wallet_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let WalletOutpoints { vtxos, .. } = match wallet_outpoints(&data, &wallet_info).await {
+        Ok(outpoints) => outpoints,
+        Err(response) => return response,
+    };
+
+    let vtxos = vtxos
+        .spendable
+        .iter()
+        .map(|(outpoint, _)| VtxoEntry {
+            outpoint: outpoint.outpoint.to_string(),
+            amount: outpoint.amount.to_sat(),
+            amount_btc: btc(outpoint.amount.to_sat()),
+            expire_at: outpoint.expire_at,
+            is_pending: outpoint.is_pending,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(VtxoListResponse {
+        wallet_id: wallet_info.id,
+        vtxos,
+    })
+}
+
+#[get("/boarding_outputs/{wallet_id}")]
+pub async fn list_boarding_outputs(
+    wallet_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let wallet_info = match find_wallet(&data, &wallet_id) {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let WalletOutpoints {
+        boarding,
+        boarding_utxos,
+        ..
+    } = match wallet_outpoints(&data, &wallet_info).await {
         Ok(outpoints) => outpoints,
+        Err(response) => return response,
+    };
+
+    let entry = |status: BoardingOutputStatus| {
+        let boarding_utxos = &boarding_utxos;
+        move |(outpoint, amount, _): &(bitcoin::OutPoint, bitcoin::Amount, BoardingOutput)| {
+            BoardingOutputEntry {
+                outpoint: outpoint.to_string(),
+                amount: amount.to_sat(),
+                amount_btc: btc(amount.to_sat()),
+                status,
+                confirmed_at: boarding_utxos
+                    .iter()
+                    .find(|utxo| utxo.outpoint == *outpoint && utxo.is_confirmed)
+                    .and_then(|utxo| utxo.confirmation_blocktime),
+            }
+        }
+    };
+
+    let boarding_outputs = boarding
+        .spendable
+        .iter()
+        .map(entry(BoardingOutputStatus::Spendable))
+        .chain(boarding.pending.iter().map(entry(BoardingOutputStatus::Pending)))
+        .chain(boarding.expired.iter().map(entry(BoardingOutputStatus::Expired)))
+        .collect();
+
+    HttpResponse::Ok().json(BoardingOutputsResponse {
+        wallet_id: wallet_info.id,
+        boarding_outputs,
+    })
+}
+
+/// Everything a wallet owns, categorised as spendable, expired or pending.
+pub(crate) struct WalletOutpoints {
+    pub vtxos: VirtualTxOutpoints,
+    pub boarding: BoardingOutpoints,
+    /// The boarding outputs as reported by Esplora, with their confirmation times.
+    pub boarding_utxos: Vec<ExplorerUtxo>,
+}
+
+/// Fetch the wallet's VTXOs from the Ark server and its boarding outputs from Esplora.
+pub(crate) async fn wallet_outpoints(
+    data: &AppState,
+    wallet_info: &WalletInfo,
+) -> Result<WalletOutpoints, HttpResponse> {
+    let server_info = match data.server_info() {
+        Some(info) => info,
+        None => return Err(HttpResponse::InternalServerError().body("Server not connected")),
+    };
+
+    let esplora_client = match data.esplora_client() {
+        Some(client) => client,
+        None => {
+            return Err(
+                HttpResponse::InternalServerError().body("Esplora client not available")
+            );
+        }
+    };
+
+    let owner = owner_pk(data, wallet_info)?;
+
+    let (boarding_output, vtxo) =
+        wallet_outputs(&server_info, owner, wallet_info.unilateral_exit_delay)?;
+
+    let grpc_client = data.ark_client.get().await.map_err(|_| {
+        HttpResponse::InternalServerError().body("Failed to connect to Ark server")
+    })?;
+
+    compute_wallet_balances(data, &esplora_client, &grpc_client, boarding_output, &vtxo).await
+}
+
+/// Categorise the VTXOs of `vtxo` and the outputs of `boarding_output`, for callers that already
+/// hold the clients and the wallet's outputs.
+pub(crate) async fn compute_wallet_balances(
+    data: &AppState,
+    esplora_client: &EsploraClient,
+    grpc_client: &ark_grpc::Client,
+    boarding_output: BoardingOutput,
+    vtxo: &Vtxo,
+) -> Result<WalletOutpoints, HttpResponse> {
+    let vtxos = match grpc_client.list_vtxos(&vtxo.to_ark_address()).await {
+        Ok(vtxos) => vtxos,
         Err(e) => {
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to fetch boarding outpoints: {}", e));
+            data.ark_client.check(&e).await;
+            return Err(
+                HttpResponse::InternalServerError().body(format!("Failed to list VTXOs: {}", e))
+            );
         }
     };
 
+    let mut spendable_vtxos = HashMap::new();
+    spendable_vtxos.insert(vtxo.clone(), vtxos.spendable);
+
+    let boarding_address = boarding_output.address();
+    let boarding_utxos = data
+        .outpoints
+        .find_outpoints(esplora_client, boarding_address)
+        .await
+        .map_err(|e| {
+            HttpResponse::InternalServerError()
+                .body(format!("Failed to fetch boarding outpoints: {}", e))
+        })?;
+
     let mut outpoint_cache = HashMap::new();
-    outpoint_cache.insert(boarding_address.to_string(), boarding_outpoints);
+    outpoint_cache.insert(boarding_address.to_string(), boarding_utxos.clone());
 
     let find_outpoints =
         move |address: &bitcoin::Address| -> Result<Vec<ExplorerUtxo>, ark_core::Error> {
@@ -176,35 +1056,395 @@ pub async fn get_balance(wallet_id: web::Path<String>, data: web::Data<AppState>
             }
         };
 
-    let virtual_tx_outpoints =
-        match list_virtual_tx_outpoints(find_outpoints.clone(), spendable_vtxos) {
-            Ok(outpoints) => outpoints,
-            Err(e) => {
-                return HttpResponse::InternalServerError()
-                    .body(format!("Failed to get virtual tx outpoints: {}", e));
+    let virtual_tx_outpoints = list_virtual_tx_outpoints(find_outpoints.clone(), spendable_vtxos)
+        .map_err(|e| {
+            HttpResponse::InternalServerError()
+                .body(format!("Failed to get virtual tx outpoints: {}", e))
+        })?;
+
+    let boarding_outpoints = list_boarding_outpoints(find_outpoints, &[boarding_output])
+        .map_err(|e| {
+            HttpResponse::InternalServerError()
+                .body(format!("Failed to get boarding outpoints: {}", e))
+        })?;
+
+    Ok(WalletOutpoints {
+        vtxos: virtual_tx_outpoints,
+        boarding: boarding_outpoints,
+        boarding_utxos,
+    })
+}
+
+/// Look up the wallet `wallet_id`, which must be a UUID.
+///
+/// IDs that are not UUIDs are refused before the lookup, so that nothing a client sends can end
+/// up in a file path; the returned wallet carries the canonical form of the ID.
+pub(crate) fn find_wallet(data: &AppState, wallet_id: &str) -> Result<WalletInfo, HttpResponse> {
+    let wallet_id = match Uuid::parse_str(wallet_id) {
+        Ok(uuid) => uuid.to_string(),
+        Err(_) => return Err(HttpResponse::BadRequest().body("Invalid wallet ID: expected a UUID")),
+    };
+
+    match data.wallets.lock().unwrap().get(&wallet_id) {
+        Some(info) => Ok(info.clone()),
+        None => Err(HttpResponse::NotFound().body("Wallet not found")),
+    }
+}
+
+/// Add a wallet to the in-memory map and write it to disk, undoing the insert if the write fails.
+fn store_wallet(data: &AppState, wallet_info: WalletInfo) -> Result<(), HttpResponse> {
+    data.wallets.lock().unwrap().insert(wallet_info.id.clone(), wallet_info.clone());
+    persist_stored_wallet(data, &wallet_info)
+}
+
+/// Write a wallet that is already in the in-memory map to disk, taking it out again if that
+/// fails. Called after the wallets lock is released, so other handlers never wait on disk IO.
+fn persist_stored_wallet(data: &AppState, wallet_info: &WalletInfo) -> Result<(), HttpResponse> {
+    if let Err(e) = persist_wallet(Path::new(WALLETS_DIR), wallet_info) {
+        data.wallets.lock().unwrap().remove(&wallet_info.id);
+        tracing::error!(wallet_id = %wallet_info.id, error = %e, "Failed to persist wallet");
+        return Err(HttpResponse::InternalServerError()
+            .body(format!("Failed to save wallet to disk: {}", e)));
+    }
+
+    Ok(())
+}
+
+/// Forget the cached outputs of the wallet's addresses, e.g. after a settlement spent its
+/// boarding outputs.
+pub(crate) fn forget_outpoints(data: &AppState, wallet_info: &WalletInfo) {
+    let Some(server_info) = data.server_info() else {
+        return;
+    };
+    let Ok((boarding_output, vtxo)) =
+        owner_pk(data, wallet_info).and_then(|owner| {
+            wallet_outputs(&server_info, owner, wallet_info.unilateral_exit_delay)
+        })
+    else {
+        return;
+    };
+
+    data.outpoints.invalidate(boarding_output.address());
+    data.outpoints.invalidate(vtxo.address());
+}
+
+/// Build the boarding output and the default VTXO owned by `owner` under the server's current
+/// parameters, with the wallet's `unilateral_exit_delay` if it has its own.
+///
+/// Every address, balance and spend of a wallet goes through here, so that they all agree on the
+/// exit delay.
+pub(crate) fn wallet_outputs(
+    server_info: &ark_core::server::Info,
+    owner: XOnlyPublicKey,
+    unilateral_exit_delay: Option<Sequence>,
+) -> Result<(BoardingOutput, Vtxo), HttpResponse> {
+    let secp = Secp256k1::new();
+    let exit_delay = unilateral_exit_delay.unwrap_or(server_info.unilateral_exit_delay);
+
+    let boarding_output = BoardingOutput::new(
+        &secp,
+        server_info.pk.x_only_public_key().0,
+        owner,
+        exit_delay,
+        server_info.network,
+    )
+    .map_err(|_| HttpResponse::InternalServerError().body("Failed to create boarding output"))?;
+
+    let vtxo = Vtxo::new(
+        &secp,
+        server_info.pk.x_only_public_key().0,
+        owner,
+        vec![],
+        exit_delay,
+        server_info.network,
+    )
+    .map_err(|_| HttpResponse::InternalServerError().body("Failed to create VTXO"))?;
+
+    Ok((boarding_output, vtxo))
+}
+
+/// Remove every wallet whose key does not parse or whose addresses cannot be derived, returning
+/// the IDs of the quarantined wallets.
+///
+/// Without `server_info` only the keys are checked, since address derivation needs the server key.
+pub(crate) fn quarantine_invalid_wallets(
+    wallets: &mut HashMap<String, WalletInfo>,
+    server_info: Option<&ark_core::server::Info>,
+    passphrase: Option<&str>,
+) -> Vec<String> {
+    let invalid = wallets
+        .values()
+        .filter_map(|wallet_info| {
+            let owner = match wallet_info.owner_pk(passphrase) {
+                Ok(owner) => owner,
+                Err(e) => {
+                    tracing::error!(wallet_id = %wallet_info.id, error = %e, "Invalid wallet key");
+                    return Some(wallet_info.id.clone());
+                }
+            };
+
+            let server_info = server_info?;
+            match wallet_outputs(server_info, owner, wallet_info.unilateral_exit_delay) {
+                Ok(_) => None,
+                Err(_) => {
+                    tracing::error!(
+                        wallet_id = %wallet_info.id,
+                        "Failed to derive wallet addresses"
+                    );
+                    Some(wallet_info.id.clone())
+                }
             }
+        })
+        .collect::<Vec<_>>();
+
+    for wallet_id in invalid.iter() {
+        wallets.remove(wallet_id);
+    }
+
+    invalid
+}
+
+/// The total amount of the `spendable` outputs that are `reserved` for a spend.
+fn reserved_amount(
+    spendable: impl IntoIterator<Item = (bitcoin::OutPoint, u64)>,
+    reserved: &[bitcoin::OutPoint],
+) -> u64 {
+    spendable
+        .into_iter()
+        .filter(|(outpoint, _)| reserved.contains(outpoint))
+        .map(|(_, amount)| amount)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use bitcoin::hashes::Hash;
+    use std::time::Duration;
+
+    /// State pointing at an Ark server and Esplora backend that refuse every connection.
+    fn unreachable_state() -> AppState {
+        testing::app_state("http://127.0.0.1:1", "http://127.0.0.1:1")
+    }
+
+    #[actix_web::test]
+    async fn balance_excludes_outputs_reserved_by_a_spend_in_flight() {
+        let vtxo_outpoint = bitcoin::OutPoint {
+            txid: bitcoin::Txid::all_zeros(),
+            vout: 0,
         };
+        let ark_url = testing::ark_server_listing(&[(vtxo_outpoint, 10_000)]).await;
+        let data = web::Data::new(testing::app_state(&ark_url, &testing::empty_esplora()));
+        let sk = testing::insert_wallet(&data, 2, testing::TestKeys::Full);
+        let owner = sk.x_only_public_key(&Secp256k1::new()).0;
+        let (_, vtxo) = wallet_outputs(&data.server_info().unwrap(), owner, None).ok().unwrap();
 
-    let boarding_outpoints = match list_boarding_outpoints(find_outpoints, &[boarding_output]) {
-        Ok(outpoints) => outpoints,
-        Err(e) => {
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to get boarding outpoints: {}", e));
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .service(get_balance)
+                .service(crate::transactions::send_to_ark_address),
+        )
+        .await;
+        let balance = || async {
+            let balance: serde_json::Value = test::call_and_read_body_json(
+                &app,
+                test::TestRequest::get()
+                    .uri(&format!("/get_balance/{}", testing::WALLET_ID))
+                    .to_request(),
+            )
+            .await;
+            balance["offchain_balance"].clone()
+        };
+
+        let before = balance().await;
+        assert_eq!(before["spendable"], 10_000);
+        assert_eq!(before["reserved"], 0);
+
+        // The fake Ark server never answers the submission, so the send stays in flight.
+        let mut send = Box::pin(test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/send_to_ark_address")
+                .set_json(serde_json::json!({
+                    "wallet_id": testing::WALLET_ID,
+                    "address": vtxo.to_ark_address().encode(),
+                    "amount": 1_000,
+                }))
+                .to_request(),
+        ));
+        assert!(
+            tokio::time::timeout(Duration::from_secs(2), &mut send)
+                .await
+                .is_err(),
+            "the send should still be in flight"
+        );
+
+        let during = balance().await;
+        assert_eq!(during["spendable"], 0);
+        assert_eq!(during["reserved"], 10_000);
+
+        drop(send);
+        let after = balance().await;
+        assert_eq!(after["spendable"], 10_000);
+        assert_eq!(after["reserved"], 0);
+    }
+
+    #[actix_web::test]
+    async fn wallets_with_their_own_exit_delay_get_their_own_addresses() {
+        let data = web::Data::new(unreachable_state());
+        assert!(requested_exit_delay(&data, None).ok().unwrap().is_none());
+        let too_short = requested_exit_delay(&data, Some(512)).err().unwrap();
+        assert_eq!(too_short.status(), StatusCode::BAD_REQUEST);
+        // Rounded up to 8 intervals of 512 seconds.
+        let delay = requested_exit_delay(&data, Some(4_000)).ok().unwrap();
+        assert_eq!(delay, Some(Sequence::from_512_second_intervals(8)));
+
+        let owner = testing::insert_wallet(&data, 2, testing::TestKeys::WatchOnly)
+            .x_only_public_key(&Secp256k1::new())
+            .0;
+        data.wallets.lock().unwrap().get_mut(testing::WALLET_ID).unwrap().unilateral_exit_delay =
+            delay;
+        let app = test::init_service(App::new().app_data(data.clone()).service(get_address)).await;
+
+        let address: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("/get_address/{}", testing::WALLET_ID))
+                .to_request(),
+        )
+        .await;
+
+        let server_info = data.server_info().unwrap();
+        let (default_boarding, default_vtxo) =
+            wallet_outputs(&server_info, owner, None).ok().unwrap();
+        let (boarding_output, vtxo) = wallet_outputs(&server_info, owner, delay).ok().unwrap();
+        assert_eq!(address["onchain_address"], boarding_output.address().to_string());
+        assert_eq!(address["offchain_address"], vtxo.to_ark_address().to_string());
+        assert_ne!(boarding_output.address(), default_boarding.address());
+        assert_ne!(vtxo.to_ark_address().encode(), default_vtxo.to_ark_address().encode());
+    }
+
+    #[actix_web::test]
+    async fn concurrent_balance_requests_do_not_block_each_other() {
+        let data = web::Data::new(unreachable_state());
+        testing::insert_wallet(&data, 2, testing::TestKeys::WatchOnly);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .service(get_balance)
+                .service(list_wallets),
+        )
+        .await;
+
+        // Every balance request awaits a connection attempt. Were the wallets lock held across
+        // that await, the single-threaded test runtime would deadlock on the next request.
+        let balance_uri = format!("/get_balance/{}", testing::WALLET_ID);
+        let requests = (0..64).map(|i| {
+            let uri = match i % 2 {
+                0 => balance_uri.as_str(),
+                _ => "/list_wallets",
+            };
+            test::call_service(&app, test::TestRequest::get().uri(uri).to_request())
+        });
+        let responses = tokio::time::timeout(
+            Duration::from_secs(30),
+            futures::future::join_all(requests),
+        )
+        .await
+        .expect("requests to finish");
+
+        for (i, response) in responses.iter().enumerate() {
+            match i % 2 {
+                0 => assert!(response.status().is_server_error()),
+                _ => assert!(response.status().is_success()),
+            }
         }
-    };
+    }
 
-    let response = BalanceResponse {
-        wallet_id: wallet_info.id,
-        offchain_balance: OffchainBalance {
-            spendable: virtual_tx_outpoints.spendable_balance().to_sat(),
-            expired: virtual_tx_outpoints.expired_balance().to_sat(),
-        },
-        boarding_balance: BoardingBalance {
-            spendable: boarding_outpoints.spendable_balance().to_sat(),
-            expired: boarding_outpoints.expired_balance().to_sat(),
-            pending: boarding_outpoints.pending_balance().to_sat(),
-        },
-    };
+    #[actix_web::test]
+    async fn total_balance_reports_wallets_it_cannot_reach() {
+        let data = web::Data::new(unreachable_state());
+        let ids = [
+            "00000000-0000-4000-8000-00000000000b",
+            "00000000-0000-4000-8000-00000000000a",
+        ];
+        for id in ids {
+            testing::insert_wallet_with_id(&data, id, 2, testing::TestKeys::WatchOnly);
+        }
+
+        let app = test::init_service(App::new().app_data(data).service(total_balance)).await;
+        let total: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get().uri("/total_balance").to_request(),
+        )
+        .await;
+
+        assert_eq!(total["total_spendable"], 0);
+        assert_eq!(total["total_spendable_btc"], "0.00000000");
+        let per_wallet = total["per_wallet"].as_array().unwrap();
+        assert_eq!(per_wallet[0]["wallet_id"], ids[1]);
+        assert_eq!(per_wallet[1]["wallet_id"], ids[0]);
+        assert!(per_wallet.iter().all(|wallet| wallet["error"].is_string()));
+    }
+
+    #[actix_web::test]
+    async fn wallet_ids_must_be_uuids() {
+        let data = web::Data::new(unreachable_state());
+        let app = test::init_service(App::new().app_data(data).service(get_balance)).await;
+        let status = |uri: String| {
+            let app = &app;
+            async move {
+                let request = test::TestRequest::get().uri(&uri).to_request();
+                test::call_service(app, request).await.status()
+            }
+        };
 
-    HttpResponse::Ok().json(response)
-} 
\ No newline at end of file
+        for wallet_id in ["..%2F..%2Fetc%2Fpasswd", "wallet", "..", "%2E%2E"] {
+            let uri = format!("/get_balance/{}", wallet_id);
+            assert_eq!(status(uri).await, StatusCode::BAD_REQUEST, "{}", wallet_id);
+        }
+        let uri = format!("/get_balance/{}", testing::WALLET_ID);
+        assert_eq!(status(uri).await, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn settlement_is_only_suggested_when_it_would_help() {
+        let expired_only = balance_guidance(false, false, true);
+        assert!(!expired_only.needs_settlement);
+        assert_eq!(expired_only.recovery, Some("unilateral_exit"));
+        assert_eq!(expired_only.hint.as_deref(), Some(EXPIRED_FUNDS_HINT));
+
+        let boarding = balance_guidance(false, true, false);
+        assert!(boarding.needs_settlement);
+        assert_eq!(boarding.recovery, None);
+
+        let spendable = balance_guidance(true, true, true);
+        assert!(!spendable.needs_settlement);
+        assert_eq!(spendable.hint, None);
+    }
+
+    #[actix_web::test]
+    async fn keys_already_imported_are_refused_without_decrypting_seeds() {
+        let data = unreachable_state();
+        let sk = testing::insert_wallet(&data, 2, testing::TestKeys::Full);
+        // A seed that no longer decrypts shows the stored owner key is what gets compared.
+        data.wallets.lock().unwrap().get_mut(testing::WALLET_ID).unwrap().keys =
+            WalletKeys::Full {
+                seed: WalletSeed::Plain("not a key".to_string()),
+            };
+
+        let owner = sk.x_only_public_key(&Secp256k1::new()).0;
+        let keys = WalletKeys::WatchOnly {
+            pubkey: owner.to_string(),
+        };
+        let refused = import_keys(&data, owner, keys, None).err().unwrap();
+
+        assert_eq!(refused.status(), StatusCode::CONFLICT);
+        assert_eq!(data.wallets.lock().unwrap().len(), 1);
+    }
+}
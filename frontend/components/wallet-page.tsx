@@ -30,6 +30,7 @@ interface WalletBalance {
   wallet_id: string;
   offchain_balance: {
     spendable: number;
+    reserved: number;
     expired: number;
   };
   boarding_balance: {